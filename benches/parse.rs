@@ -0,0 +1,81 @@
+// Copyright (C) 2019 Oscar Shrimpton
+//
+// This file is part of stockton-bsp.
+//
+// stockton-bsp is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// stockton-bsp is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with stockton-bsp.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Times `BSPFile::from_buffer` over `tests/test.bsp`, or the map given as an argument.
+//!
+//! Run with `cargo bench --features rayon` to compare a single thread against all of them,
+//! or without the feature to time the serial parser on its own.
+
+#[cfg(feature = "rayon")]
+extern crate rayon;
+extern crate stockton_bsp;
+
+use std::env;
+use std::fs;
+use std::time::{Duration, Instant};
+
+use stockton_bsp::BSPFile;
+
+/// How long to keep parsing for each measurement.
+const TARGET: Duration = Duration::from_secs(2);
+
+/// Parse `data` repeatedly for about `TARGET`, returning the mean time per parse.
+fn time_parse(data: &[u8]) -> Duration {
+    // warm up, and make sure the map parses at all
+    BSPFile::from_buffer(data.to_vec().into_boxed_slice()).unwrap();
+
+    let start = Instant::now();
+    let mut runs = 0;
+    while start.elapsed() < TARGET {
+        BSPFile::from_buffer(data.to_vec().into_boxed_slice()).unwrap();
+        runs += 1;
+    }
+
+    start.elapsed() / runs
+}
+
+fn main() {
+    // cargo passes `--bench`, so skip any flags
+    let path = env::args()
+        .skip(1)
+        .find(|arg| !arg.starts_with("--"))
+        .unwrap_or_else(|| concat!(env!("CARGO_MANIFEST_DIR"), "/tests/test.bsp").to_owned());
+    let data = fs::read(&path).unwrap();
+
+    println!("{} ({} bytes)", path, data.len());
+
+    #[cfg(feature = "rayon")]
+    {
+        let serial = rayon::ThreadPoolBuilder::new()
+            .num_threads(1)
+            .build()
+            .unwrap()
+            .install(|| time_parse(&data));
+        let parallel = time_parse(&data);
+
+        println!("1 thread:   {:?} per parse", serial);
+        println!(
+            "{} thread(s): {:?} per parse ({:.2}x)",
+            rayon::current_num_threads(),
+            parallel,
+            serial.as_secs_f64() / parallel.as_secs_f64()
+        );
+    }
+
+    #[cfg(not(feature = "rayon"))]
+    println!("serial: {:?} per parse", time_parse(&data));
+}
@@ -0,0 +1,27 @@
+// Copyright (C) 2019 Oscar Shrimpton
+//
+// This file is part of stockton-bsp.
+//
+// stockton-bsp is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// stockton-bsp is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with stockton-bsp.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Parsing any input should return, never panic.
+//! Run with `cargo fuzz run from_buffer fuzz/corpus/from_buffer`, after copying `tests/test.bsp` into that directory.
+
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use stockton_bsp::BSPFile;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = BSPFile::from_buffer(data.into());
+});
@@ -0,0 +1,398 @@
+// Copyright (C) 2019 Oscar Shrimpton
+//
+// This file is part of stockton-bsp.
+//
+// stockton-bsp is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// stockton-bsp is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with stockton-bsp.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Ray & box traces against brush geometry, following the Q3 collision code.
+
+use crate::lumps::brushes::{Brush, BrushesLump};
+#[cfg(test)]
+use crate::lumps::test_util::test_map;
+use crate::lumps::planes::{Plane, PlanesLump};
+use crate::lumps::textures::{ContentsFlags, SurfaceFlags, TexturesLump};
+use crate::lumps::tree::{BSPNode, BSPTree};
+use crate::BSPFile;
+use na::Vector3;
+
+/// How far traces stop in front of the surface they hit, to avoid getting stuck on it.
+const SURFACE_CLIP_EPSILON: f32 = 0.125;
+
+/// The result of a trace. Equivalent to Q3's `trace_t`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trace {
+    /// How far along the trace got before hitting something, from 0.0 to 1.0.
+    pub fraction: f32,
+
+    /// Where the trace ended.
+    pub end_pos: Vector3<f32>,
+
+    /// The plane that was hit, if any.
+    pub plane: Option<Plane>,
+
+    /// The texture of the brush side that was hit, if any.
+    pub texture_idx: Option<usize>,
+
+    /// Surface flags of the brush side that was hit.
+    pub surface: SurfaceFlags,
+
+    /// Contents of the brush that was hit.
+    pub contents: ContentsFlags,
+
+    /// True if the trace started inside a solid brush.
+    pub start_solid: bool,
+
+    /// True if the trace never left a solid brush.
+    pub all_solid: bool,
+}
+
+/// Traces lines and axis-aligned boxes through the brushes of a map.
+#[derive(Debug, Clone, Copy)]
+pub struct Tracer<'a> {
+    tree: &'a BSPTree,
+    planes: &'a PlanesLump,
+    brushes: &'a BrushesLump,
+    textures: &'a TexturesLump,
+}
+
+/// Internal struct. State for a single trace.
+struct TraceWork<'a> {
+    start: Vector3<f32>,
+    end: Vector3<f32>,
+    extents: Vector3<f32>,
+    mask: ContentsFlags,
+    checked: Vec<bool>,
+    trace: Trace,
+    tracer: &'a Tracer<'a>,
+}
+
+impl<'a> Tracer<'a> {
+    pub fn new(
+        tree: &'a BSPTree,
+        planes: &'a PlanesLump,
+        brushes: &'a BrushesLump,
+        textures: &'a TexturesLump,
+    ) -> Tracer<'a> {
+        Tracer {
+            tree,
+            planes,
+            brushes,
+            textures,
+        }
+    }
+
+    /// Trace against the world brushes of the given file.
+    pub fn from_file(file: &'a BSPFile) -> Tracer<'a> {
+        Tracer::new(&file.tree, &file.planes, &file.brushes, &file.textures)
+    }
+
+    /// Trace a line from `start` to `end`.
+    /// Only brushes with contents in `mask` are solid.
+    pub fn trace_ray(&self, start: Vector3<f32>, end: Vector3<f32>, mask: ContentsFlags) -> Trace {
+        let zero = Vector3::new(0.0, 0.0, 0.0);
+        self.trace_box(start, end, zero, zero, mask)
+    }
+
+    /// Trace an axis-aligned box with the given bounds from `start` to `end`.
+    /// `mins` and `maxs` are relative to the start & end points.
+    /// Only brushes with contents in `mask` are solid.
+    pub fn trace_box(
+        &self,
+        start: Vector3<f32>,
+        end: Vector3<f32>,
+        mins: Vector3<f32>,
+        maxs: Vector3<f32>,
+        mask: ContentsFlags,
+    ) -> Trace {
+        // Make the box symmetric around the origin so we only need the extents.
+        let offset = (mins + maxs) / 2.0;
+
+        let mut work = TraceWork {
+            start: start + offset,
+            end: end + offset,
+            extents: maxs - offset,
+            mask,
+            checked: vec![false; self.brushes.brushes.len()],
+            trace: Trace {
+                fraction: 1.0,
+                end_pos: end,
+                plane: None,
+                texture_idx: None,
+                surface: SurfaceFlags::empty(),
+                contents: ContentsFlags::empty(),
+                start_solid: false,
+                all_solid: false,
+            },
+            tracer: self,
+        };
+
+        let (from, to) = (work.start, work.end);
+        work.trace_node(&self.tree.root, 0.0, 1.0, from, to);
+
+        let mut trace = work.trace;
+        if trace.fraction < 1.0 {
+            trace.end_pos = start + ((end - start) * trace.fraction);
+        }
+
+        trace
+    }
+}
+
+impl<'a> TraceWork<'a> {
+    /// How far the box sticks out in front of a plane with the given normal.
+    fn box_offset(&self, normal: &Vector3<f32>) -> f32 {
+        (normal.x * self.extents.x).abs()
+            + (normal.y * self.extents.y).abs()
+            + (normal.z * self.extents.z).abs()
+    }
+
+    /// Trace the section `p1` to `p2` (`p1f` to `p2f` of the whole trace) through the given node.
+    fn trace_node(&mut self, node: &BSPNode, p1f: f32, p2f: f32, p1: Vector3<f32>, p2: Vector3<f32>) {
+        // Already hit something nearer
+        if self.trace.fraction <= p1f {
+            return;
+        }
+
+        if let Some(leaf) = &node.leaf {
+            for brush_idx in leaf.brushes_idx.iter() {
+                self.trace_brush(*brush_idx as usize);
+                if self.trace.all_solid {
+                    return;
+                }
+            }
+            return;
+        }
+
+        let children = match &node.children {
+            Some(children) => children,
+            None => return,
+        };
+        let plane = match self.tracer.planes.planes.get(node.plane_idx as usize) {
+            Some(plane) => plane,
+            None => return,
+        };
+
+        let t1 = plane.normal.dot(&p1) - plane.dist;
+        let t2 = plane.normal.dot(&p2) - plane.dist;
+        let offset = self.box_offset(&plane.normal);
+
+        // Entirely on one side
+        if t1 >= offset + 1.0 && t2 >= offset + 1.0 {
+            return self.trace_node(&children[0], p1f, p2f, p1, p2);
+        }
+        if t1 < -offset - 1.0 && t2 < -offset - 1.0 {
+            return self.trace_node(&children[1], p1f, p2f, p1, p2);
+        }
+
+        // Split the trace, with each part overlapping the plane slightly
+        let (side, frac, frac2) = if t1 < t2 {
+            let idist = 1.0 / (t1 - t2);
+            (1, (t1 - offset + SURFACE_CLIP_EPSILON) * idist, (t1 + offset + SURFACE_CLIP_EPSILON) * idist)
+        } else if t1 > t2 {
+            let idist = 1.0 / (t1 - t2);
+            (0, (t1 + offset + SURFACE_CLIP_EPSILON) * idist, (t1 - offset - SURFACE_CLIP_EPSILON) * idist)
+        } else {
+            (0, 1.0, 0.0)
+        };
+        let frac = frac.clamp(0.0, 1.0);
+        let frac2 = frac2.clamp(0.0, 1.0);
+
+        let midf = p1f + ((p2f - p1f) * frac);
+        let mid = p1 + ((p2 - p1) * frac);
+        self.trace_node(&children[side], p1f, midf, p1, mid);
+
+        let midf = p1f + ((p2f - p1f) * frac2);
+        let mid = p1 + ((p2 - p1) * frac2);
+        self.trace_node(&children[side ^ 1], midf, p2f, mid, p2);
+    }
+
+    /// Clip the trace against the brush with the given index, if it hasn't been already.
+    fn trace_brush(&mut self, brush_idx: usize) {
+        match self.checked.get_mut(brush_idx) {
+            Some(checked) if !*checked => *checked = true,
+            _ => return,
+        }
+
+        let tracer = self.tracer;
+        let brush: &Brush = &tracer.brushes.brushes[brush_idx];
+        let contents = match tracer.textures.textures.get(brush.texture_idx) {
+            Some(texture) => texture.contents,
+            None => return,
+        };
+        if !contents.intersects(self.mask) || brush.sides.is_empty() {
+            return;
+        }
+
+        let mut enter_frac = -1.0;
+        let mut leave_frac = 1.0;
+        let mut lead = None;
+        let mut starts_out = false;
+        let mut gets_out = false;
+
+        for side in brush.sides.iter() {
+            let plane = match tracer.planes.planes.get(side.plane_idx) {
+                Some(plane) => plane,
+                None => return,
+            };
+            let dist = plane.dist + self.box_offset(&plane.normal);
+
+            let d1 = plane.normal.dot(&self.start) - dist;
+            let d2 = plane.normal.dot(&self.end) - dist;
+
+            if d2 > 0.0 {
+                gets_out = true;
+            }
+            if d1 > 0.0 {
+                starts_out = true;
+            }
+
+            // Completely in front of this side, so can't hit the brush
+            if d1 > 0.0 && (d2 >= SURFACE_CLIP_EPSILON || d2 >= d1) {
+                return;
+            }
+
+            // Completely behind this side
+            if d1 <= 0.0 && d2 <= 0.0 {
+                continue;
+            }
+
+            if d1 > d2 {
+                // Entering the brush
+                let f = ((d1 - SURFACE_CLIP_EPSILON) / (d1 - d2)).max(0.0);
+                if f > enter_frac {
+                    enter_frac = f;
+                    lead = Some((plane, side.texture_idx));
+                }
+            } else {
+                // Leaving the brush
+                let f = ((d1 + SURFACE_CLIP_EPSILON) / (d1 - d2)).min(1.0);
+                if f < leave_frac {
+                    leave_frac = f;
+                }
+            }
+        }
+
+        if !starts_out {
+            self.trace.start_solid = true;
+            if !gets_out {
+                self.trace.all_solid = true;
+                self.trace.fraction = 0.0;
+                self.trace.contents = contents;
+            }
+            return;
+        }
+
+        if enter_frac < leave_frac && enter_frac > -1.0 && enter_frac < self.trace.fraction {
+            if let Some((plane, texture_idx)) = lead {
+                self.trace.fraction = enter_frac.max(0.0);
+                self.trace.plane = Some(*plane);
+                self.trace.texture_idx = Some(texture_idx);
+                self.trace.surface = tracer
+                    .textures
+                    .textures
+                    .get(texture_idx)
+                    .map_or(SurfaceFlags::empty(), |t| t.surface);
+                self.trace.contents = contents;
+            }
+        }
+    }
+}
+
+#[test]
+fn trace_ray_hit() {
+    let (tree, planes, brushes, textures) = test_map();
+    let tracer = Tracer::new(&tree, &planes, &brushes, &textures);
+
+    let trace = tracer.trace_ray(
+        Vector3::new(-100.0, 0.0, 0.0),
+        Vector3::new(100.0, 0.0, 0.0),
+        ContentsFlags::SOLID,
+    );
+
+    assert_eq!(trace.fraction, (84.0 - SURFACE_CLIP_EPSILON) / 200.0);
+    assert_eq!(trace.plane.unwrap().normal, Vector3::new(-1.0, 0.0, 0.0));
+    assert_eq!(trace.texture_idx, Some(1));
+    assert_eq!(trace.surface, SurfaceFlags::METAL_STEPS);
+    assert_eq!(trace.contents, ContentsFlags::SOLID);
+    assert!(trace.end_pos.x < -16.0 && trace.end_pos.x > -17.0);
+    assert!(!trace.start_solid && !trace.all_solid);
+}
+
+#[test]
+fn trace_box_hit() {
+    let (tree, planes, brushes, textures) = test_map();
+    let tracer = Tracer::new(&tree, &planes, &brushes, &textures);
+
+    let trace = tracer.trace_box(
+        Vector3::new(0.0, 100.0, 0.0),
+        Vector3::new(0.0, -100.0, 0.0),
+        Vector3::new(-8.0, -8.0, -8.0),
+        Vector3::new(8.0, 8.0, 8.0),
+        ContentsFlags::SOLID,
+    );
+
+    assert_eq!(trace.fraction, (76.0 - SURFACE_CLIP_EPSILON) / 200.0);
+    assert_eq!(trace.plane.unwrap().normal, Vector3::new(0.0, 1.0, 0.0));
+
+    // The box would clip the corner of the brush, but the ray wouldn't
+    let start = Vector3::new(-100.0, 20.0, 0.0);
+    let end = Vector3::new(100.0, 20.0, 0.0);
+    assert_eq!(tracer.trace_ray(start, end, ContentsFlags::SOLID).fraction, 1.0);
+    assert!(
+        tracer
+            .trace_box(start, end, Vector3::new(-8.0, -8.0, -8.0), Vector3::new(8.0, 8.0, 8.0), ContentsFlags::SOLID)
+            .fraction
+            < 1.0
+    );
+}
+
+#[test]
+fn trace_solid() {
+    let (tree, planes, brushes, textures) = test_map();
+    let tracer = Tracer::new(&tree, &planes, &brushes, &textures);
+
+    let trace = tracer.trace_ray(
+        Vector3::new(0.0, 0.0, 0.0),
+        Vector3::new(100.0, 0.0, 0.0),
+        ContentsFlags::SOLID,
+    );
+    assert!(trace.start_solid);
+    assert!(!trace.all_solid);
+    assert_eq!(trace.fraction, 1.0);
+
+    let trace = tracer.trace_ray(
+        Vector3::new(0.0, 0.0, 0.0),
+        Vector3::new(1.0, 0.0, 0.0),
+        ContentsFlags::SOLID,
+    );
+    assert!(trace.start_solid);
+    assert!(trace.all_solid);
+    assert_eq!(trace.fraction, 0.0);
+}
+
+#[test]
+fn trace_mask() {
+    let (tree, planes, brushes, textures) = test_map();
+    let tracer = Tracer::new(&tree, &planes, &brushes, &textures);
+
+    let trace = tracer.trace_ray(
+        Vector3::new(-100.0, 0.0, 0.0),
+        Vector3::new(100.0, 0.0, 0.0),
+        ContentsFlags::WATER | ContentsFlags::PLAYER_CLIP,
+    );
+
+    assert_eq!(trace.fraction, 1.0);
+    assert_eq!(trace.end_pos, Vector3::new(100.0, 0.0, 0.0));
+    assert!(trace.plane.is_none());
+}
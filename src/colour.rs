@@ -0,0 +1,227 @@
+// Copyright (C) 2019 Oscar Shrimpton
+//
+// This file is part of stockton-bsp.
+//
+// stockton-bsp is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// stockton-bsp is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with stockton-bsp.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Colour correction for the lighting stored in Q3 maps.
+//!
+//! Lightmaps, light volumes and vertex colours are all compiled expecting the engine to shift them up
+//! by `r_mapOverBrightBits`, less whatever it can get back through hardware gamma (`r_overBrightBits`).
+//! Using the raw values makes maps look too dark, and shifting without clamping properly changes their hue.
+
+use na::Vector3;
+
+use crate::lumps::light_maps::LightMap;
+use crate::lumps::light_vols::{LightSample, LightVol};
+use crate::lumps::{LightMapsLump, LightVolsLump, VerticesLump};
+use crate::types::{RGB, RGBA};
+
+/// The transfer function applied after the overbright shift.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Transfer {
+    /// Leave colours as they are.
+    None,
+
+    /// Raise to the power of `1 / gamma`, like `r_gamma`.
+    Gamma(f32),
+
+    /// Treat colours as sRGB and convert them to linear, for renderers that light in linear space.
+    SrgbToLinear,
+}
+
+/// Parameters for correcting a map's lighting.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ColourCorrection {
+    /// The overbright bits the map was compiled for. 2 for almost every Q3 map.
+    pub map_overbright_bits: u32,
+
+    /// The overbright bits the renderer makes up for itself, eg. with hardware gamma.
+    /// The shift applied is `map_overbright_bits - overbright_bits`.
+    pub overbright_bits: u32,
+
+    pub transfer: Transfer,
+}
+
+impl Default for ColourCorrection {
+    /// What ioquake3 does without hardware gamma: the full shift of 2 bits and no gamma change.
+    fn default() -> ColourCorrection {
+        ColourCorrection {
+            map_overbright_bits: 2,
+            overbright_bits: 0,
+            transfer: Transfer::None,
+        }
+    }
+}
+
+impl ColourCorrection {
+    /// The number of bits colours are shifted up by.
+    pub fn shift(&self) -> u32 {
+        self.map_overbright_bits.saturating_sub(self.overbright_bits)
+    }
+
+    /// Correct a single colour.
+    /// This follows `R_ColorShiftLightingBytes`: if any channel goes over 255, all of them are scaled down together.
+    pub fn apply(&self, colour: RGB) -> RGB {
+        let shift = self.shift().min(8);
+        let mut c = [
+            u32::from(colour.r) << shift,
+            u32::from(colour.g) << shift,
+            u32::from(colour.b) << shift,
+        ];
+
+        let max = c[0].max(c[1]).max(c[2]);
+        if max > 255 {
+            for channel in c.iter_mut() {
+                *channel = *channel * 255 / max;
+            }
+        }
+
+        RGB {
+            r: self.transfer_byte(c[0]),
+            g: self.transfer_byte(c[1]),
+            b: self.transfer_byte(c[2]),
+        }
+    }
+
+    /// Correct a colour with alpha. The alpha channel is left alone.
+    pub fn apply_rgba(&self, colour: RGBA) -> RGBA {
+        let RGB { r, g, b } = self.apply(RGB {
+            r: colour.r,
+            g: colour.g,
+            b: colour.b,
+        });
+
+        RGBA { r, g, b, a: colour.a }
+    }
+
+    /// Correct a colour on a 0-255 scale that's already been interpolated, such as from a `LightSample`.
+    /// The result is still on a 0-255 scale, but isn't rounded.
+    pub fn apply_f32(&self, colour: Vector3<f32>) -> Vector3<f32> {
+        let mut c = colour * (1 << self.shift().min(8)) as f32;
+
+        let max = c.max();
+        if max > 255.0 {
+            c *= 255.0 / max;
+        }
+
+        c.map(|channel| self.transfer(channel.max(0.0) / 255.0) * 255.0)
+    }
+
+    /// Correct every texel of a lightmap.
+    pub fn apply_light_map(&self, light_map: &LightMap) -> LightMap {
+        let mut corrected = light_map.clone();
+        for texel in corrected.map.iter_mut().flat_map(|row| row.iter_mut()) {
+            *texel = self.apply(*texel);
+        }
+
+        corrected
+    }
+
+    pub fn apply_light_maps(&self, light_maps: &LightMapsLump) -> LightMapsLump {
+        LightMapsLump {
+            maps: light_maps.maps.iter().map(|m| self.apply_light_map(m)).collect(),
+        }
+    }
+
+    /// Correct both colours of a light volume.
+    pub fn apply_light_vol(&self, vol: &LightVol) -> LightVol {
+        LightVol {
+            ambient: self.apply(vol.ambient),
+            directional: self.apply(vol.directional),
+            dir: vol.dir,
+        }
+    }
+
+    pub fn apply_light_vols(&self, vols: &LightVolsLump) -> LightVolsLump {
+        LightVolsLump {
+            vols: vols.vols.iter().map(|v| self.apply_light_vol(v)).collect(),
+        }
+    }
+
+    /// Correct both colours of a sample taken from the light grid.
+    /// Samples should be taken from the uncorrected grid, since the Q3 renderer interpolates before clamping.
+    pub fn apply_sample(&self, sample: &LightSample) -> LightSample {
+        LightSample {
+            ambient: self.apply_f32(sample.ambient),
+            directional: self.apply_f32(sample.directional),
+            dir: sample.dir,
+        }
+    }
+
+    /// Correct the colour of every vertex.
+    pub fn apply_vertices(&self, vertices: &VerticesLump) -> VerticesLump {
+        let mut corrected = vertices.clone();
+        for vertex in corrected.vertices.iter_mut() {
+            vertex.color = self.apply_rgba(vertex.color);
+        }
+
+        corrected
+    }
+
+    /// Internal function. Applies the transfer function to a channel in 0-1.
+    fn transfer(&self, c: f32) -> f32 {
+        match self.transfer {
+            Transfer::None => c,
+            Transfer::Gamma(gamma) if gamma > 0.0 => c.powf(1.0 / gamma),
+            Transfer::Gamma(_) => c,
+            Transfer::SrgbToLinear if c <= 0.04045 => c / 12.92,
+            Transfer::SrgbToLinear => ((c + 0.055) / 1.055).powf(2.4),
+        }
+    }
+
+    /// Internal function. Applies the transfer function to a channel in 0-255.
+    fn transfer_byte(&self, c: u32) -> u8 {
+        if self.transfer == Transfer::None {
+            return c as u8;
+        }
+
+        (self.transfer(c as f32 / 255.0) * 255.0).round() as u8
+    }
+}
+
+#[test]
+fn overbright_shift() {
+    let correction = ColourCorrection::default();
+
+    assert_eq!(correction.apply(RGB { r: 10, g: 20, b: 30 }), RGB { r: 40, g: 80, b: 120 });
+
+    // clamped without changing hue
+    assert_eq!(correction.apply(RGB { r: 128, g: 64, b: 32 }), RGB { r: 255, g: 127, b: 63 });
+
+    let hardware = ColourCorrection {
+        overbright_bits: 1,
+        ..correction
+    };
+    assert_eq!(hardware.apply(RGB { r: 10, g: 20, b: 30 }), RGB { r: 20, g: 40, b: 60 });
+}
+
+#[test]
+fn overbright_transfer() {
+    let srgb = ColourCorrection {
+        map_overbright_bits: 0,
+        overbright_bits: 0,
+        transfer: Transfer::SrgbToLinear,
+    };
+    assert_eq!(srgb.apply(RGB { r: 0, g: 255, b: 128 }), RGB { r: 0, g: 255, b: 55 });
+
+    let gamma = ColourCorrection {
+        transfer: Transfer::Gamma(2.0),
+        ..srgb
+    };
+    assert_eq!(gamma.apply_rgba(RGBA { r: 64, g: 0, b: 255, a: 7 }), RGBA { r: 128, g: 0, b: 255, a: 7 });
+
+    let floats = ColourCorrection::default().apply_f32(Vector3::new(100.0, 50.0, 0.0));
+    assert!((floats - Vector3::new(255.0, 127.5, 0.0)).norm() < 0.001);
+}
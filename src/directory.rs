@@ -15,18 +15,41 @@
 // You should have received a copy of the GNU General Public License
 // along with stockton-bsp.  If not, see <http://www.gnu.org/licenses/>.
 
-use crate::types::Result;
+use crate::types::{Error, Result};
 use std::convert::TryInto;
 
 /// "IBSP"
-const MAGIC_HEADER: &[u8] = &[0x49, 0x42, 0x53, 0x50];
-const HEADER_LEN: usize = 4 + 4 + (17 * 4 * 2);
+pub(crate) const MAGIC_HEADER: &[u8] = &[0x49, 0x42, 0x53, 0x50];
+
+/// The name of each lump in a Q3 file, in directory order.
+pub const LUMP_NAMES: [&str; 17] = [
+    "Entities",
+    "Textures",
+    "Planes",
+    "Nodes",
+    "Leaves",
+    "LeafFaces",
+    "LeafBrushes",
+    "Models",
+    "Brushes",
+    "BrushSides",
+    "Vertices",
+    "MeshVerts",
+    "Effects",
+    "Faces",
+    "LightMaps",
+    "LightVols",
+    "VisData",
+];
 
 /// The header found at the start of a (Q3) bsp file
 #[derive(Clone, Copy, Debug)]
 pub struct Header {
     pub version: u32,
     pub dir_entries: [DirEntry; 17],
+
+    /// The extra entry Quake Live (IBSP47) files have for the advertisements lump.
+    pub advertisements: Option<DirEntry>,
 }
 
 /// A directory entry, pointing to a lump in the file
@@ -45,47 +68,124 @@ impl Header {
     /// string[4] magic             Magic number. Always "IBSP".
     /// int version                 Version number. 0x2e for the BSP files distributed with Quake 3.
     /// direntry[17] direntries     Lump directory, seventeen entries.
+    /// direntry advertisements     Quake Live (version 0x2f) only.
     pub fn from(v: &[u8]) -> Result<Header> {
-        if v.len() < HEADER_LEN {
-            return Err(invalid_error!("Header is too short"));
-        }
-        let magic = &v[0..4];
+        let (version, dir_entries) = read_header(v, MAGIC_HEADER)?;
 
-        if magic != MAGIC_HEADER {
-            return Err(invalid_error!("Header magic is incorrect"));
-        }
+        let advertisements = if version == 0x2f {
+            let entry = v
+                .get(8 + (17 * 8)..8 + (18 * 8))
+                .ok_or_else(|| invalid_error!("Header is too short"))?;
 
-        let version: &[u8; 4] = v[4..8].try_into().unwrap();
+            Some(read_entries::<1>(entry)[0])
+        } else {
+            None
+        };
 
-        let entries: &[u8] = &v[8..144];
-        let mut dir_entries: [DirEntry; 17] = [DirEntry {
-            offset: 0,
-            length: 0,
-        }; 17];
+        Ok(Header {
+            version,
+            dir_entries,
+            advertisements,
+        })
+    }
 
-        for n in 0..17 {
-            let base = &entries[(n * 8)..(n * 8) + 8];
-            dir_entries[n] = DirEntry {
-                offset: u32::from_le_bytes(base[0..4].try_into().unwrap()),
-                length: u32::from_le_bytes(base[4..8].try_into().unwrap()),
-            }
+    /// The directory entry at the given index. Index 17 is the advertisements lump, if there is one.
+    pub fn entry(&self, index: usize) -> Option<DirEntry> {
+        match index {
+            17 => self.advertisements,
+            _ => self.dir_entries.get(index).copied(),
         }
+    }
 
-        Ok(Header {
-            version: u32::from_le_bytes(*version),
-            dir_entries,
+    /// Get the lump at given index from the buffer, checking it's inside the buffer.
+    pub fn get_lump<'l>(&self, buf: &'l [u8], index: usize) -> Result<&'l [u8]> {
+        let entry = self
+            .entry(index)
+            .ok_or_else(|| invalid_error!("Lump {} isn't in the directory", index))?;
+
+        entry.get_lump(buf).ok_or(Error::OutOfBounds {
+            lump: LUMP_NAMES.get(index).copied().unwrap_or("Advertisements"),
+            offset: entry.offset as usize,
+            length: entry.length as usize,
         })
     }
+}
+
+impl DirEntry {
+    /// Get the lump this entry points to from the buffer, or `None` if it's out of bounds.
+    pub fn get_lump<'l>(&self, buf: &'l [u8]) -> Option<&'l [u8]> {
+        let start = self.offset as usize;
+        let end = start.checked_add(self.length as usize)?;
+
+        buf.get(start..end)
+    }
+}
+
+/// Read a header made of the given magic, a version number, and `N` directory entries.
+pub(crate) fn read_header<const N: usize>(v: &[u8], magic: &[u8]) -> Result<(u32, [DirEntry; N])> {
+    let len = magic.len() + 4 + (N * 4 * 2);
+    if v.len() < len {
+        return Err(invalid_error!("Header is too short"));
+    }
+
+    if &v[0..magic.len()] != magic {
+        return Err(invalid_error!("Header magic is incorrect"));
+    }
+
+    let version: &[u8; 4] = v[magic.len()..magic.len() + 4].try_into().unwrap();
+
+    Ok((
+        u32::from_le_bytes(*version),
+        read_entries(&v[magic.len() + 4..len]),
+    ))
+}
+
+/// Read `N` directory entries from the start of the given slice.
+/// # Panics
+/// If slice is shorter than `N` entries.
+pub(crate) fn read_entries<const N: usize>(entries: &[u8]) -> [DirEntry; N] {
+    let mut dir_entries = [DirEntry {
+        offset: 0,
+        length: 0,
+    }; N];
+
+    for (n, entry) in dir_entries.iter_mut().enumerate() {
+        let base = &entries[(n * 8)..(n * 8) + 8];
+        *entry = DirEntry {
+            offset: u32::from_le_bytes(base[0..4].try_into().unwrap()),
+            length: u32::from_le_bytes(base[4..8].try_into().unwrap()),
+        }
+    }
+
+    dir_entries
+}
 
-    /// Get the lump at given index from the buffer, with offset & length based on this directory.
-    pub fn get_lump<'l>(&self, buf: &'l [u8], index: usize) -> &'l [u8] {
-        let entry = self.dir_entries[index];
+/// Assemble a file from the given magic, version and lumps, writing the directory as it goes.
+/// Lumps are written in the order given, each starting on a 4 byte boundary.
+pub(crate) fn assemble(magic: &[u8], version: u32, lumps: &[Vec<u8>]) -> Box<[u8]> {
+    let header_len = magic.len() + 4 + (lumps.len() * 4 * 2);
 
-        &buf[entry.offset as usize..entry.offset as usize + entry.length as usize]
+    let mut buf = Vec::with_capacity(header_len + lumps.iter().map(|l| l.len() + 3).sum::<usize>());
+    buf.extend_from_slice(magic);
+    buf.extend_from_slice(&version.to_le_bytes());
+
+    let mut offset = header_len;
+    for lump in lumps {
+        buf.extend_from_slice(&(offset as u32).to_le_bytes());
+        buf.extend_from_slice(&(lump.len() as u32).to_le_bytes());
+        offset += (lump.len() + 3) & !3;
+    }
+
+    for lump in lumps {
+        buf.extend_from_slice(lump);
+        buf.resize((buf.len() + 3) & !3, 0);
     }
+
+    buf.into_boxed_slice()
 }
 
 #[test]
+#[allow(clippy::explicit_counter_loop)]
 fn header() {
     let data = [
         0x49, 0x42, 0x53, 0x50, // magic number (IBSP)
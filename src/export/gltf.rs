@@ -0,0 +1,589 @@
+// Copyright (C) 2019 Oscar Shrimpton
+//
+// This file is part of stockton-bsp.
+//
+// stockton-bsp is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// stockton-bsp is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with stockton-bsp.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Binary glTF 2.0 (`.glb`) export.
+//!
+//! Each model becomes a node with its own mesh: `world` for model 0, and `model_N` for the others.
+//! Model nodes carry the attributes of their entity as `extras`: `worldspawn` for the world, and whichever uses
+//! `"model" "*N"` for the others.
+//! Entities with an `origin` become empty nodes placed at it, also with their attributes as `extras`.
+//! An entity with a malformed `origin`, or a `model` that doesn't exist, is left out rather than failing the export.
+//!
+//! Each mesh has a primitive for every texture & lightmap atlas it uses, with the surface texcoords as `TEXCOORD_0`,
+//! the lightmap texcoords remapped into the atlas as `TEXCOORD_1`, and the vertex colours as `COLOR_0`.
+//! Materials are named after their texture, since the texture images aren't part of the map.
+//! glTF has no lightmap slot, so the atlas is given in the material's extras as `"lightmap": {"index": N, "texCoord": 1}`,
+//! where `N` is a texture pointing at one of the embedded PNG atlases.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use na::Vector3;
+
+use super::{convert, model_mesh, png};
+use crate::lumps::light_maps::LightMapAtlases;
+use crate::lumps::textures::SurfaceFlags;
+use crate::mesh::Mesh;
+use crate::types::Result;
+use crate::BSPFile;
+
+const GLB_MAGIC: &[u8; 4] = b"glTF";
+const GLB_VERSION: u32 = 2;
+const CHUNK_JSON: &[u8; 4] = b"JSON";
+const CHUNK_BIN: &[u8; 4] = b"BIN\0";
+
+const ARRAY_BUFFER: u32 = 34962;
+const ELEMENT_ARRAY_BUFFER: u32 = 34963;
+
+const UNSIGNED_BYTE: u32 = 5121;
+const UNSIGNED_INT: u32 = 5125;
+const FLOAT: u32 = 5126;
+
+const LINEAR: u32 = 9729;
+const CLAMP_TO_EDGE: u32 = 33071;
+
+/// Options for `GltfExport`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GltfOptions {
+    /// As in `ObjOptions::skip`.
+    pub skip: SurfaceFlags,
+
+    /// As in `ObjOptions::tessellation`.
+    pub tessellation: usize,
+
+    /// Convert from Q3's Z-up coordinates to glTF's Y-up. Only turn this off if something else will.
+    pub y_up: bool,
+
+    /// Multiplied with every position. Q3 units are roughly an inch, so `0.0254` gives metres.
+    pub scale: f32,
+
+    /// The width & height of each lightmap atlas. See `LightMapsLump::pack_atlases`.
+    pub atlas_size: usize,
+
+    /// Texels of padding around each lightmap in the atlases.
+    pub atlas_padding: usize,
+}
+
+impl Default for GltfOptions {
+    fn default() -> GltfOptions {
+        GltfOptions {
+            skip: SurfaceFlags::NODRAW | SurfaceFlags::SKY,
+            tessellation: 5,
+            y_up: true,
+            scale: 1.0,
+            atlas_size: 1024,
+            atlas_padding: 1,
+        }
+    }
+}
+
+/// A map converted to glTF, ready to be written as a `.glb` file.
+#[derive(Debug, Clone)]
+pub struct GltfExport {
+    json: String,
+    bin: Vec<u8>,
+}
+
+impl GltfExport {
+    /// Build the meshes, pack the lightmaps & lay out the glTF document.
+    pub fn new(file: &BSPFile, options: GltfOptions) -> Result<GltfExport> {
+        let atlases = file
+            .light_maps
+            .pack_atlases(options.atlas_size, options.atlas_size, options.atlas_padding)?;
+
+        let mut builder = Builder {
+            file,
+            options: &options,
+            atlases: &atlases,
+            bin: Vec::new(),
+            buffer_views: Vec::new(),
+            accessors: Vec::new(),
+            materials: Vec::new(),
+            material_indices: BTreeMap::new(),
+        };
+
+        // one image & texture per atlas
+        let mut images = Vec::with_capacity(atlases.atlases.len());
+        let mut textures = Vec::with_capacity(atlases.atlases.len());
+        for (i, atlas) in atlases.atlases.iter().enumerate() {
+            let png = png::encode_rgba(atlas.width as u32, atlas.height as u32, &atlas.data);
+            let view = builder.buffer_view(&png, None);
+
+            images.push(Json::object(vec![
+                ("name", format!("lightmap_atlas_{}", i).into()),
+                ("bufferView", view.into()),
+                ("mimeType", "image/png".into()),
+            ]));
+            textures.push(Json::object(vec![("sampler", 0usize.into()), ("source", i.into())]));
+        }
+
+        // the entity using each model, with worldspawn as the world
+        let mut model_entities = vec![None; file.models.models.len()];
+        if let Some(world) = model_entities.first_mut() {
+            *world = file.entities.worldspawn();
+        }
+        for entity in file.entities.entities.iter() {
+            if let Ok(Some(idx)) = entity.model_index(&file.models) {
+                model_entities[idx].get_or_insert(entity);
+            }
+        }
+
+        let mut meshes = Vec::new();
+        let mut nodes = Vec::new();
+        for (i, model) in file.models.models.iter().enumerate() {
+            let mesh = model_mesh(file, model, options.skip, options.tessellation)?;
+
+            let name = if i == 0 { "world".to_owned() } else { format!("model_{}", i) };
+            let mut node = vec![("name", Json::from(name.as_str()))];
+
+            let primitives = builder.primitives(&mesh);
+            if !primitives.is_empty() {
+                node.push(("mesh", meshes.len().into()));
+                meshes.push(Json::object(vec![("name", name.into()), ("primitives", Json::Array(primitives))]));
+            }
+
+            if let Some(entity) = model_entities[i] {
+                node.push(("extras", entity_extras(entity.pairs(), |k| entity.get(k))));
+            }
+
+            nodes.push(Json::object(node));
+        }
+
+        for entity in file.entities.entities.iter() {
+            let origin = match entity.origin() {
+                Ok(Some(origin)) => convert(&origin, options.y_up) * options.scale,
+                _ => continue,
+            };
+
+            nodes.push(Json::object(vec![
+                ("name", entity.classname().unwrap_or("entity").into()),
+                ("translation", vec3(&origin)),
+                ("extras", entity_extras(entity.pairs(), |k| entity.get(k))),
+            ]));
+        }
+
+        let mut document = vec![
+            (
+                "asset",
+                Json::object(vec![("version", "2.0".into()), ("generator", "stockton-bsp".into())]),
+            ),
+            ("scene", 0usize.into()),
+            (
+                "scenes",
+                Json::Array(vec![Json::object(vec![(
+                    "nodes",
+                    Json::Array((0..nodes.len()).map(Json::from).collect()),
+                )])]),
+            ),
+            ("nodes", Json::Array(nodes)),
+        ];
+
+        // glTF doesn't allow empty arrays
+        let Builder {
+            bin,
+            buffer_views,
+            accessors,
+            materials,
+            ..
+        } = builder;
+        let arrays = vec![
+            ("meshes", meshes),
+            ("materials", materials),
+            ("textures", textures),
+            ("images", images),
+            ("accessors", accessors),
+            ("bufferViews", buffer_views),
+        ];
+        for (key, array) in arrays {
+            if !array.is_empty() {
+                document.push((key, Json::Array(array)));
+            }
+        }
+        if !atlases.atlases.is_empty() {
+            document.push((
+                "samplers",
+                Json::Array(vec![Json::object(vec![
+                    ("magFilter", LINEAR.into()),
+                    ("minFilter", LINEAR.into()),
+                    ("wrapS", CLAMP_TO_EDGE.into()),
+                    ("wrapT", CLAMP_TO_EDGE.into()),
+                ])]),
+            ));
+        }
+        if !bin.is_empty() {
+            document.push(("buffers", Json::Array(vec![Json::object(vec![("byteLength", bin.len().into())])])));
+        }
+
+        let mut json = String::new();
+        Json::object(document).write(&mut json);
+
+        Ok(GltfExport { json, bin })
+    }
+
+    /// The glTF JSON document.
+    pub fn json(&self) -> &str {
+        &self.json
+    }
+
+    /// The binary buffer the document refers to.
+    pub fn bin(&self) -> &[u8] {
+        &self.bin
+    }
+
+    /// Write the document & buffer as a `.glb` file.
+    pub fn write_glb<W: Write>(&self, w: &mut W) -> Result<()> {
+        let json_len = (self.json.len() + 3) & !3;
+        let bin_len = (self.bin.len() + 3) & !3;
+
+        let mut total = 12 + 8 + json_len;
+        if !self.bin.is_empty() {
+            total += 8 + bin_len;
+        }
+
+        w.write_all(GLB_MAGIC)?;
+        w.write_all(&GLB_VERSION.to_le_bytes())?;
+        w.write_all(&(total as u32).to_le_bytes())?;
+
+        // the JSON chunk is padded with spaces, the binary chunk with zeroes
+        w.write_all(&(json_len as u32).to_le_bytes())?;
+        w.write_all(CHUNK_JSON)?;
+        w.write_all(self.json.as_bytes())?;
+        w.write_all(&b"   "[..json_len - self.json.len()])?;
+
+        if !self.bin.is_empty() {
+            w.write_all(&(bin_len as u32).to_le_bytes())?;
+            w.write_all(CHUNK_BIN)?;
+            w.write_all(&self.bin)?;
+            w.write_all(&[0; 3][..bin_len - self.bin.len()])?;
+        }
+
+        Ok(())
+    }
+
+    /// Write a `.glb` file to `path`.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let mut glb = BufWriter::new(File::create(path)?);
+        self.write_glb(&mut glb)?;
+        glb.flush()?;
+
+        Ok(())
+    }
+}
+
+/// Internal struct. Accumulates the binary buffer & everything that refers to it.
+struct Builder<'a> {
+    file: &'a BSPFile,
+    options: &'a GltfOptions,
+    atlases: &'a LightMapAtlases,
+    bin: Vec<u8>,
+    buffer_views: Vec<Json>,
+    accessors: Vec<Json>,
+    materials: Vec<Json>,
+    material_indices: BTreeMap<(usize, Option<usize>), usize>,
+}
+
+impl<'a> Builder<'a> {
+    /// Append the bytes to the buffer as a new buffer view, and return its index.
+    fn buffer_view(&mut self, bytes: &[u8], target: Option<u32>) -> usize {
+        // accessors need their data aligned to their component size
+        self.bin.resize((self.bin.len() + 3) & !3, 0);
+
+        let mut view = vec![
+            ("buffer", 0usize.into()),
+            ("byteOffset", self.bin.len().into()),
+            ("byteLength", bytes.len().into()),
+        ];
+        if let Some(target) = target {
+            view.push(("target", target.into()));
+        }
+
+        self.bin.extend_from_slice(bytes);
+        self.buffer_views.push(Json::object(view));
+
+        self.buffer_views.len() - 1
+    }
+
+    /// Add an accessor over a new buffer view, and return its index.
+    fn accessor(&mut self, bytes: &[u8], target: u32, component: u32, kind: &str, count: usize) -> usize {
+        let view = self.buffer_view(bytes, Some(target));
+        self.accessors.push(Json::object(vec![
+            ("bufferView", view.into()),
+            ("componentType", component.into()),
+            ("count", count.into()),
+            ("type", kind.into()),
+        ]));
+
+        self.accessors.len() - 1
+    }
+
+    /// The index of the material for the texture & atlas, adding it if needed.
+    fn material(&mut self, texture_idx: usize, atlas: Option<usize>) -> usize {
+        if let Some(idx) = self.material_indices.get(&(texture_idx, atlas)) {
+            return *idx;
+        }
+
+        let texture = self.file.textures.textures.get(texture_idx);
+        let name = texture.map_or("", |t| &t.name);
+        let name = name.split('\0').next().unwrap_or("");
+
+        let mut extras = vec![("texture", Json::from(name))];
+        if let Some(texture) = texture {
+            extras.push(("surfaceFlags", texture.surface.bits().into()));
+            extras.push(("contentsFlags", texture.contents.bits().into()));
+        }
+        if let Some(atlas) = atlas {
+            extras.push((
+                "lightmap",
+                Json::object(vec![("index", atlas.into()), ("texCoord", 1usize.into())]),
+            ));
+        }
+
+        self.materials.push(Json::object(vec![
+            ("name", if name.is_empty() { "unnamed" } else { name }.into()),
+            (
+                "pbrMetallicRoughness",
+                Json::object(vec![("metallicFactor", 0usize.into()), ("roughnessFactor", 1usize.into())]),
+            ),
+            ("extras", Json::object(extras)),
+        ]));
+
+        let idx = self.materials.len() - 1;
+        self.material_indices.insert((texture_idx, atlas), idx);
+
+        idx
+    }
+
+    /// One primitive for each texture & lightmap atlas in the mesh.
+    fn primitives(&mut self, mesh: &Mesh) -> Vec<Json> {
+        let mut groups: BTreeMap<(usize, Option<usize>), Vec<usize>> = BTreeMap::new();
+        for (i, batch) in mesh.batches.iter().enumerate() {
+            let atlas = self.atlases.atlas_of(batch.key.lightmap_idx);
+            groups.entry((batch.key.texture_idx, atlas)).or_default().push(i);
+        }
+
+        let mut primitives = Vec::with_capacity(groups.len());
+        for ((texture_idx, atlas), batches) in groups {
+            let mut positions = Vec::new();
+            let mut normals = Vec::new();
+            let mut surface = Vec::new();
+            let mut lightmap = Vec::new();
+            let mut colors = Vec::new();
+            let mut indices = Vec::new();
+            let mut min = Vector3::repeat(f32::INFINITY);
+            let mut max = Vector3::repeat(f32::NEG_INFINITY);
+            let mut count = 0;
+
+            for batch in batches.iter().map(|b| &mesh.batches[*b]) {
+                let (first, start) = (count as u32, batch.vertices.start as u32);
+                let has_lightmap = atlas.is_some();
+
+                for vertex in mesh.vertices[batch.vertices.clone()].iter() {
+                    let position = convert(&vertex.position, self.options.y_up) * self.options.scale;
+                    min = min.zip_map(&position, f32::min);
+                    max = max.zip_map(&position, f32::max);
+
+                    let lightmap_tex = match batch.key.lightmap_idx {
+                        Some(idx) if has_lightmap => self.atlases.remap(idx, vertex.tex.v),
+                        _ => vertex.tex.v,
+                    };
+
+                    push_f32s(&mut positions, position.as_slice());
+                    push_f32s(&mut normals, convert(&vertex.normal, self.options.y_up).as_slice());
+                    push_f32s(&mut surface, &vertex.tex.u);
+                    push_f32s(&mut lightmap, &lightmap_tex);
+                    colors.extend_from_slice(&[vertex.color.r, vertex.color.g, vertex.color.b, vertex.color.a]);
+                    count += 1;
+                }
+
+                // Q3 faces are wound clockwise, glTF's anticlockwise
+                for triangle in mesh.indices[batch.indices.clone()].chunks_exact(3) {
+                    for idx in [triangle[0], triangle[2], triangle[1]] {
+                        indices.extend_from_slice(&(idx - start + first).to_le_bytes());
+                    }
+                }
+            }
+
+            if indices.is_empty() {
+                continue;
+            }
+
+            let position = self.accessor(&positions, ARRAY_BUFFER, FLOAT, "VEC3", count);
+            if let Json::Object(ref mut fields) = self.accessors[position] {
+                fields.push(("min".to_owned(), vec3(&min)));
+                fields.push(("max".to_owned(), vec3(&max)));
+            }
+            let normal = self.accessor(&normals, ARRAY_BUFFER, FLOAT, "VEC3", count);
+            let texcoord_0 = self.accessor(&surface, ARRAY_BUFFER, FLOAT, "VEC2", count);
+            let texcoord_1 = self.accessor(&lightmap, ARRAY_BUFFER, FLOAT, "VEC2", count);
+            let color = self.accessor(&colors, ARRAY_BUFFER, UNSIGNED_BYTE, "VEC4", count);
+            if let Json::Object(ref mut fields) = self.accessors[color] {
+                fields.push(("normalized".to_owned(), Json::Bool(true)));
+            }
+            let index_count = indices.len() / 4;
+            let indices = self.accessor(&indices, ELEMENT_ARRAY_BUFFER, UNSIGNED_INT, "SCALAR", index_count);
+
+            primitives.push(Json::object(vec![
+                (
+                    "attributes",
+                    Json::object(vec![
+                        ("POSITION", position.into()),
+                        ("NORMAL", normal.into()),
+                        ("TEXCOORD_0", texcoord_0.into()),
+                        ("TEXCOORD_1", texcoord_1.into()),
+                        ("COLOR_0", color.into()),
+                    ]),
+                ),
+                ("indices", indices.into()),
+                ("material", self.material(texture_idx, atlas).into()),
+            ]));
+        }
+
+        primitives
+    }
+}
+
+/// Internal function. Appends little-endian floats.
+fn push_f32s(buf: &mut Vec<u8>, values: &[f32]) {
+    for value in values {
+        buf.extend_from_slice(&value.to_le_bytes());
+    }
+}
+
+/// Internal function. A vector as a JSON array.
+fn vec3(v: &Vector3<f32>) -> Json {
+    Json::Array(v.iter().map(|c| Json::Float(*c)).collect())
+}
+
+/// Internal function. An entity's attributes as a JSON object, in file order.
+/// Repeated keys appear once, with the value `Entity::get` gives.
+fn entity_extras<'e, F: Fn(&str) -> Option<&'e str>>(pairs: &[(String, String)], get: F) -> Json {
+    let mut fields: Vec<(String, Json)> = Vec::with_capacity(pairs.len());
+    for (key, _) in pairs.iter() {
+        if fields.iter().any(|(k, _)| k == key) {
+            continue;
+        }
+
+        fields.push((key.clone(), get(key).unwrap_or("").into()));
+    }
+
+    Json::Object(fields)
+}
+
+/// Internal enum. Just enough JSON to write a glTF document.
+#[derive(Debug, Clone, PartialEq)]
+enum Json {
+    Bool(bool),
+    Int(u64),
+    Float(f32),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    fn object(fields: Vec<(&str, Json)>) -> Json {
+        Json::Object(fields.into_iter().map(|(k, v)| (k.to_owned(), v)).collect())
+    }
+
+    fn write(&self, out: &mut String) {
+        match self {
+            Json::Bool(b) => out.push_str(if *b { "true" } else { "false" }),
+            Json::Int(i) => out.push_str(&i.to_string()),
+            // JSON has no infinities or NaNs
+            Json::Float(f) if !f.is_finite() => out.push('0'),
+            Json::Float(f) => out.push_str(&f.to_string()),
+            Json::String(s) => write_string(s, out),
+            Json::Array(items) => {
+                out.push('[');
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    item.write(out);
+                }
+                out.push(']');
+            }
+            Json::Object(fields) => {
+                out.push('{');
+                for (i, (key, value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    write_string(key, out);
+                    out.push(':');
+                    value.write(out);
+                }
+                out.push('}');
+            }
+        }
+    }
+}
+
+impl From<usize> for Json {
+    fn from(i: usize) -> Json {
+        Json::Int(i as u64)
+    }
+}
+
+impl From<u32> for Json {
+    fn from(i: u32) -> Json {
+        Json::Int(i.into())
+    }
+}
+
+impl From<&str> for Json {
+    fn from(s: &str) -> Json {
+        Json::String(s.to_owned())
+    }
+}
+
+impl From<String> for Json {
+    fn from(s: String) -> Json {
+        Json::String(s)
+    }
+}
+
+/// Internal function. Writes a quoted & escaped JSON string.
+fn write_string(s: &str, out: &mut String) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+#[test]
+fn json_escaping() {
+    let mut out = String::new();
+    Json::object(vec![
+        ("a \"b\"", "c\\d\n\0".into()),
+        ("list", Json::Array(vec![1usize.into(), Json::Float(0.5), Json::Float(f32::NAN), Json::Bool(false)])),
+    ])
+    .write(&mut out);
+
+    assert_eq!(out, r#"{"a \"b\"":"c\\d\n\u0000","list":[1,0.5,0,false]}"#);
+}
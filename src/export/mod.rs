@@ -0,0 +1,56 @@
+// Copyright (C) 2019 Oscar Shrimpton
+//
+// This file is part of stockton-bsp.
+//
+// stockton-bsp is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// stockton-bsp is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with stockton-bsp.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Exporting map geometry to formats other tools can open.
+
+pub mod gltf;
+pub mod obj;
+pub mod png;
+
+pub use self::gltf::{GltfExport, GltfOptions};
+pub use self::obj::{ObjExport, ObjOptions};
+
+use na::Vector3;
+
+use crate::lumps::models::Model;
+use crate::lumps::textures::SurfaceFlags;
+use crate::mesh::{Billboards, Mesh, MeshOptions};
+use crate::types::Result;
+use crate::BSPFile;
+
+/// Internal function. Builds a model's mesh, without billboards or faces whose texture has any of the `skip` flags.
+fn model_mesh(file: &BSPFile, model: &Model, skip: SurfaceFlags, tessellation: usize) -> Result<Mesh> {
+    let faces = model.faces_idx.clone().filter(|f| {
+        let texture = file.faces.faces.get(*f).and_then(|f| file.textures.textures.get(f.texture_idx));
+        !texture.map_or(false, |t| t.surface.intersects(skip))
+    });
+    let options = MeshOptions {
+        tessellation,
+        billboards: Billboards::Skip,
+    };
+
+    Mesh::from_faces(file, faces, &options)
+}
+
+/// Internal function. Converts a position or normal from Q3's Z-up coordinates to Y-up, if asked to.
+fn convert(v: &Vector3<f32>, y_up: bool) -> Vector3<f32> {
+    if y_up {
+        Vector3::new(v.x, v.z, -v.y)
+    } else {
+        *v
+    }
+}
@@ -0,0 +1,207 @@
+// Copyright (C) 2019 Oscar Shrimpton
+//
+// This file is part of stockton-bsp.
+//
+// stockton-bsp is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// stockton-bsp is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with stockton-bsp.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Wavefront OBJ & MTL export.
+//!
+//! The world is written as the object `world`, and each submodel as `model_N`, matching the `*N` entities use.
+//! Each texture name becomes a material, with its name as the diffuse map.
+//!
+//! OBJ only has one set of texture coordinates, so the lightmap coordinates go in a sidecar file.
+//! It has an `o` line for each object, then for each group of faces a `lightmap` line with the lightmap's index
+//! (or `none`), followed by a `vt` line for each vertex. Vertices are in the same order as the `v` lines in the OBJ.
+
+use std::collections::BTreeSet;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use super::{convert, model_mesh};
+use crate::lumps::textures::SurfaceFlags;
+use crate::mesh::Mesh;
+use crate::types::Result;
+use crate::BSPFile;
+
+/// Options for `ObjExport`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ObjOptions {
+    /// Faces whose texture has any of these flags are left out. `NODRAW | SKY` by default.
+    pub skip: SurfaceFlags,
+
+    /// How many times to subdivide each patch in each direction. See `Face::tessellate`.
+    pub tessellation: usize,
+
+    /// Convert from Q3's Z-up coordinates to the Y-up most tools expect from OBJ files.
+    pub y_up: bool,
+
+    /// Added to each texture name in the MTL file, since the map doesn't say which image format textures are in.
+    pub texture_extension: String,
+}
+
+impl Default for ObjOptions {
+    fn default() -> ObjOptions {
+        ObjOptions {
+            skip: SurfaceFlags::NODRAW | SurfaceFlags::SKY,
+            tessellation: 5,
+            y_up: true,
+            texture_extension: ".tga".to_owned(),
+        }
+    }
+}
+
+/// The world & submodels of a map, ready to be written as OBJ.
+#[derive(Debug, Clone)]
+pub struct ObjExport<'a> {
+    file: &'a BSPFile,
+    options: ObjOptions,
+    objects: Vec<(String, Mesh)>,
+}
+
+impl<'a> ObjExport<'a> {
+    /// Build the meshes for every model in the file. Billboards are left out.
+    pub fn new(file: &'a BSPFile, options: ObjOptions) -> Result<ObjExport<'a>> {
+        let mut objects = Vec::with_capacity(file.models.models.len());
+        for (i, model) in file.models.models.iter().enumerate() {
+            let name = if i == 0 { "world".to_owned() } else { format!("model_{}", i) };
+            objects.push((name, model_mesh(file, model, options.skip, options.tessellation)?));
+        }
+
+        Ok(ObjExport {
+            file,
+            options,
+            objects,
+        })
+    }
+
+    /// The material name for the given texture, without the NUL padding. OBJ names can't have spaces in.
+    fn material(&self, texture_idx: usize) -> String {
+        let name = self.file.textures.textures.get(texture_idx).map_or("", |t| &t.name);
+        let name = name.split('\0').next().unwrap_or("");
+        if name.is_empty() {
+            return "unnamed".to_owned();
+        }
+
+        name.split_whitespace().collect::<Vec<_>>().join("_")
+    }
+
+    /// Write the OBJ file, referring to the given MTL file if there is one.
+    pub fn write_obj<W: Write>(&self, w: &mut W, mtl: Option<&str>) -> Result<()> {
+        writeln!(w, "# Exported by stockton-bsp")?;
+        if let Some(mtl) = mtl {
+            writeln!(w, "mtllib {}", mtl)?;
+        }
+
+        // OBJ indices start at 1, and count up through the whole file
+        let mut base = 1;
+        for (name, mesh) in self.objects.iter() {
+            writeln!(w, "o {}", name)?;
+
+            for vertex in mesh.vertices.iter() {
+                let p = convert(&vertex.position, self.options.y_up);
+                writeln!(w, "v {} {} {}", p.x, p.y, p.z)?;
+            }
+            for vertex in mesh.vertices.iter() {
+                // Q3 texture coordinates start at the top of the image, OBJ's at the bottom
+                writeln!(w, "vt {} {}", vertex.tex.u[0], 1.0 - vertex.tex.u[1])?;
+            }
+            for vertex in mesh.vertices.iter() {
+                let n = convert(&vertex.normal, self.options.y_up);
+                writeln!(w, "vn {} {} {}", n.x, n.y, n.z)?;
+            }
+
+            for batch in mesh.batches.iter() {
+                writeln!(w, "usemtl {}", self.material(batch.key.texture_idx))?;
+
+                // Q3 faces are wound clockwise, OBJ's anticlockwise
+                for triangle in mesh.indices[batch.indices.clone()].chunks_exact(3) {
+                    let (a, b, c) = (triangle[0] + base, triangle[2] + base, triangle[1] + base);
+                    writeln!(w, "f {0}/{0}/{0} {1}/{1}/{1} {2}/{2}/{2}", a, b, c)?;
+                }
+            }
+
+            base += mesh.vertices.len() as u32;
+        }
+
+        Ok(())
+    }
+
+    /// Write the MTL file, with one material for each texture used.
+    pub fn write_mtl<W: Write>(&self, w: &mut W) -> Result<()> {
+        let textures: BTreeSet<usize> = self
+            .objects
+            .iter()
+            .flat_map(|(_, mesh)| mesh.batches.iter().map(|b| b.key.texture_idx))
+            .collect();
+
+        let mut written = BTreeSet::new();
+        writeln!(w, "# Exported by stockton-bsp")?;
+        for texture_idx in textures {
+            let name = self.material(texture_idx);
+            if !written.insert(name.clone()) {
+                continue;
+            }
+
+            writeln!(w)?;
+            writeln!(w, "newmtl {}", name)?;
+            writeln!(w, "Kd 1 1 1")?;
+            writeln!(w, "map_Kd {}{}", name, self.options.texture_extension)?;
+        }
+
+        Ok(())
+    }
+
+    /// Write the lightmap texture coordinates sidecar, described in the module docs.
+    pub fn write_lightmap_uvs<W: Write>(&self, w: &mut W) -> Result<()> {
+        writeln!(w, "# Lightmap texture coordinates, exported by stockton-bsp")?;
+        for (name, mesh) in self.objects.iter() {
+            writeln!(w, "o {}", name)?;
+
+            for batch in mesh.batches.iter() {
+                match batch.key.lightmap_idx {
+                    Some(idx) => writeln!(w, "lightmap {}", idx)?,
+                    None => writeln!(w, "lightmap none")?,
+                }
+
+                for vertex in mesh.vertices[batch.vertices.clone()].iter() {
+                    writeln!(w, "vt {} {}", vertex.tex.v[0], 1.0 - vertex.tex.v[1])?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Write the OBJ to `path`, with the MTL & lightmap coordinates next to it with the extensions `mtl` & `lmuv`.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let mtl = path.with_extension("mtl");
+        let mtl_name = mtl.file_name().and_then(|n| n.to_str());
+
+        let mut obj = BufWriter::new(File::create(path)?);
+        self.write_obj(&mut obj, mtl_name)?;
+        obj.flush()?;
+
+        let mut mtl = BufWriter::new(File::create(&mtl)?);
+        self.write_mtl(&mut mtl)?;
+        mtl.flush()?;
+
+        let mut uvs = BufWriter::new(File::create(path.with_extension("lmuv"))?);
+        self.write_lightmap_uvs(&mut uvs)?;
+        uvs.flush()?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,144 @@
+// Copyright (C) 2019 Oscar Shrimpton
+//
+// This file is part of stockton-bsp.
+//
+// stockton-bsp is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// stockton-bsp is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with stockton-bsp.  If not, see <http://www.gnu.org/licenses/>.
+
+//! A minimal PNG encoder, for embedding lightmaps in exported files.
+//!
+//! Image data is stored without compression, which keeps this small at the cost of larger files.
+
+/// The 8 bytes every PNG file starts with.
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+
+/// The most a stored deflate block can hold.
+const MAX_STORED_BLOCK: usize = 0xffff;
+
+/// Encode a row-major RGBA8 image as a PNG file.
+/// # Panics
+/// If `rgba` isn't `width * height * 4` bytes long.
+pub fn encode_rgba(width: u32, height: u32, rgba: &[u8]) -> Vec<u8> {
+    let stride = width as usize * 4;
+    assert_eq!(rgba.len(), stride * height as usize);
+
+    let mut png = SIGNATURE.to_vec();
+
+    // 8 bits per channel, RGBA, default compression, filtering & no interlacing
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&width.to_be_bytes());
+    header.extend_from_slice(&height.to_be_bytes());
+    header.extend_from_slice(&[8, 6, 0, 0, 0]);
+    push_chunk(&mut png, b"IHDR", &header);
+
+    // each row starts with its filter type, which is always none
+    let mut raw = Vec::with_capacity((stride + 1) * height as usize);
+    for row in rgba.chunks_exact(stride.max(1)).take(height as usize) {
+        raw.push(0);
+        raw.extend_from_slice(row);
+    }
+    push_chunk(&mut png, b"IDAT", &zlib_stored(&raw));
+
+    push_chunk(&mut png, b"IEND", &[]);
+
+    png
+}
+
+/// Internal function. Appends a chunk with its length & CRC.
+fn push_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+/// Internal function. Wraps the data in a zlib stream of stored (uncompressed) deflate blocks.
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let n_blocks = ((data.len() + MAX_STORED_BLOCK - 1) / MAX_STORED_BLOCK).max(1);
+    let mut out = Vec::with_capacity(2 + data.len() + (n_blocks * 5) + 4);
+
+    // deflate with a 32K window, no preset dictionary
+    out.extend_from_slice(&[0x78, 0x01]);
+
+    let mut blocks = data.chunks(MAX_STORED_BLOCK).peekable();
+    if blocks.peek().is_none() {
+        out.extend_from_slice(&[1, 0, 0, 0xff, 0xff]);
+    }
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        let len = block.len() as u16;
+
+        out.push(last as u8);
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+
+    out
+}
+
+/// Internal function. The CRC-32 used by PNG & zlib.
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffffu32;
+    for byte in data {
+        crc ^= u32::from(*byte);
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0xedb8_8320 } else { crc >> 1 };
+        }
+    }
+
+    !crc
+}
+
+/// Internal function. The Adler-32 checksum at the end of a zlib stream.
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for byte in data {
+        a = (a + u32::from(*byte)) % 65521;
+        b = (b + a) % 65521;
+    }
+
+    (b << 16) | a
+}
+
+#[test]
+fn checksums() {
+    assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    assert_eq!(crc32(b"IEND"), 0xae42_6082);
+    assert_eq!(adler32(b"Wikipedia"), 0x11e6_0398);
+}
+
+#[test]
+fn encode_rgba_layout() {
+    let png = encode_rgba(2, 1, &[255, 0, 0, 255, 0, 255, 0, 255]);
+    assert_eq!(&png[..8], &SIGNATURE);
+
+    // IHDR
+    assert_eq!(&png[8..16], &[0, 0, 0, 13, b'I', b'H', b'D', b'R']);
+    assert_eq!(&png[16..24], &[0, 0, 0, 2, 0, 0, 0, 1]);
+
+    // IDAT holds a zlib header, one stored block of the filtered row & the adler checksum
+    let idat_len = u32::from_be_bytes([png[33], png[34], png[35], png[36]]) as usize;
+    assert_eq!(&png[37..41], b"IDAT");
+    let idat = &png[41..41 + idat_len];
+    assert_eq!(&idat[..7], &[0x78, 0x01, 1, 9, 0, 0xf6, 0xff]);
+    assert_eq!(&idat[7..16], &[0, 255, 0, 0, 255, 0, 255, 0, 255]);
+
+    assert_eq!(&png[png.len() - 12..], &[0, 0, 0, 0, b'I', b'E', b'N', b'D', 0xae, 0x42, 0x60, 0x82]);
+}
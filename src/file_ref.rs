@@ -0,0 +1,332 @@
+// Copyright (C) 2019 Oscar Shrimpton
+//
+// This file is part of stockton-bsp.
+//
+// stockton-bsp is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// stockton-bsp is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with stockton-bsp.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Borrowed, lazily parsed view over a BSP file.
+
+use std::fmt;
+use std::str;
+
+use crate::directory::{Header, LUMP_NAMES};
+use crate::lumps::helpers::{join, join3};
+use crate::lumps::light_maps::{LightMap, LIGHTMAP_SIZE};
+use crate::lumps::light_vols::{LightVol, VOL_LENGTH};
+use crate::lumps::planes::{Plane, PLANE_SIZE};
+use crate::lumps::textures::{Texture, TEXTURE_LUMP_SIZE};
+use crate::lumps::vertices::{Vertex, VERTEX_SIZE};
+use crate::lumps::*;
+use crate::types::{Error, Result};
+use crate::options::LoadOptions;
+use crate::BSPFile;
+
+/// A view over a BSP file in a borrowed buffer.
+/// Only the header is parsed up front, lumps are parsed when they're asked for.
+#[derive(Debug, Clone, Copy)]
+pub struct BSPFileRef<'a> {
+    pub directory: Header,
+    buf: &'a [u8],
+}
+
+/// A lump of fixed-size records, decoded one at a time as they're accessed.
+#[derive(Clone, Copy)]
+pub struct Records<'a, T> {
+    data: &'a [u8],
+    size: usize,
+    decode: fn(&[u8]) -> T,
+}
+
+impl<'a> BSPFileRef<'a> {
+    /// Check the header of the given buffer, and that all of its lumps are inside it.
+    /// No lumps are parsed.
+    pub fn from_buffer(buf: &'a [u8]) -> Result<BSPFileRef<'a>> {
+        let header = Header::from(buf)?;
+
+        match header.version {
+            // Quake 3 or Quake LIVE (IBSP47)
+            0x2e | 0x2f => {}
+            _ => {
+                return Err(Error::Unsupported {
+                    version: header.version,
+                })
+            }
+        }
+
+        for index in 0..18 {
+            if header.entry(index).is_some() {
+                header.get_lump(buf, index)?;
+            }
+        }
+
+        Ok(BSPFileRef {
+            directory: header,
+            buf,
+        })
+    }
+
+    /// The raw bytes of the lump at the given index.
+    /// Lumps that aren't in the directory are empty.
+    pub fn lump(&self, index: usize) -> &'a [u8] {
+        // every entry was checked in `from_buffer`
+        self.directory.get_lump(self.buf, index).unwrap_or(&[])
+    }
+
+    /// The entities lump as a string, without any parsing or copying.
+    pub fn entities_str(&self) -> Result<&'a str> {
+        let lump = self.lump(0);
+        let len = lump.iter().position(|b| *b == 0).unwrap_or(lump.len());
+
+        Ok(str::from_utf8(&lump[..len])?)
+    }
+
+    pub fn entities(&self) -> Result<EntitiesLump> {
+        EntitiesLump::from_lump(self.lump(0))
+    }
+
+    pub fn textures(&self) -> Result<TexturesLump> {
+        TexturesLump::from_lump(self.lump(1))
+    }
+
+    /// The textures lump, decoded as each texture is accessed.
+    pub fn texture_records(&self) -> Result<Records<'a, Result<Texture>>> {
+        Records::new(LUMP_NAMES[1], self.lump(1), TEXTURE_LUMP_SIZE, Texture::from_slice)
+    }
+
+    pub fn planes(&self) -> Result<PlanesLump> {
+        PlanesLump::from_lump(self.lump(2))
+    }
+
+    /// The planes lump, decoded as each plane is accessed.
+    pub fn plane_records(&self) -> Result<Records<'a, Plane>> {
+        Records::new(LUMP_NAMES[2], self.lump(2), PLANE_SIZE, Plane::from_slice)
+    }
+
+    pub fn vertices(&self) -> Result<VerticesLump> {
+        VerticesLump::from_lump(self.lump(10))
+    }
+
+    /// The vertices lump, decoded as each vertex is accessed.
+    pub fn vertex_records(&self) -> Result<Records<'a, Vertex>> {
+        Records::new(LUMP_NAMES[10], self.lump(10), VERTEX_SIZE, Vertex::from_slice)
+    }
+
+    pub fn meshverts(&self) -> Result<MeshVertsLump> {
+        MeshVertsLump::from_lump(self.lump(11))
+    }
+
+    pub fn light_maps(&self) -> Result<LightMapsLump> {
+        LightMapsLump::from_lump(self.lump(14))
+    }
+
+    /// The lightmaps lump, decoded as each lightmap is accessed.
+    pub fn light_map_records(&self) -> Result<Records<'a, LightMap>> {
+        Records::new(LUMP_NAMES[14], self.lump(14), LIGHTMAP_SIZE, LightMap::from_slice)
+    }
+
+    pub fn light_vols(&self) -> Result<LightVolsLump> {
+        LightVolsLump::from_lump(self.lump(15))
+    }
+
+    /// The light volumes lump, decoded as each volume is accessed.
+    pub fn light_vol_records(&self) -> Result<Records<'a, LightVol>> {
+        Records::new(LUMP_NAMES[15], self.lump(15), VOL_LENGTH, LightVol::from_slice)
+    }
+
+    pub fn visdata(&self) -> Result<VisDataLump> {
+        VisDataLump::from_lump(self.lump(16))
+    }
+
+    pub fn brushes(&self, textures: &TexturesLump, planes: &PlanesLump) -> Result<BrushesLump> {
+        BrushesLump::from_lump(self.lump(8), self.lump(9), textures, planes)
+    }
+
+    pub fn effects(&self, brushes: &BrushesLump) -> Result<EffectsLump> {
+        EffectsLump::from_lump(self.lump(12), brushes)
+    }
+
+    pub fn faces(
+        &self,
+        textures: &TexturesLump,
+        effects: &EffectsLump,
+        vertices: &VerticesLump,
+        meshverts: &MeshVertsLump,
+        light_maps: &LightMapsLump,
+    ) -> Result<FaceLump> {
+        FaceLump::from_lump(self.lump(13), textures, effects, vertices, meshverts, light_maps)
+    }
+
+    pub fn tree(&self, faces: &FaceLump, brushes: &BrushesLump) -> Result<BSPTree> {
+        BSPTree::from_lumps(
+            self.lump(3),
+            self.lump(4),
+            self.lump(5),
+            self.lump(6),
+            faces,
+            brushes,
+        )
+    }
+
+    pub fn models(&self, faces: &FaceLump, brushes: &BrushesLump) -> Result<ModelsLump> {
+        ModelsLump::from_lump(self.lump(7), faces, brushes)
+    }
+
+    /// Only present for Quake live maps (IBSP47)
+    pub fn advertisements(&self) -> Result<Option<AdvertisementsLump>> {
+        if self.directory.version == 0x2f {
+            Ok(Some(AdvertisementsLump::from_lump(self.lump(17))?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Parse every lump into an owned `BSPFile`.
+    /// With the `rayon` feature, lumps that don't depend on each other are parsed at the same time.
+    /// Errors are still checked in the same order, so the same one is returned either way.
+    pub fn parse(&self) -> Result<BSPFile> {
+        self.parse_with(&LoadOptions::default())
+    }
+
+    /// Parse the lumps chosen by `options` into an owned `BSPFile`, leaving the others empty.
+    pub fn parse_with(&self, options: &LoadOptions) -> Result<BSPFile> {
+        let ((entities, textures, planes), (vertices, meshverts, light_maps), (light_vols, visdata, advertisements)) = join3(
+            || join3(|| self.entities(), || self.textures(), || self.planes()),
+            || {
+                join3(
+                    || self.vertices(),
+                    || self.meshverts(),
+                    || load_if(options.light_maps, || self.light_maps()),
+                )
+            },
+            || {
+                join3(
+                    || load_if(options.light_vols, || self.light_vols()),
+                    || load_if(options.visdata, || self.visdata()),
+                    || load_if(options.advertisements, || self.advertisements()),
+                )
+            },
+        );
+        let entities = entities?;
+        let textures = textures?;
+        let planes = planes?;
+        let vertices = vertices?;
+        let meshverts = meshverts?;
+        let light_maps = light_maps?;
+        let light_vols = light_vols?.unwrap_or_else(LightVolsLump::empty);
+        let visdata = visdata?.unwrap_or_else(VisDataLump::empty);
+
+        // brushes -> effects -> faces -> tree & models
+        let brushes = self.brushes(&textures, &planes)?;
+        let effects = self.effects(&brushes)?;
+        let faces = FaceLump::from_lump_with(
+            self.lump(13),
+            &textures,
+            &effects,
+            &vertices,
+            &meshverts,
+            light_maps.as_ref(),
+        )?;
+        let (tree, models) = join(
+            || load_if(options.tree, || self.tree(&faces, &brushes)),
+            || self.models(&faces, &brushes),
+        );
+        let tree = tree?.unwrap_or_else(BSPTree::empty);
+        let models = models?;
+        let light_maps = light_maps.unwrap_or_else(LightMapsLump::empty);
+        let advertisements = advertisements?.flatten();
+
+        Ok(BSPFile {
+            directory: self.directory,
+            entities,
+            textures,
+            planes,
+            light_vols,
+            light_maps,
+            vertices,
+            meshverts,
+            visdata,
+            advertisements,
+            brushes,
+            effects,
+            faces,
+            tree,
+            models,
+        })
+    }
+}
+
+/// Internal function. Runs `parse` only if the lump is to be loaded.
+fn load_if<T, F: FnOnce() -> Result<T>>(load: bool, parse: F) -> Result<Option<T>> {
+    if load {
+        parse().map(Some)
+    } else {
+        Ok(None)
+    }
+}
+
+impl<'a, T> Records<'a, T> {
+    /// Internal function. Checks the lump is a whole number of records.
+    fn new(lump: &'static str, data: &'a [u8], size: usize, decode: fn(&[u8]) -> T) -> Result<Records<'a, T>> {
+        if data.len() % size != 0 {
+            return Err(size_error!(lump, data.len(), size));
+        }
+
+        Ok(Records {
+            data,
+            size,
+            decode,
+        })
+    }
+
+    /// The number of records in the lump.
+    pub fn len(&self) -> usize {
+        self.data.len() / self.size
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// The raw bytes of the record at the given index.
+    pub fn raw(&self, index: usize) -> Option<&'a [u8]> {
+        if index < self.len() {
+            Some(&self.data[index * self.size..(index + 1) * self.size])
+        } else {
+            None
+        }
+    }
+
+    /// Decode the record at the given index.
+    pub fn get(&self, index: usize) -> Option<T> {
+        self.raw(index).map(self.decode)
+    }
+
+    /// Decode each record in turn.
+    pub fn iter(&self) -> impl Iterator<Item = T> + 'a
+    where
+        T: 'a,
+    {
+        let decode = self.decode;
+        self.data.chunks_exact(self.size).map(decode)
+    }
+}
+
+impl<'a, T> fmt::Debug for Records<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Records")
+            .field("len", &self.len())
+            .field("size", &self.size)
+            .finish()
+    }
+}
@@ -19,16 +19,96 @@
 extern crate bitflags;
 extern crate bit_vec;
 extern crate nalgebra as na;
+#[cfg(feature = "mmap")]
+extern crate memmap2;
+#[cfg(feature = "rayon")]
+extern crate rayon;
 
 #[macro_use]
 mod macros;
+pub mod collision;
+pub mod colour;
 pub mod directory;
+pub mod export;
+mod file_ref;
 pub mod lumps;
+pub mod mesh;
+mod options;
+pub mod q1;
+pub mod q2;
+mod reader;
+pub mod rbsp;
+pub mod vbsp;
 pub mod types;
 
+use std::io::{Read, Seek};
+use std::path::Path;
+
 use lumps::*;
-use directory::Header;
-use types::{Error, Result};
+use directory::{assemble, Header, MAGIC_HEADER};
+use types::{Location, Result};
+
+pub use file_ref::{BSPFileRef, Records};
+pub use options::LoadOptions;
+pub use reader::BSPReader;
+#[cfg(feature = "mmap")]
+pub use reader::MappedBSPFile;
+
+/// A parsed BSP file from any of the supported games.
+#[derive(Debug, Clone)]
+pub enum AnyBSPFile {
+    /// Quake 3 (IBSP46) or Quake Live (IBSP47)
+    Quake3(BSPFile),
+
+    /// Quake 2 (IBSP38)
+    Quake2(q2::Q2BSPFile),
+
+    /// Quake 1 (BSP29)
+    Quake1(q1::Q1BSPFile),
+
+    /// Half-Life & other GoldSrc games (BSP30)
+    GoldSrc(q1::Q1BSPFile),
+
+    /// Jedi Outcast, Jedi Academy & Soldier of Fortune 2 (RBSP1)
+    Raven(rbsp::RBSPFile),
+
+    /// Source engine games (VBSP19 - VBSP21)
+    /// Boxed since the header is much larger than the other formats'.
+    Source(Box<vbsp::VBSPFile>),
+}
+
+impl AnyBSPFile {
+    /// Try to parse the given buffer as a BSP file, working out which game it's from by the header.
+    pub fn from_buffer(buf: Box<[u8]>) -> Result<AnyBSPFile> {
+        // Quake 1 & GoldSrc files have no magic, and start with the version instead.
+        let first = buf.get(0..4).map(|v| u32::from_le_bytes([v[0], v[1], v[2], v[3]]));
+        match first {
+            Some(q1::Q1_VERSION) => return Ok(AnyBSPFile::Quake1(q1::Q1BSPFile::from_buffer(buf)?)),
+            Some(q1::GOLDSRC_VERSION) => return Ok(AnyBSPFile::GoldSrc(q1::Q1BSPFile::from_buffer(buf)?)),
+            _ => {}
+        }
+
+        if buf.get(0..4) == Some(rbsp::RBSP_MAGIC) {
+            return Ok(AnyBSPFile::Raven(rbsp::RBSPFile::from_buffer(buf)?));
+        }
+
+        if buf.get(0..4) == Some(vbsp::VBSP_MAGIC) {
+            return Ok(AnyBSPFile::Source(Box::new(vbsp::VBSPFile::from_buffer(buf)?)));
+        }
+
+        let version = match buf.get(0..8) {
+            Some(header) if &header[0..4] == MAGIC_HEADER => {
+                Some(u32::from_le_bytes([header[4], header[5], header[6], header[7]]))
+            }
+            _ => None,
+        };
+
+        match version {
+            Some(q2::Q2_VERSION) => Ok(AnyBSPFile::Quake2(q2::Q2BSPFile::from_buffer(buf)?)),
+            _ => Ok(AnyBSPFile::Quake3(BSPFile::from_buffer(buf)?)),
+        }
+    }
+}
 
 /// Represents a parsed BSP file.
 #[derive(Debug, Clone)]
@@ -55,73 +135,67 @@ pub struct BSPFile {
 impl BSPFile {
     /// Try to parse the given buffer as a BSP file
     pub fn from_buffer(buf: Box<[u8]>) -> Result<BSPFile> {
-        let header = Header::from(&buf)?;
-
-        match header.version {
-            // Quake 3 or Quake LIVE (IBSP47)
-            0x2e | 0x2f => {
-                let entities = EntitiesLump::from_lump(header.get_lump(&buf, 0))?;
-                let textures = TexturesLump::from_lump(header.get_lump(&buf, 1))?;
-                let planes = PlanesLump::from_lump(header.get_lump(&buf, 2))?;
-                let vertices = VerticesLump::from_lump(header.get_lump(&buf, 10))?;
-                let meshverts = MeshVertsLump::from_lump(header.get_lump(&buf, 11))?;
-                let light_maps = LightMapsLump::from_lump(header.get_lump(&buf, 14))?;
-                let light_vols = LightVolsLump::from_lump(header.get_lump(&buf, 15))?;
-                let visdata = VisDataLump::from_lump(header.get_lump(&buf, 16))?;
-                let brushes = BrushesLump::from_lump(
-                    header.get_lump(&buf, 8),
-                    header.get_lump(&buf, 9),
-                    &textures,
-                    &planes
-                )?;
-                let effects = EffectsLump::from_lump(header.get_lump(&buf, 12), &brushes)?;
-                let faces = FaceLump::from_lump(
-                    header.get_lump(&buf, 13),
-                    &textures,
-                    &effects,
-                    &vertices,
-                    &meshverts,
-                    &light_maps,
-                )?;
-                let tree = BSPTree::from_lumps(
-                    header.get_lump(&buf, 3),
-                    header.get_lump(&buf, 4),
-                    header.get_lump(&buf, 5),
-                    header.get_lump(&buf, 6),
-                    &faces,
-                    &brushes,
-                )?;
-
-                let models = ModelsLump::from_lump(header.get_lump(&buf, 7), &faces, &brushes)?;
-
-                // Quake Live has an advertisements lump
-                let advertisements = if header.version == 0x2f {
-                    Some(AdvertisementsLump::from_lump(header.get_lump(&buf, 17))?)
-                } else {
-                    None
-                };
-
-                Ok(BSPFile {
-                    directory: header,
-                    entities,
-                    textures,
-                    planes,
-                    light_vols,
-                    light_maps,
-                    vertices,
-                    meshverts,
-                    visdata,
-                    advertisements,
-                    brushes,
-                    effects,
-                    faces,
-                    tree,
-                    models
-                })
+        BSPFileRef::from_buffer(&buf)?.parse()
+    }
+
+    /// Read & parse the file at the given path.
+    /// The whole file is read into memory first. See `MappedBSPFile` for mapping it instead.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<BSPFile> {
+        LoadOptions::default().open(path)
+    }
+
+    /// Parse a file from anything that can seek, reading each lump from its offset.
+    /// Use `BSPReader` directly to only read some of the lumps.
+    pub fn from_reader<R: Read + Seek>(reader: R) -> Result<BSPFile> {
+        BSPReader::new(reader)?.parse()
+    }
+
+    /// Serialise to a buffer that can be read by `from_buffer` or the engine.
+    /// The directory is rebuilt, so only the version is taken from `self.directory`.
+    /// # Errors
+    /// Fails if the file couldn't be read back, which is the case for files loaded without the tree or lightmaps
+    /// (see `LoadOptions`): the nodes lump would be empty, or faces would refer to lightmaps that aren't there.
+    pub fn to_buffer(&self) -> Result<Box<[u8]>> {
+        let (brushes, brush_sides) = self.brushes.to_lumps();
+        let tree = self.tree.to_lumps();
+        if tree.nodes.is_empty() {
+            return Err(invalid_error!("Can't write a file with no tree, as it was loaded without one"));
+        }
+
+        for (i, face) in self.faces.faces.iter().enumerate() {
+            match face.lightmap_idx {
+                Some(idx) if idx >= self.light_maps.maps.len() => {
+                    let at = Location::record("Faces", i, (i * faces::FACE_SIZE) + 28);
+                    return Err(reference_error!(at, "lightmap", "LightMap", idx));
+                }
+                _ => {}
             }
-            _ => Err(Error::Unsupported {
-                version: header.version,
-            }),
         }
+
+        let mut lumps = vec![
+            self.entities.to_lump(),
+            self.textures.to_lump(),
+            self.planes.to_lump(),
+            tree.nodes,
+            tree.leaves,
+            tree.leaf_faces,
+            tree.leaf_brushes,
+            self.models.to_lump(),
+            brushes,
+            brush_sides,
+            self.vertices.to_lump(),
+            self.meshverts.to_lump(),
+            self.effects.to_lump(),
+            self.faces.to_lump(),
+            self.light_maps.to_lump(),
+            self.light_vols.to_lump(),
+            self.visdata.to_lump(),
+        ];
+
+        if let Some(advertisements) = &self.advertisements {
+            lumps.push(advertisements.to_lump());
+        }
+
+        Ok(assemble(MAGIC_HEADER, self.directory.version, &lumps))
     }
 }
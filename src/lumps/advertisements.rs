@@ -15,14 +15,14 @@
 // You should have received a copy of the GNU General Public License
 // along with stockton-bsp.  If not, see <http://www.gnu.org/licenses/>.
 
-use super::helpers::{slice_to_u32, slice_to_vec3};
+use super::helpers::{push_u32, push_vec3, slice_to_u32, slice_to_vec3};
 use crate::types::Result;
 use na::Vector3;
 use std::fmt;
 
 const ADVERTISEMENT_SIZE: usize = 4 + (4 * 3) + (4 * 3 * 4) + 64;
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq)]
 pub struct Advertisement {
     pub cell_id: u32,
     pub normal: Vector3<f32>,
@@ -32,7 +32,7 @@ pub struct Advertisement {
     pub model: [u8; 64],
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct AdvertisementsLump {
     pub advertisements: Box<[Advertisement]>,
 }
@@ -40,7 +40,7 @@ pub struct AdvertisementsLump {
 impl AdvertisementsLump {
     pub fn from_lump(buf: &[u8]) -> Result<AdvertisementsLump> {
         if buf.len() % ADVERTISEMENT_SIZE != 0 {
-            return Err(invalid_error!("AdvertisementsLump is incorrectly sized"));
+            return Err(size_error!("Advertisements", buf.len(), ADVERTISEMENT_SIZE));
         }
         let n_ads = buf.len() / ADVERTISEMENT_SIZE;
 
@@ -50,7 +50,7 @@ impl AdvertisementsLump {
 
             // try_into() doesn't work because the array is too big
             let mut model = [0; 64];
-            (&mut model).clone_from_slice(&raw[64..128]);
+            model.clone_from_slice(&raw[64..128]);
 
             advertisements.push(Advertisement {
                 cell_id: slice_to_u32(&raw[0..4]),
@@ -69,6 +69,20 @@ impl AdvertisementsLump {
             advertisements: advertisements.into_boxed_slice(),
         })
     }
+
+    pub fn to_lump(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(self.advertisements.len() * ADVERTISEMENT_SIZE);
+        for ad in self.advertisements.iter() {
+            push_u32(&mut buf, ad.cell_id);
+            push_vec3(&mut buf, &ad.normal);
+            for corner in ad.rect.iter() {
+                push_vec3(&mut buf, corner);
+            }
+            buf.extend_from_slice(&ad.model);
+        }
+
+        buf
+    }
 }
 
 
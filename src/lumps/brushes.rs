@@ -18,19 +18,19 @@
 //! Parses the brushes & brushsides lumps from a bsp file
 
 /// The size of one brush record.
-const BRUSH_SIZE: usize = (4 * 3);
+const BRUSH_SIZE: usize = 4 * 3;
 
 /// The size of one brushsize record
-const SIDE_SIZE: usize = (4 * 2);
+const SIDE_SIZE: usize = 4 * 2;
 
-use crate::lumps::helpers::slice_to_i32;
+use crate::lumps::helpers::{push_i32, slice_to_i32};
 use crate::lumps::planes::PlanesLump;
 use crate::lumps::textures::TexturesLump;
-use crate::types::Result;
+use crate::types::{Location, Result};
 
 /// A brushes lump from a bsp file.
 /// BrushSides are also stored inside here.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct BrushesLump {
     pub brushes: Box<[Brush]>,
 }
@@ -59,29 +59,47 @@ impl BrushesLump {
         textures_lump: &TexturesLump,
         planes_lump: &PlanesLump
     ) -> Result<BrushesLump> {
-        if brushes_lump.len() % BRUSH_SIZE != 0 || brush_sides_lump.len() % SIDE_SIZE != 0 {
-            return Err(invalid_error!("BrushesLump is incorrectly sized"));
+        BrushesLump::from_sized_lump(brushes_lump, brush_sides_lump, SIDE_SIZE, textures_lump, planes_lump)
+    }
+
+    /// Parse the brushes & brushsides lump, where each brushside is `side_size` bytes long.
+    /// Used for formats which add extra fields to the end of each brushside, such as RBSP.
+    pub(crate) fn from_sized_lump(
+        brushes_lump: &[u8],
+        brush_sides_lump: &[u8],
+        side_size: usize,
+        textures_lump: &TexturesLump,
+        planes_lump: &PlanesLump
+    ) -> Result<BrushesLump> {
+        if brushes_lump.len() % BRUSH_SIZE != 0 {
+            return Err(size_error!("Brushes", brushes_lump.len(), BRUSH_SIZE));
+        }
+        if brush_sides_lump.len() % side_size != 0 {
+            return Err(size_error!("BrushSides", brush_sides_lump.len(), side_size));
         }
         let length = brushes_lump.len() / BRUSH_SIZE;
 
-        let mut brushes = Vec::with_capacity(length as usize);
+        let mut brushes = Vec::with_capacity(length);
         for n in 0..length {
             let offset = n * BRUSH_SIZE;
             let brush = &brushes_lump[offset..offset + BRUSH_SIZE];
 
             let texture_idx = slice_to_i32(&brush[8..12]) as usize;
             if texture_idx >= textures_lump.textures.len() {
-                return Err(invalid_error!("Brushes references a texture that doesn't exist"));
+                let at = Location::record("Brushes", n, offset + 8);
+                return Err(reference_error!(at, "texture", "Texture", texture_idx));
             }
 
             brushes.push(Brush {
                 sides: BrushesLump::get_sides(
                     brush_sides_lump,
+                    side_size,
                     slice_to_i32(&brush[0..4]),
                     slice_to_i32(&brush[4..8]),
                     textures_lump,
                     planes_lump,
-                )?,
+                )
+                .map_err(|e| e.at_record(n, offset))?,
                 texture_idx
             });
         }
@@ -91,39 +109,71 @@ impl BrushesLump {
         })
     }
 
+    /// Serialise back into a brushes lump and a brushsides lump, in that order.
+    /// Each brush's sides are written contiguously.
+    pub fn to_lumps(&self) -> (Vec<u8>, Vec<u8>) {
+        let mut brushes = Vec::with_capacity(self.brushes.len() * BRUSH_SIZE);
+        let mut sides = Vec::new();
+
+        let mut n_sides = 0;
+        for brush in self.brushes.iter() {
+            push_i32(&mut brushes, n_sides as i32);
+            push_i32(&mut brushes, brush.sides.len() as i32);
+            push_i32(&mut brushes, brush.texture_idx as i32);
+
+            for side in brush.sides.iter() {
+                push_i32(&mut sides, side.plane_idx as i32);
+                push_i32(&mut sides, side.texture_idx as i32);
+            }
+            n_sides += brush.sides.len();
+        }
+
+        (brushes, sides)
+    }
+
     /// Internal function to get the relevant brushsides for a brush from the data in the brush lump.
     fn get_sides(
         brush_sides_lump: &[u8],
+        side_size: usize,
         start: i32,
         length: i32,
         textures_lump: &TexturesLump,
         planes_lump: &PlanesLump,
     ) -> Result<Box<[BrushSide]>> {
-        let mut sides = Vec::with_capacity(length as usize);
-
-        if length > 0 {
-            for n in start..start + length {
-                let offset = n as usize * SIDE_SIZE;
-                let brush = &brush_sides_lump[offset..offset + SIDE_SIZE];
+        if length <= 0 {
+            return Ok(Box::new([]));
+        }
 
-                let plane_idx = slice_to_i32(&brush[0..4]) as usize;
-                if plane_idx / 2 >= planes_lump.planes.len() {
-                    return Err(invalid_error!("BrushSide references a plane that doesn't exist"));
-                }
+        let n_sides = brush_sides_lump.len() / side_size;
+        if start < 0 || start as usize + length as usize > n_sides {
+            let end = i64::from(start) + i64::from(length);
+            return Err(reference_error!(Location::field("Brushes", 0), "sides", "BrushSide", end));
+        }
 
-                let is_opposing = plane_idx % 2 != 0;
+        let mut sides = Vec::with_capacity(length as usize);
+        for n in start..start + length {
+            let offset = n as usize * side_size;
+            let brush = &brush_sides_lump[offset..offset + side_size];
+
+            let plane_idx = slice_to_i32(&brush[0..4]) as usize;
+            if plane_idx / 2 >= planes_lump.planes.len() {
+                let at = Location::record("BrushSides", n as usize, offset);
+                return Err(reference_error!(at, "plane", "Plane", plane_idx / 2));
+            }
 
-                let texture_idx = slice_to_i32(&brush[4..8]) as usize;
-                if texture_idx >= textures_lump.textures.len() {
-                    return Err(invalid_error!("BrushSide references a texture that doesn't exist"));
-                }
+            let is_opposing = plane_idx % 2 != 0;
 
-                sides.push(BrushSide {
-                    plane_idx,
-                    texture_idx,
-                    is_opposing
-                });
+            let texture_idx = slice_to_i32(&brush[4..8]) as usize;
+            if texture_idx >= textures_lump.textures.len() {
+                let at = Location::record("BrushSides", n as usize, offset + 4);
+                return Err(reference_error!(at, "texture", "Texture", texture_idx));
             }
+
+            sides.push(BrushSide {
+                plane_idx,
+                texture_idx,
+                is_opposing
+            });
         }
 
         Ok(sides.into_boxed_slice())
@@ -0,0 +1,211 @@
+// Copyright (C) 2019 Oscar Shrimpton
+//
+// This file is part of stockton-bsp.
+//
+// stockton-bsp is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// stockton-bsp is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with stockton-bsp.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Edge-based faces, as used by Quake 1, Quake 2 & Source.
+//! Faces are polygons made of a list of surface edges, each of which refers to an edge between two vertices.
+
+use super::helpers::{slice_to_i16, slice_to_i32, slice_to_u16};
+use super::planes::PlanesLump;
+use super::vertices::PositionsLump;
+use crate::types::{Location, Result};
+use std::ops::Range;
+
+const EDGE_SIZE: usize = 2 * 2;
+const EDGE_FACE_SIZE: usize = 2 + 2 + 4 + 2 + 2 + 4 + 4;
+
+/// A list of edges, each a pair of vertex indices.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EdgesLump {
+    pub edges: Box<[[u16; 2]]>,
+}
+
+impl EdgesLump {
+    pub fn from_lump(lump: &[u8], positions: &PositionsLump) -> Result<EdgesLump> {
+        if lump.len() % EDGE_SIZE != 0 {
+            return Err(size_error!("Edges", lump.len(), EDGE_SIZE));
+        }
+
+        let mut edges = Vec::with_capacity(lump.len() / EDGE_SIZE);
+        for (n, raw) in lump.chunks_exact(EDGE_SIZE).enumerate() {
+            let edge = [slice_to_u16(&raw[0..2]), slice_to_u16(&raw[2..4])];
+            if let Some(v) = edge.iter().find(|v| **v as usize >= positions.positions.len()) {
+                let at = Location::record("Edges", n, n * EDGE_SIZE);
+                return Err(reference_error!(at, "vertex", "Vertex", *v));
+            }
+
+            edges.push(edge);
+        }
+
+        Ok(EdgesLump {
+            edges: edges.into_boxed_slice(),
+        })
+    }
+}
+
+/// A list of references to edges.
+/// Negative values refer to the edge with that index, but going from its second vertex to its first.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SurfEdgesLump {
+    pub surf_edges: Box<[i32]>,
+}
+
+impl SurfEdgesLump {
+    pub fn from_lump(lump: &[u8], edges: &EdgesLump) -> Result<SurfEdgesLump> {
+        if lump.len() % 4 != 0 {
+            return Err(size_error!("SurfEdges", lump.len(), 4));
+        }
+
+        let mut surf_edges = Vec::with_capacity(lump.len() / 4);
+        for (n, raw) in lump.chunks_exact(4).enumerate() {
+            let surf_edge = slice_to_i32(raw);
+            if surf_edge.unsigned_abs() as usize >= edges.edges.len() {
+                let at = Location::record("SurfEdges", n, n * 4);
+                return Err(reference_error!(at, "edge", "Edge", surf_edge));
+            }
+
+            surf_edges.push(surf_edge);
+        }
+
+        Ok(SurfEdgesLump {
+            surf_edges: surf_edges.into_boxed_slice(),
+        })
+    }
+
+    /// The index of the vertex this surface edge starts at.
+    /// # Panics
+    /// If `i` or the edge it refers to is out of bounds.
+    pub fn start_vertex(&self, i: usize, edges: &EdgesLump) -> usize {
+        let surf_edge = self.surf_edges[i];
+        if surf_edge >= 0 {
+            edges.edges[surf_edge as usize][0] as usize
+        } else {
+            edges.edges[surf_edge.unsigned_abs() as usize][1] as usize
+        }
+    }
+
+    /// The indices of the vertices the given range of surface edges start at, in order.
+    /// # Panics
+    /// If the range or the edges it refers to are out of bounds.
+    pub fn vertex_indices<'a>(&'a self, range: Range<usize>, edges: &'a EdgesLump) -> impl Iterator<Item = usize> + 'a {
+        range.map(move |i| self.start_vertex(i, edges))
+    }
+}
+
+/// A face made from edges.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EdgeFace {
+    pub plane_idx: usize,
+
+    /// True if the face is on the back side of its plane.
+    pub back: bool,
+
+    /// Indices into the surface edges lump.
+    pub surf_edges_idx: Range<usize>,
+
+    pub texinfo_idx: usize,
+
+    /// Light styles, with 255 meaning unused.
+    pub styles: [u8; 4],
+
+    /// Byte offset into the lighting lump, or `None` if the face isn't lit.
+    pub light_offset: Option<usize>,
+}
+
+impl EdgeFace {
+    /// The vertex indices of this face's polygon, in order.
+    /// # Panics
+    /// If the face wasn't validated against the given lumps.
+    pub fn vertex_indices<'a>(
+        &self,
+        surf_edges: &'a SurfEdgesLump,
+        edges: &'a EdgesLump,
+    ) -> impl Iterator<Item = usize> + 'a {
+        surf_edges.vertex_indices(self.surf_edges_idx.clone(), edges)
+    }
+}
+
+/// A list of edge-based faces, as used by Quake 1 & Quake 2.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EdgeFacesLump {
+    pub faces: Box<[EdgeFace]>,
+}
+
+impl EdgeFacesLump {
+    /// Parse the faces lump.
+    /// # Format
+    /// ushort planenum
+    /// short side
+    /// int firstedge
+    /// short numedges
+    /// short texinfo
+    /// byte styles[4]
+    /// int lightofs
+    pub fn from_lump(
+        lump: &[u8],
+        planes: &PlanesLump,
+        surf_edges: &SurfEdgesLump,
+        n_texinfo: usize,
+    ) -> Result<EdgeFacesLump> {
+        if lump.len() % EDGE_FACE_SIZE != 0 {
+            return Err(size_error!("Faces", lump.len(), EDGE_FACE_SIZE));
+        }
+
+        let mut faces = Vec::with_capacity(lump.len() / EDGE_FACE_SIZE);
+        for (n, raw) in lump.chunks_exact(EDGE_FACE_SIZE).enumerate() {
+            let at = |offset| Location::record("Faces", n, n * EDGE_FACE_SIZE + offset);
+
+            let plane_idx = slice_to_u16(&raw[0..2]) as usize;
+            if plane_idx >= planes.planes.len() {
+                return Err(reference_error!(at(0), "plane", "Plane", plane_idx));
+            }
+
+            let surf_edges_idx = {
+                let start = slice_to_i32(&raw[4..8]);
+                let n = slice_to_i16(&raw[8..10]);
+                if start < 0 || n < 0 || start as usize + n as usize > surf_edges.surf_edges.len() {
+                    return Err(reference_error!(at(4), "surf_edges", "SurfEdge", start as i64 + n as i64));
+                }
+
+                start as usize..start as usize + n as usize
+            };
+
+            let texinfo_idx = slice_to_i16(&raw[10..12]);
+            if texinfo_idx < 0 || texinfo_idx as usize >= n_texinfo {
+                return Err(reference_error!(at(10), "texinfo", "TexInfo", texinfo_idx));
+            }
+
+            let light_offset = slice_to_i32(&raw[16..20]);
+
+            faces.push(EdgeFace {
+                plane_idx,
+                back: slice_to_i16(&raw[2..4]) != 0,
+                surf_edges_idx,
+                texinfo_idx: texinfo_idx as usize,
+                styles: [raw[12], raw[13], raw[14], raw[15]],
+                light_offset: if light_offset < 0 {
+                    None
+                } else {
+                    Some(light_offset as usize)
+                },
+            });
+        }
+
+        Ok(EdgeFacesLump {
+            faces: faces.into_boxed_slice(),
+        })
+    }
+}
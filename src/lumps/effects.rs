@@ -18,8 +18,8 @@
 use std::str;
 
 use super::brushes::BrushesLump;
-use super::helpers::slice_to_i32;
-use crate::types::Result;
+use super::helpers::{push_fixed_str, push_i32, push_opt_idx, slice_to_i32};
+use crate::types::{Location, Result};
 
 /// The size of one effect definition
 const EFFECT_SIZE: usize = 64 + 4 + 4;
@@ -31,14 +31,16 @@ pub struct Effect {
     pub name: String,
 
     /// The brush used for this effect
-    pub brush_idx: usize
+    pub brush_idx: usize,
 
-    // todo: unknown: i32
+    /// The side of the brush that is visible, if any.
+    /// Added in 4.0, so code building an `Effect` by hand from 3.x needs to set it.
+    pub visible_side: Option<usize>,
 }
 
 /// Lump containing all effects
 /// Found at index 12 in a q3 bsp
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct EffectsLump {
     pub effects: Box<[Effect]>,
 }
@@ -47,7 +49,7 @@ impl EffectsLump {
     /// Parses the given lump and links the brush references to the given `BrushesLump`
     pub fn from_lump(lump: &[u8], brushes: &BrushesLump) -> Result<EffectsLump> {
         if lump.len() % EFFECT_SIZE != 0 {
-            return Err(invalid_error!("EffectsLump is incorrectly sized"));
+            return Err(size_error!("Effects", lump.len(), EFFECT_SIZE));
         }
         let length = lump.len() / EFFECT_SIZE;
 
@@ -57,12 +59,21 @@ impl EffectsLump {
 
             let brush_idx = slice_to_i32(&raw[64..68]) as usize;
             if brush_idx >= brushes.brushes.len() {
-                return Err(invalid_error!("Effect references brush that doesn't exist"));
+                let at = Location::record("Effects", n, n * EFFECT_SIZE + 64);
+                return Err(reference_error!(at, "brush", "Brush", brush_idx));
             }
 
+            let visible_side = slice_to_i32(&raw[68..72]);
+            let visible_side = if visible_side >= 0 {
+                Some(visible_side as usize)
+            } else {
+                None
+            };
+
             effects.push(Effect {
                 name: str::from_utf8(&raw[..64])?.to_owned(),
-                brush_idx
+                brush_idx,
+                visible_side
             });
         }
 
@@ -71,6 +82,18 @@ impl EffectsLump {
         })
     }
 
+    /// Serialise back into an effects lump.
+    pub fn to_lump(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(self.effects.len() * EFFECT_SIZE);
+        for effect in self.effects.iter() {
+            push_fixed_str(&mut buf, &effect.name, 64);
+            push_i32(&mut buf, effect.brush_idx as i32);
+            push_opt_idx(&mut buf, effect.visible_side);
+        }
+
+        buf
+    }
+
     pub fn empty() -> EffectsLump {
         EffectsLump {
             effects: vec![].into_boxed_slice(),
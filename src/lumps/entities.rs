@@ -16,98 +16,394 @@
 // along with stockton-bsp.  If not, see <http://www.gnu.org/licenses/>.
 
 const QUOTE: u8 = b'"';
+const BACKSLASH: u8 = b'\\';
 const END_BRACKET: u8 = b'}';
 const START_BRACKET: u8 = b'{';
 
 use std::collections::HashMap;
+use std::ops::Range;
 use std::str;
 
-use crate::types::Result;
+use super::models::ModelsLump;
+use crate::types::{Location, Result};
+use na::Vector3;
 
 #[derive(Debug, Clone)]
 /// Game-related map information
 pub struct EntitiesLump {
     /// The extracted entity data
     pub entities: Vec<Entity>,
+
+    /// The lump this was parsed from, so it can be written back exactly if nothing's changed.
+    source: Option<Box<[u8]>>,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone)]
 /// A game entity
 pub struct Entity {
-    pub attributes: HashMap<String, String>,
-}
+    /// Each key's value. If a key is given more than once, the last value wins, as in the Q3 engine.
+    attributes: HashMap<String, String>,
+
+    /// Every key & value in the order they appear, including duplicates. This is what gets serialised.
+    pairs: Vec<(String, String)>,
 
-/// Internal enum to parse through the entities string.
-#[derive(PartialEq, Eq)]
-enum ParseState {
-    InKey,
-    InValue,
-    AfterKey,
-    InsideEntity,
-    OutsideEntity,
+    /// Which entity in the lump this was parsed from, and the offset of its opening bracket. Used in errors.
+    at: Option<Location>,
+
+    /// The bytes of the lump this was parsed from, brackets included, so it can be written back as it was.
+    span: Option<Range<usize>>,
 }
 
 impl EntitiesLump {
+    /// Create a lump from the given entities, with nothing to preserve from a source file.
+    pub fn new(entities: Vec<Entity>) -> EntitiesLump {
+        EntitiesLump {
+            entities,
+            source: None,
+        }
+    }
+
     /// Parse the given lump as an Entities Lump.
+    /// # Format
+    /// Entities are sets of quoted key & value pairs between brackets.
+    /// Inside quotes, `\"` and `\\` are unescaped, and any other backslash is left as it is.
+    /// Outside quotes, `//` comments out the rest of the line, and anything else that isn't a bracket is ignored.
+    /// Strings that aren't valid UTF-8 are read as Latin-1, which is what most Q3-era tools wrote.
     pub fn from_lump(lump: &[u8]) -> Result<EntitiesLump> {
-        use self::ParseState::*;
-
-        let string = str::from_utf8(lump).unwrap();
-
-        let mut attrs = HashMap::new();
         let mut entities = Vec::new();
+        let mut pairs = None;
+        let mut key = None;
+        let mut start = 0;
 
-        let mut state = ParseState::OutsideEntity;
+        let mut i = 0;
+        while i < lump.len() {
+            match lump[i] {
+                QUOTE => {
+                    let (string, end) = read_quoted(lump, i + 1)?;
+                    i = end;
 
-        let mut key_start = 0;
-        let mut key_end = 0;
-        let mut val_start = 0;
-        let mut val_end;
-
-        for (i, chr) in string.bytes().enumerate() {
-            match chr {
-                QUOTE => match state {
-                    InsideEntity => {
-                        state = ParseState::InKey;
-                        key_start = i + 1;
-                    }
-                    InKey => {
-                        state = ParseState::AfterKey;
-                        key_end = i;
-                    }
-                    AfterKey => {
-                        state = ParseState::InValue;
-                        val_start = i + 1;
-                    }
-                    InValue => {
-                        state = ParseState::InsideEntity;
-                        val_end = i;
+                    let pairs: &mut Vec<(String, String)> = match pairs {
+                        Some(ref mut pairs) => pairs,
+                        None => return Err(invalid_error!("Entity definition is malformed")),
+                    };
 
-                        attrs.insert(string[key_start..key_end].to_owned(), string[val_start..val_end].to_owned());
+                    match key.take() {
+                        Some(key) => pairs.push((key, string)),
+                        None => key = Some(string),
                     }
-                    _ => {
+                }
+                START_BRACKET => {
+                    if pairs.is_some() {
                         return Err(invalid_error!("Entity definition is malformed"));
                     }
+                    pairs = Some(Vec::new());
+                    start = i;
+                }
+                END_BRACKET => match pairs.take() {
+                    Some(pairs) if key.is_none() => {
+                        let mut entity = Entity::from_pairs(pairs);
+                        entity.at = Some(Location::record("Entities", entities.len(), start));
+                        entity.span = Some(start..i + 1);
+                        entities.push(entity);
+                    }
+                    _ => return Err(invalid_error!("Entity definition is malformed")),
                 },
-                END_BRACKET => {
-                    if state != InsideEntity {
-                        return Err(invalid_error!("Entity definition is malformed"));
+                b'/' if lump.get(i + 1) == Some(&b'/') => {
+                    while i < lump.len() && lump[i] != b'\n' {
+                        i += 1;
                     }
+                }
+                _ => {}
+            }
 
-                    state = OutsideEntity;
+            i += 1;
+        }
 
-                    entities.push(Entity { attributes: attrs });
-                    attrs = HashMap::new();
-                }
-                START_BRACKET => {
-                    if state != OutsideEntity {
-                        return Err(invalid_error!("Entity definition is malformed"));
-                    }
-                    state = InsideEntity;
+        if pairs.is_some() {
+            return Err(invalid_error!("Entity definition is malformed"));
+        }
+
+        Ok(EntitiesLump {
+            entities,
+            source: Some(lump.into()),
+        })
+    }
+
+    /// Serialise the entities back into a lump.
+    /// If the entities are the same as when they were parsed, the original lump is returned byte-for-byte.
+    /// Otherwise keys are written in order, one per line, terminated by a NUL byte as in q3map2 output.
+    /// Entities that haven't changed are still copied from the original lump, so strings read as Latin-1 stay Latin-1.
+    pub fn to_lump(&self) -> Vec<u8> {
+        if let Some(ref source) = self.source {
+            if let Ok(parsed) = EntitiesLump::from_lump(source) {
+                if parsed.entities == self.entities {
+                    return source.to_vec();
                 }
-                _ => {}
             }
         }
-        Ok(EntitiesLump { entities })
+
+        let mut buf = Vec::new();
+
+        for entity in &self.entities {
+            if let Some(original) = self.original_bytes(entity) {
+                buf.extend_from_slice(original);
+                buf.push(b'\n');
+                continue;
+            }
+
+            buf.extend_from_slice(b"{\n");
+            for (key, value) in entity.pairs.iter() {
+                push_quoted(&mut buf, key);
+                buf.push(b' ');
+                push_quoted(&mut buf, value);
+                buf.push(b'\n');
+            }
+            buf.extend_from_slice(b"}\n");
+        }
+        buf.push(0);
+
+        buf
+    }
+
+    /// Internal function. The bytes the entity was parsed from, if they're in this lump's source & it hasn't changed.
+    fn original_bytes(&self, entity: &Entity) -> Option<&[u8]> {
+        let original = self.source.as_ref()?.get(entity.span.clone()?)?;
+        match EntitiesLump::from_lump(original) {
+            Ok(ref parsed) if parsed.entities.len() == 1 && parsed.entities[0] == *entity => Some(original),
+            _ => None,
+        }
+    }
+
+    /// The first entity with a `classname` of `worldspawn`, which holds settings for the whole map.
+    pub fn worldspawn(&self) -> Option<&Entity> {
+        self.entities.iter().find(|e| e.classname() == Some("worldspawn"))
+    }
+}
+
+impl PartialEq for EntitiesLump {
+    /// Lumps are equal if their entities are, regardless of how they were formatted.
+    fn eq(&self, other: &EntitiesLump) -> bool {
+        self.entities == other.entities
+    }
+}
+
+impl Entity {
+    /// Create an entity from the given keys & values, in order.
+    pub fn from_pairs(pairs: Vec<(String, String)>) -> Entity {
+        Entity {
+            attributes: pairs.iter().cloned().collect(),
+            pairs,
+            at: None,
+            span: None,
+        }
+    }
+
+    /// Where this entity was parsed from, or just the entities lump if it was made with `from_pairs`.
+    pub fn location(&self) -> Location {
+        self.at.unwrap_or_else(|| Location::lump("Entities"))
+    }
+
+    /// Every key & value in the order they appear, including duplicates.
+    pub fn pairs(&self) -> &[(String, String)] {
+        &self.pairs
+    }
+
+    /// Each key's value. If a key is given more than once, the last value wins, as in the Q3 engine.
+    /// Use `insert`, `push` & `remove` to edit these.
+    pub fn attributes(&self) -> &HashMap<String, String> {
+        &self.attributes
+    }
+
+    /// Every value given for the key, in order.
+    pub fn get_all<'a>(&'a self, key: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.pairs
+            .iter()
+            .filter(move |(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Add a key & value to the end of the entity, even if the key is already there.
+    pub fn push(&mut self, key: String, value: String) {
+        self.attributes.insert(key.clone(), value.clone());
+        self.pairs.push((key, value));
+    }
+
+    /// Set the value of a key, replacing the first occurrence and removing any others.
+    /// If the key isn't there, it's added to the end.
+    pub fn insert(&mut self, key: String, value: String) {
+        match self.pairs.iter().position(|(k, _)| *k == key) {
+            Some(first) => {
+                self.pairs[first].1 = value.clone();
+
+                let mut n = 0;
+                self.pairs.retain(|(k, _)| {
+                    n += 1;
+                    n - 1 == first || *k != key
+                });
+            }
+            None => self.pairs.push((key.clone(), value.clone())),
+        }
+
+        self.attributes.insert(key, value);
+    }
+
+    /// Remove every occurrence of a key.
+    pub fn remove(&mut self, key: &str) {
+        self.pairs.retain(|(k, _)| k != key);
+        self.attributes.remove(key);
+    }
+}
+
+impl PartialEq for Entity {
+    /// Entities are equal if their keys & values are, regardless of where they were parsed from.
+    fn eq(&self, other: &Entity) -> bool {
+        self.pairs == other.pairs
+    }
+}
+
+/// Typed accessors for common keys.
+/// Missing keys give `Ok(None)`, and keys that are present but malformed give `Error::BadValue` naming the key,
+/// at the entity's `location`.
+impl Entity {
+    /// Get the raw value of the given key.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.attributes.get(key).map(String::as_str)
+    }
+
+    pub fn classname(&self) -> Option<&str> {
+        self.get("classname")
+    }
+
+    pub fn target(&self) -> Option<&str> {
+        self.get("target")
+    }
+
+    pub fn targetname(&self) -> Option<&str> {
+        self.get("targetname")
+    }
+
+    /// The raw `model` key. This is either `*N` for a brush model in the file, or the path of a model asset.
+    pub fn model(&self) -> Option<&str> {
+        self.get("model")
+    }
+
+    pub fn origin(&self) -> Result<Option<Vector3<f32>>> {
+        self.get_vec3("origin")
+    }
+
+    /// Pitch, yaw & roll in degrees.
+    /// Read from `angles` if it's set, otherwise from the yaw-only `angle` key.
+    pub fn angles(&self) -> Result<Option<Vector3<f32>>> {
+        if let Some(angles) = self.get_vec3("angles")? {
+            return Ok(Some(angles));
+        }
+
+        Ok(self.get_f32("angle")?.map(|yaw| Vector3::new(0.0, yaw, 0.0)))
+    }
+
+    pub fn spawnflags(&self) -> Result<Option<u32>> {
+        self.get_parsed("spawnflags")
+    }
+
+    /// The index into the `ModelsLump` of this entity's brush model, if it has one.
+    /// Models that point to an asset instead give `Ok(None)`.
+    pub fn model_index(&self, models: &ModelsLump) -> Result<Option<usize>> {
+        let index = match self.model() {
+            Some(model) if model.starts_with('*') => &model[1..],
+            _ => return Ok(None),
+        };
+
+        let index: usize = index
+            .parse()
+            .map_err(|_| value_error!(self.location(), "model", self.model().unwrap()))?;
+
+        if index >= models.models.len() {
+            return Err(reference_error!(self.location(), "model", "Model", index));
+        }
+
+        Ok(Some(index))
+    }
+
+    /// Parse the given key as a single number.
+    pub fn get_f32(&self, key: &'static str) -> Result<Option<f32>> {
+        self.get_parsed(key)
+    }
+
+    /// Parse the given key as 3 numbers separated by whitespace.
+    pub fn get_vec3(&self, key: &'static str) -> Result<Option<Vector3<f32>>> {
+        let value = match self.get(key) {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+
+        let parts = value
+            .split_whitespace()
+            .map(str::parse)
+            .collect::<std::result::Result<Vec<f32>, _>>()
+            .map_err(|_| value_error!(self.location(), key, value))?;
+
+        if parts.len() != 3 {
+            return Err(value_error!(self.location(), key, value));
+        }
+
+        Ok(Some(Vector3::new(parts[0], parts[1], parts[2])))
+    }
+
+    /// Internal function. Parses the given key with `FromStr`.
+    fn get_parsed<T: str::FromStr>(&self, key: &'static str) -> Result<Option<T>> {
+        match self.get(key) {
+            Some(value) => value
+                .trim()
+                .parse()
+                .map(Some)
+                .map_err(|_| value_error!(self.location(), key, value)),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Internal function. Reads a quoted string starting after the opening quote at `start`.
+/// Returns the string and the index of the closing quote.
+fn read_quoted(lump: &[u8], start: usize) -> Result<(String, usize)> {
+    let mut bytes = Vec::new();
+
+    let mut i = start;
+    loop {
+        match lump.get(i) {
+            Some(&QUOTE) => break,
+            Some(&BACKSLASH) if lump.get(i + 1) == Some(&QUOTE) || lump.get(i + 1) == Some(&BACKSLASH) => {
+                bytes.push(lump[i + 1]);
+                i += 1;
+            }
+            Some(b) => bytes.push(*b),
+            None => return Err(invalid_error!("Entity string is missing a closing quote")),
+        }
+        i += 1;
     }
-}
\ No newline at end of file
+
+    let string = match String::from_utf8(bytes) {
+        Ok(string) => string,
+        Err(e) => e.into_bytes().into_iter().map(char::from).collect(),
+    };
+
+    Ok((string, i))
+}
+
+/// Internal function. Writes a quoted string, escaping only what `read_quoted` would otherwise misread.
+fn push_quoted(buf: &mut Vec<u8>, string: &str) {
+    buf.push(QUOTE);
+
+    let bytes = string.as_bytes();
+    for (i, b) in bytes.iter().enumerate() {
+        let next = bytes.get(i + 1);
+        match *b {
+            QUOTE => buf.extend_from_slice(b"\\\""),
+            BACKSLASH if next.is_none() || next == Some(&QUOTE) || next == Some(&BACKSLASH) => {
+                buf.extend_from_slice(b"\\\\")
+            }
+            b => buf.push(b),
+        }
+    }
+
+    buf.push(QUOTE);
+}
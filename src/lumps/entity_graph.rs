@@ -0,0 +1,261 @@
+// Copyright (C) 2019 Oscar Shrimpton
+//
+// This file is part of stockton-bsp.
+//
+// stockton-bsp is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// stockton-bsp is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with stockton-bsp.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Links between entities, made by naming each other in their keys.
+
+use std::collections::HashMap;
+
+use super::entities::EntitiesLump;
+
+/// The key an entity used to link to another.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LinkKind {
+    /// `target`, fired when the entity is used.
+    Target,
+
+    /// `target2`, a second set of targets some games fire at different times.
+    Target2,
+
+    /// `killtarget`, removed when the entity is used.
+    KillTarget,
+
+    /// `team`, shared by entities that move together. Links go both ways.
+    Team,
+}
+
+impl LinkKind {
+    /// The kinds that are linked by matching one entity's key against another's `targetname`.
+    const TARGETS: [(LinkKind, &'static str); 3] = [
+        (LinkKind::Target, "target"),
+        (LinkKind::Target2, "target2"),
+        (LinkKind::KillTarget, "killtarget"),
+    ];
+
+    /// If the entity at the other end gets used, rather than removed or moved.
+    pub fn fires(self) -> bool {
+        self == LinkKind::Target || self == LinkKind::Target2
+    }
+}
+
+/// A link from one entity to another, by index into the `EntitiesLump`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Link {
+    pub kind: LinkKind,
+    pub from: usize,
+    pub to: usize,
+}
+
+/// A key naming a target that no entity has as its `targetname`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DanglingTarget {
+    pub kind: LinkKind,
+    pub from: usize,
+    pub name: String,
+}
+
+/// A graph of the links between entities.
+/// Every `target`, `target2` & `killtarget` value is linked to all entities with that `targetname`,
+/// including duplicate keys, and entities sharing a `team` are linked to each other.
+#[derive(Debug, Clone)]
+pub struct EntityGraph {
+    outgoing: Vec<Vec<Link>>,
+    incoming: Vec<Vec<Link>>,
+    dangling: Vec<DanglingTarget>,
+}
+
+impl EntityGraph {
+    /// Build the graph for the given entities.
+    pub fn new(entities: &EntitiesLump) -> EntityGraph {
+        let mut names: HashMap<&str, Vec<usize>> = HashMap::new();
+        let mut teams: HashMap<&str, Vec<usize>> = HashMap::new();
+        for (i, entity) in entities.entities.iter().enumerate() {
+            for name in entity.get_all("targetname") {
+                names.entry(name).or_default().push(i);
+            }
+            if let Some(team) = entity.get("team") {
+                teams.entry(team).or_default().push(i);
+            }
+        }
+
+        let n = entities.entities.len();
+        let mut graph = EntityGraph {
+            outgoing: vec![Vec::new(); n],
+            incoming: vec![Vec::new(); n],
+            dangling: Vec::new(),
+        };
+
+        for (from, entity) in entities.entities.iter().enumerate() {
+            for (kind, key) in LinkKind::TARGETS.iter() {
+                for name in entity.get_all(key) {
+                    match names.get(name) {
+                        Some(targets) => {
+                            for to in targets.iter() {
+                                graph.add(Link { kind: *kind, from, to: *to });
+                            }
+                        }
+                        None => graph.dangling.push(DanglingTarget {
+                            kind: *kind,
+                            from,
+                            name: name.to_owned(),
+                        }),
+                    }
+                }
+            }
+
+            if let Some(members) = entity.get("team").and_then(|team| teams.get(team)) {
+                for to in members.iter().filter(|to| **to != from) {
+                    graph.add(Link {
+                        kind: LinkKind::Team,
+                        from,
+                        to: *to,
+                    });
+                }
+            }
+        }
+
+        graph
+    }
+
+    /// Internal function. Adds a link in both directions.
+    fn add(&mut self, link: Link) {
+        self.outgoing[link.from].push(link);
+        self.incoming[link.to].push(link);
+    }
+
+    /// Every link from the given entity.
+    pub fn links_from(&self, entity: usize) -> &[Link] {
+        self.outgoing.get(entity).map_or(&[], Vec::as_slice)
+    }
+
+    /// Every link to the given entity.
+    pub fn links_to(&self, entity: usize) -> &[Link] {
+        self.incoming.get(entity).map_or(&[], Vec::as_slice)
+    }
+
+    /// The entities used when the given entity fires, through `target` & `target2`.
+    pub fn fires(&self, entity: usize) -> impl Iterator<Item = usize> + '_ {
+        self.links_from(entity)
+            .iter()
+            .filter(|l| l.kind.fires())
+            .map(|l| l.to)
+    }
+
+    /// The entities that fire the given entity.
+    pub fn fired_by(&self, entity: usize) -> impl Iterator<Item = usize> + '_ {
+        self.links_to(entity)
+            .iter()
+            .filter(|l| l.kind.fires())
+            .map(|l| l.from)
+    }
+
+    /// The entities removed when the given entity fires.
+    pub fn kills(&self, entity: usize) -> impl Iterator<Item = usize> + '_ {
+        self.links_from(entity)
+            .iter()
+            .filter(|l| l.kind == LinkKind::KillTarget)
+            .map(|l| l.to)
+    }
+
+    /// The other entities on the given entity's team.
+    pub fn team(&self, entity: usize) -> impl Iterator<Item = usize> + '_ {
+        self.links_from(entity)
+            .iter()
+            .filter(|l| l.kind == LinkKind::Team)
+            .map(|l| l.to)
+    }
+
+    /// Every target key that doesn't match any `targetname`.
+    pub fn dangling(&self) -> &[DanglingTarget] {
+        &self.dangling
+    }
+
+    /// Groups of entities that end up firing themselves, through `target` & `target2`.
+    /// Each group is a strongly connected component, so every entity in it can reach every other.
+    /// Entities that target themselves directly are a group of one.
+    pub fn cycles(&self) -> Vec<Vec<usize>> {
+        // Tarjan's algorithm, with an explicit stack so long chains of relays can't overflow.
+        let n = self.outgoing.len();
+        let mut index = vec![usize::MAX; n];
+        let mut low = vec![0; n];
+        let mut on_stack = vec![false; n];
+        let mut stack = Vec::new();
+        let mut next = 0;
+        let mut cycles = Vec::new();
+
+        for root in 0..n {
+            if index[root] != usize::MAX {
+                continue;
+            }
+
+            // (entity, position in its outgoing links)
+            let mut calls = vec![(root, 0)];
+            index[root] = next;
+            low[root] = next;
+            next += 1;
+            stack.push(root);
+            on_stack[root] = true;
+
+            while let Some(&mut (v, ref mut pos)) = calls.last_mut() {
+                let links = &self.outgoing[v];
+                while *pos < links.len() && !links[*pos].kind.fires() {
+                    *pos += 1;
+                }
+
+                if let Some(link) = links.get(*pos) {
+                    *pos += 1;
+
+                    let w = link.to;
+                    if index[w] == usize::MAX {
+                        index[w] = next;
+                        low[w] = next;
+                        next += 1;
+                        stack.push(w);
+                        on_stack[w] = true;
+                        calls.push((w, 0));
+                    } else if on_stack[w] {
+                        low[v] = low[v].min(index[w]);
+                    }
+                    continue;
+                }
+
+                calls.pop();
+                if let Some(&(parent, _)) = calls.last() {
+                    low[parent] = low[parent].min(low[v]);
+                }
+
+                if low[v] == index[v] {
+                    let mut component = Vec::new();
+                    loop {
+                        let w = stack.pop().unwrap();
+                        on_stack[w] = false;
+                        component.push(w);
+                        if w == v {
+                            break;
+                        }
+                    }
+
+                    if component.len() > 1 || self.fires(v).any(|w| w == v) {
+                        component.sort_unstable();
+                        cycles.push(component);
+                    }
+                }
+            }
+        }
+
+        cycles
+    }
+}
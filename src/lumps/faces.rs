@@ -16,18 +16,21 @@
 // along with stockton-bsp.  If not, see <http://www.gnu.org/licenses/>.
 
 use super::effects::EffectsLump;
-use super::helpers::{slice_to_i32, slice_to_vec2i, slice_to_vec3};
+#[cfg(test)]
+use super::test_util::test_patch;
+use super::helpers::{decode_records, push_i32, push_opt_idx, push_vec2i, push_vec3, slice_to_i32, slice_to_u32, slice_to_vec2i, slice_to_vec3};
 use super::light_maps::LightMapsLump;
 use super::textures::TexturesLump;
-use super::vertices::{MeshVertsLump, VerticesLump};
-use crate::types::Result;
+use super::vertices::{MeshVertsLump, TexCoord, Vertex, VerticesLump};
+use crate::types::{Error, Location, Result, RGBA};
 use na::{Vector2, Vector3};
 
+use std::convert::TryFrom;
 use std::ops::Range;
 
-const FACE_SIZE: usize = (4 * 8) + (4 * 2) + (4 * 2) + (4 * 3) + ((4 * 2) * 3) + (4 * 3) + (4 * 2);
+pub(crate) const FACE_SIZE: usize = (4 * 8) + (4 * 2) + (4 * 2) + (4 * 3) + ((4 * 2) * 3) + (4 * 3) + (4 * 2);
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct FaceLump {
     pub faces: Box<[Face]>,
 }
@@ -41,6 +44,19 @@ pub enum FaceType {
     Billboard = 4,
 }
 
+impl FaceType {
+    /// Interpret a face type from a file.
+    pub fn from_i32(val: i32) -> Option<FaceType> {
+        match val {
+            1 => Some(FaceType::Polygon),
+            2 => Some(FaceType::Patch),
+            3 => Some(FaceType::Mesh),
+            4 => Some(FaceType::Billboard),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct Face {
     pub face_type: FaceType,
@@ -59,6 +75,19 @@ pub struct Face {
     pub size: Vector2<i32>,
 }
 
+/// The output of tessellating a `FaceType::Patch` face.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PatchMesh {
+    /// Interpolated vertices, in row-major order.
+    pub vertices: Vec<Vertex>,
+
+    /// Triangle list indexing into `vertices`, wound the same way as the Q3 renderer.
+    pub indices: Vec<u32>,
+
+    /// Number of vertices in each row & column of `vertices`.
+    pub size: Vector2<usize>,
+}
+
 impl FaceLump {
     pub fn from_lump(
         data: &[u8],
@@ -67,28 +96,42 @@ impl FaceLump {
         vertices_lump: &VerticesLump,
         meshverts_lump: &MeshVertsLump,
         light_maps: &LightMapsLump,
+    ) -> Result<FaceLump> {
+        FaceLump::from_lump_with(data, textures, effects, vertices_lump, meshverts_lump, Some(light_maps))
+    }
+
+    /// Parse the faces lump, only checking lightmap indices if `light_maps` is given.
+    /// Used when the lightmaps weren't loaded.
+    pub(crate) fn from_lump_with(
+        data: &[u8],
+        textures: &TexturesLump,
+        effects: &EffectsLump,
+        vertices_lump: &VerticesLump,
+        meshverts_lump: &MeshVertsLump,
+        light_maps: Option<&LightMapsLump>,
     ) -> Result<FaceLump> {
         if data.len() % FACE_SIZE != 0 {
-            return Err(invalid_error!("FaceLump is incorrectly sized"));
-        }
-        let length = data.len() / FACE_SIZE;
-
-        let mut faces = Vec::with_capacity(length);
-        for n in 0..length {
-            faces.push(Face::from_slice(
-                &data[n * FACE_SIZE..(n + 1) * FACE_SIZE],
-                textures,
-                effects,
-                vertices_lump,
-                meshverts_lump,
-                light_maps,
-            )?);
+            return Err(size_error!("Faces", data.len(), FACE_SIZE));
         }
+        let faces = decode_records(data, FACE_SIZE, |n, raw| {
+            Face::from_slice_with(raw, textures, effects, vertices_lump, meshverts_lump, light_maps)
+                .map_err(|e| e.at_record(n, n * FACE_SIZE))
+        })?;
 
         Ok(FaceLump {
             faces: faces.into_boxed_slice(),
         })
     }
+
+    /// Serialise back into a face lump.
+    pub fn to_lump(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(self.faces.len() * FACE_SIZE);
+        for face in self.faces.iter() {
+            face.push_bytes(&mut buf);
+        }
+
+        buf
+    }
 }
 
 
@@ -100,6 +143,18 @@ impl Face {
         vertices_lump: &VerticesLump,
         meshverts_lump: &MeshVertsLump,
         lightmaps: &LightMapsLump,
+    ) -> Result<Face> {
+        Face::from_slice_with(data, textures, effects, vertices_lump, meshverts_lump, Some(lightmaps))
+    }
+
+    /// Internal function. Parses a face, only checking the lightmap index if `lightmaps` is given.
+    fn from_slice_with(
+        data: &[u8],
+        textures: &TexturesLump,
+        effects: &EffectsLump,
+        vertices_lump: &VerticesLump,
+        meshverts_lump: &MeshVertsLump,
+        lightmaps: Option<&LightMapsLump>,
     ) -> Result<Face> {
         if data.len() != FACE_SIZE {
             panic!("tried to call face.from_slice with invalid slice size");
@@ -108,14 +163,14 @@ impl Face {
         // texture
         let texture_idx = slice_to_i32(&data[0..4]) as usize;
         if texture_idx >= textures.textures.len() {
-            return Err(invalid_error!("Face references Texture that doesn't exist"));
+            return Err(reference_error!(Location::field("Faces", 0), "texture", "Texture", texture_idx));
         }
 
         // effects
         let effect_idx = slice_to_i32(&data[4..8]) as usize;
         let effect_idx = if effect_idx < 0xffffffff {
             if effect_idx >= effects.effects.len() {
-                return Err(invalid_error!("Face references Effect that doesn't exist"));
+                return Err(reference_error!(Location::field("Faces", 4), "effect", "Effect", effect_idx));
             }
 
             Some(effect_idx)
@@ -124,23 +179,37 @@ impl Face {
         };
 
         // face type
-        // TODO
-        let face_type: FaceType = unsafe { ::std::mem::transmute(slice_to_i32(&data[8..12])) };
+        let face_type = slice_to_i32(&data[8..12]);
+        let face_type = FaceType::from_i32(face_type).ok_or_else(|| Error::BadValue {
+            at: Location::field("Faces", 8),
+            field: "type",
+            value: face_type.to_string(),
+        })?;
 
         // vertices
-        let vertex_offset = slice_to_i32(&data[12..16]) as usize;
-        let vertex_n = slice_to_i32(&data[16..20]) as usize;
-        if (vertex_offset + vertex_n) > vertices_lump.vertices.len() {
-            return Err(invalid_error!("Face references Vertex that doesn't exist"));
+        let vertex_offset = slice_to_u32(&data[12..16]) as usize;
+        let vertex_n = slice_to_u32(&data[16..20]) as usize;
+        if vertex_offset.saturating_add(vertex_n) > vertices_lump.vertices.len() {
+            return Err(reference_error!(
+                Location::field("Faces", 12),
+                "vertices",
+                "Vertex",
+                vertex_offset.saturating_add(vertex_n)
+            ));
         }
 
         let vertices_idx = vertex_offset..vertex_offset + vertex_n;
 
         // meshverts
-        let meshverts_offset = slice_to_i32(&data[20..24]) as usize;
-        let meshverts_n = slice_to_i32(&data[24..28]) as usize;
-        if (meshverts_offset + meshverts_n) > meshverts_lump.meshverts.len() {
-            return Err(invalid_error!("Face references MeshVert that doesn't exist"));
+        let meshverts_offset = slice_to_u32(&data[20..24]) as usize;
+        let meshverts_n = slice_to_u32(&data[24..28]) as usize;
+        if meshverts_offset.saturating_add(meshverts_n) > meshverts_lump.meshverts.len() {
+            return Err(reference_error!(
+                Location::field("Faces", 20),
+                "meshverts",
+                "MeshVert",
+                meshverts_offset.saturating_add(meshverts_n)
+            ));
         }
 
         let meshverts_idx = meshverts_offset..meshverts_offset + meshverts_n;
@@ -148,8 +217,8 @@ impl Face {
         // lightmap
         let lightmap_idx = slice_to_i32(&data[28..32]) as usize;
         let lightmap_idx = if lightmap_idx < 0xffffffff {
-            if lightmap_idx >= lightmaps.maps.len() {
-                return Err(invalid_error!("Face references LightMap that doesn't exist"));
+            if lightmaps.map_or(false, |l| lightmap_idx >= l.maps.len()) {
+                return Err(reference_error!(Location::field("Faces", 28), "lightmap", "LightMap", lightmap_idx));
             }
 
             Some(lightmap_idx)
@@ -164,9 +233,9 @@ impl Face {
 
         // map_vecs
         let mut map_vecs = [Vector3::new(0.0, 0.0, 0.0); 2];
-        for n in 0..2 {
+        for (n, map_vec) in map_vecs.iter_mut().enumerate() {
             let offset = 60 + (n * 3 * 4);
-            map_vecs[n] = slice_to_vec3(&data[offset..offset + 12]);
+            *map_vec = slice_to_vec3(&data[offset..offset + 12]);
         }
 
         // normal & size
@@ -188,4 +257,208 @@ impl Face {
             size,
         })
     }
-}
\ No newline at end of file
+
+    /// Internal function. Appends the on-disk representation of this face to a buffer.
+    fn push_bytes(&self, buf: &mut Vec<u8>) {
+        push_i32(buf, self.texture_idx as i32);
+        push_opt_idx(buf, self.effect_idx);
+        push_i32(buf, self.face_type as i32);
+        push_i32(buf, self.vertices_idx.start as i32);
+        push_i32(buf, self.vertices_idx.len() as i32);
+        push_i32(buf, self.meshverts_idx.start as i32);
+        push_i32(buf, self.meshverts_idx.len() as i32);
+        push_opt_idx(buf, self.lightmap_idx);
+        push_vec2i(buf, &self.map_start);
+        push_vec2i(buf, &self.map_size);
+        push_vec3(buf, &self.map_origin);
+        push_vec3(buf, &self.map_vecs[0]);
+        push_vec3(buf, &self.map_vecs[1]);
+        push_vec3(buf, &self.normal);
+        push_vec2i(buf, &self.size);
+    }
+
+    /// Tessellate a `FaceType::Patch` face.
+    /// The control points are split into 3x3 biquadratic Bezier patches, and each one is subdivided `level` times in each direction.
+    /// Vertices along the edges between patches are shared.
+    pub fn tessellate(&self, vertices_lump: &VerticesLump, level: usize) -> Result<PatchMesh> {
+        if self.face_type != FaceType::Patch {
+            return Err(invalid_error!("Tried to tessellate a Face that isn't a patch"));
+        }
+        if level == 0 {
+            return Err(invalid_error!("Patch tessellation level must be at least 1"));
+        }
+
+        let (width, height) = match (usize::try_from(self.size.x), usize::try_from(self.size.y)) {
+            (Ok(width), Ok(height)) => (width, height),
+            _ => return Err(invalid_error!("Patch has invalid control point dimensions")),
+        };
+        if width < 3 || height < 3 || width % 2 == 0 || height % 2 == 0 {
+            return Err(invalid_error!("Patch has invalid control point dimensions"));
+        }
+        let n_controls = width
+            .checked_mul(height)
+            .ok_or_else(|| invalid_error!("Patch has invalid control point dimensions"))?;
+        if self.vertices_idx.len() != n_controls || self.vertices_idx.end > vertices_lump.vertices.len() {
+            return Err(invalid_error!("Patch references the wrong number of vertices"));
+        }
+        let controls = &vertices_lump.vertices[self.vertices_idx.clone()];
+
+        let patches = Vector2::new((width - 1) / 2, (height - 1) / 2);
+        let size = Vector2::new((patches.x * level) + 1, (patches.y * level) + 1);
+
+        let mut vertices = Vec::with_capacity(size.x * size.y);
+        for row in 0..size.y {
+            let (patch_y, v) = patch_coord(row, level, patches.y);
+            for col in 0..size.x {
+                let (patch_x, u) = patch_coord(col, level, patches.x);
+
+                let mut grid = [&controls[0]; 9];
+                for j in 0..3 {
+                    for i in 0..3 {
+                        grid[(j * 3) + i] = &controls[((patch_y * 2 + j) * width) + (patch_x * 2) + i];
+                    }
+                }
+
+                vertices.push(bezier_vertex(&grid, u, v));
+            }
+        }
+
+        let mut indices = Vec::with_capacity((size.x - 1) * (size.y - 1) * 6);
+        for row in 0..size.y - 1 {
+            for col in 0..size.x - 1 {
+                let i = ((row * size.x) + col) as u32;
+                let below = i + size.x as u32;
+
+                indices.extend_from_slice(&[i, below, i + 1, i + 1, below, below + 1]);
+            }
+        }
+
+        Ok(PatchMesh {
+            vertices,
+            indices,
+            size,
+        })
+    }
+}
+
+/// Internal function. Find which patch a tessellated row/column is in, and how far along that patch it is.
+fn patch_coord(n: usize, level: usize, n_patches: usize) -> (usize, f32) {
+    let patch = (n / level).min(n_patches - 1);
+    let t = (n - (patch * level)) as f32 / level as f32;
+
+    (patch, t)
+}
+
+/// Internal function. Evaluate a biquadratic Bezier patch at the given point.
+/// `grid` is the 3x3 control points in row-major order.
+fn bezier_vertex(grid: &[&Vertex; 9], u: f32, v: f32) -> Vertex {
+    let basis = |t: f32| [(1.0 - t) * (1.0 - t), 2.0 * t * (1.0 - t), t * t];
+    let bu = basis(u);
+    let bv = basis(v);
+
+    let mut position = Vector3::new(0.0, 0.0, 0.0);
+    let mut normal = Vector3::new(0.0, 0.0, 0.0);
+    let mut tex = [0.0; 4];
+    let mut color = [0.0; 4];
+
+    for j in 0..3 {
+        for i in 0..3 {
+            let weight = bu[i] * bv[j];
+            let control = grid[(j * 3) + i];
+
+            position += control.position * weight;
+            normal += control.normal * weight;
+
+            tex[0] += control.tex.u[0] * weight;
+            tex[1] += control.tex.u[1] * weight;
+            tex[2] += control.tex.v[0] * weight;
+            tex[3] += control.tex.v[1] * weight;
+
+            color[0] += f32::from(control.color.r) * weight;
+            color[1] += f32::from(control.color.g) * weight;
+            color[2] += f32::from(control.color.b) * weight;
+            color[3] += f32::from(control.color.a) * weight;
+        }
+    }
+
+    if normal.norm() > 0.0 {
+        normal.normalize_mut();
+    }
+
+    let channel = |c: f32| c.round().clamp(0.0, 255.0) as u8;
+
+    Vertex {
+        position,
+        tex: TexCoord {
+            u: [tex[0], tex[1]],
+            v: [tex[2], tex[3]],
+        },
+        normal,
+        color: RGBA {
+            r: channel(color[0]),
+            g: channel(color[1]),
+            b: channel(color[2]),
+            a: channel(color[3]),
+        },
+    }
+}
+
+#[test]
+fn tessellate_single_patch() {
+    let (face, vertices) = test_patch(Vector2::new(3, 3));
+
+    let mesh = face.tessellate(&vertices, 4).unwrap();
+
+    assert_eq!(mesh.size, Vector2::new(5, 5));
+    assert_eq!(mesh.vertices.len(), 25);
+    assert_eq!(mesh.indices.len(), 4 * 4 * 6);
+
+    // Corners lie on the control points
+    assert_eq!(mesh.vertices[0], vertices.vertices[0]);
+    assert_eq!(mesh.vertices[4], vertices.vertices[2]);
+    assert_eq!(mesh.vertices[20], vertices.vertices[6]);
+    assert_eq!(mesh.vertices[24], vertices.vertices[8]);
+
+    // An evenly spaced flat grid stays evenly spaced
+    assert_eq!(mesh.vertices[12].position, Vector3::new(1.0, 1.0, 0.0));
+    assert_eq!(mesh.vertices[12].tex.u, [1.0, 1.0]);
+    assert_eq!(mesh.vertices[12].normal, Vector3::new(0.0, 0.0, 1.0));
+    assert_eq!(mesh.vertices[12].color, RGBA::from_bytes([255, 0, 50, 255]));
+
+    assert_eq!(&mesh.indices[..6], &[0, 5, 1, 1, 5, 6]);
+}
+
+#[test]
+fn tessellate_multiple_patches() {
+    let (face, vertices) = test_patch(Vector2::new(5, 3));
+
+    let mesh = face.tessellate(&vertices, 2).unwrap();
+
+    // The shared edge between patches is only emitted once
+    assert_eq!(mesh.size, Vector2::new(5, 3));
+    assert_eq!(mesh.vertices[2].position, Vector3::new(2.0, 0.0, 0.0));
+    assert_eq!(mesh.vertices[14].position, Vector3::new(4.0, 2.0, 0.0));
+    assert!(mesh.indices.iter().all(|i| (*i as usize) < mesh.vertices.len()));
+}
+
+#[test]
+fn tessellate_invalid() {
+    let (mut face, vertices) = test_patch(Vector2::new(3, 3));
+    assert!(face.tessellate(&vertices, 0).is_err());
+
+    face.size = Vector2::new(4, 3);
+    assert!(face.tessellate(&vertices, 2).is_err());
+
+    face.size = Vector2::new(-1, 3);
+    assert!(face.tessellate(&vertices, 2).is_err());
+
+    face.size = Vector2::new(3, -3);
+    assert!(face.tessellate(&vertices, 2).is_err());
+
+    face.size = Vector2::new(i32::MAX, i32::MAX);
+    assert!(face.tessellate(&vertices, 2).is_err());
+
+    face.size = Vector2::new(3, 3);
+    face.face_type = FaceType::Polygon;
+    assert!(face.tessellate(&vertices, 2).is_err());
+}
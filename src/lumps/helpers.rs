@@ -19,6 +19,9 @@
 
 use na::{Vector2, Vector3};
 use std::convert::TryInto;
+use std::str;
+
+use crate::types::Result;
 
 /// Turn a slice into a le i32, the int datatype in a bsp file.
 /// # Panics
@@ -34,11 +37,18 @@ pub fn slice_to_u32(slice: &[u8]) -> u32 {
     u32::from_le_bytes(slice.try_into().unwrap())
 }
 
-/// Turn a slice into a le usize, the int datatype in a bsp file
+/// Turn a slice into a le u16, used for indices in older bsp formats.
 /// # Panics
-/// If slice is not 4 bytes long.
-pub fn slice_to_usize(slice: &[u8]) -> usize {
-    usize::from_le_bytes(slice.try_into().unwrap())
+/// If slice is not 2 bytes long.
+pub fn slice_to_u16(slice: &[u8]) -> u16 {
+    u16::from_le_bytes(slice.try_into().unwrap())
+}
+
+/// Turn a slice into a le i16, the short datatype in older bsp formats.
+/// # Panics
+/// If slice is not 2 bytes long.
+pub fn slice_to_i16(slice: &[u8]) -> i16 {
+    i16::from_le_bytes(slice.try_into().unwrap())
 }
 
 /// Turn a slice into a le f32, the float datatype in a bsp file.
@@ -48,6 +58,13 @@ pub fn slice_to_f32(slice: &[u8]) -> f32 {
     f32::from_bits(u32::from_le_bytes(slice.try_into().unwrap()))
 }
 
+/// Read a NUL-padded string, stopping at the first NUL.
+pub fn slice_to_cstr(slice: &[u8]) -> Result<&str> {
+    let len = slice.iter().position(|b| *b == 0).unwrap_or(slice.len());
+
+    Ok(str::from_utf8(&slice[..len])?)
+}
+
 /// Turn a slice of floats into a 3D vector
 /// # Panics
 /// If slice isn't 12 bytes long.
@@ -70,9 +87,141 @@ pub fn slice_to_vec3i(slice: &[u8]) -> Vector3<i32> {
     )
 }
 
+/// Turn a slice of i16s into a 3D vector
+/// # Panics
+/// If slice isn't 6 bytes long.
+pub fn slice_to_vec3s(slice: &[u8]) -> Vector3<i32> {
+    Vector3::new(
+        i32::from(slice_to_i16(&slice[0..2])),
+        i32::from(slice_to_i16(&slice[2..4])),
+        i32::from(slice_to_i16(&slice[4..6])),
+    )
+}
+
 /// Turn a slice of i32s into a 2D vector
 /// # Panics
 /// If slice isn't 8 bytes long.
 pub fn slice_to_vec2i(slice: &[u8]) -> Vector2<i32> {
     Vector2::new(slice_to_i32(&slice[0..4]), slice_to_i32(&slice[4..8]))
 }
+
+/// Append a le i32 to the buffer.
+pub fn push_i32(buf: &mut Vec<u8>, val: i32) {
+    buf.extend_from_slice(&val.to_le_bytes());
+}
+
+/// Append a le u32 to the buffer.
+pub fn push_u32(buf: &mut Vec<u8>, val: u32) {
+    buf.extend_from_slice(&val.to_le_bytes());
+}
+
+/// Append a le f32 to the buffer.
+pub fn push_f32(buf: &mut Vec<u8>, val: f32) {
+    push_u32(buf, val.to_bits());
+}
+
+/// Append a 3D float vector to the buffer.
+pub fn push_vec3(buf: &mut Vec<u8>, vec: &Vector3<f32>) {
+    push_f32(buf, vec.x);
+    push_f32(buf, vec.y);
+    push_f32(buf, vec.z);
+}
+
+/// Append a 3D int vector to the buffer.
+pub fn push_vec3i(buf: &mut Vec<u8>, vec: &Vector3<i32>) {
+    push_i32(buf, vec.x);
+    push_i32(buf, vec.y);
+    push_i32(buf, vec.z);
+}
+
+/// Append a 2D int vector to the buffer.
+pub fn push_vec2i(buf: &mut Vec<u8>, vec: &Vector2<i32>) {
+    push_i32(buf, vec.x);
+    push_i32(buf, vec.y);
+}
+
+/// Append an optional index, using -1 for `None`.
+pub fn push_opt_idx(buf: &mut Vec<u8>, idx: Option<usize>) {
+    push_i32(buf, idx.map_or(-1, |i| i as i32));
+}
+
+/// Append a fixed-length string, truncated or padded with NULs to `len` bytes.
+pub fn push_fixed_str(buf: &mut Vec<u8>, string: &str, len: usize) {
+    let bytes = string.as_bytes();
+    let n = bytes.len().min(len);
+
+    buf.extend_from_slice(&bytes[..n]);
+    buf.resize(buf.len() + (len - n), 0);
+}
+
+/// Records below this many aren't worth splitting across threads.
+#[cfg(feature = "rayon")]
+const PARALLEL_MIN_RECORDS: usize = 64;
+
+/// Decode each `size`-byte record in `lump` with `decode`, which is given the record's index.
+/// With the `rayon` feature, large lumps are decoded in parallel.
+/// Either way, the error returned is the one for the first bad record.
+/// # Panics
+/// If `lump` is not a whole number of records.
+pub fn decode_records<T, F>(lump: &[u8], size: usize, decode: F) -> Result<Vec<T>>
+where
+    T: Send,
+    F: Fn(usize, &[u8]) -> Result<T> + Sync,
+{
+    assert!(lump.len() % size == 0);
+
+    #[cfg(feature = "rayon")]
+    {
+        use rayon::prelude::*;
+
+        lump.par_chunks_exact(size)
+            .with_min_len(PARALLEL_MIN_RECORDS)
+            .enumerate()
+            .map(|(n, raw)| decode(n, raw))
+            .collect::<Vec<_>>()
+            .into_iter()
+            .collect()
+    }
+
+    #[cfg(not(feature = "rayon"))]
+    {
+        lump.chunks_exact(size)
+            .enumerate()
+            .map(|(n, raw)| decode(n, raw))
+            .collect()
+    }
+}
+
+/// Run both closures, at the same time if the `rayon` feature is enabled.
+pub fn join<A, B, RA, RB>(a: A, b: B) -> (RA, RB)
+where
+    A: FnOnce() -> RA + Send,
+    B: FnOnce() -> RB + Send,
+    RA: Send,
+    RB: Send,
+{
+    #[cfg(feature = "rayon")]
+    {
+        rayon::join(a, b)
+    }
+
+    #[cfg(not(feature = "rayon"))]
+    {
+        (a(), b())
+    }
+}
+
+/// Run all three closures, at the same time if the `rayon` feature is enabled.
+pub fn join3<A, B, C, RA, RB, RC>(a: A, b: B, c: C) -> (RA, RB, RC)
+where
+    A: FnOnce() -> RA + Send,
+    B: FnOnce() -> RB + Send,
+    C: FnOnce() -> RC + Send,
+    RA: Send,
+    RB: Send,
+    RC: Send,
+{
+    let (ra, (rb, rc)) = join(a, || join(b, c));
+
+    (ra, rb, rc)
+}
@@ -19,10 +19,16 @@
 
 use std::fmt;
 
+use super::faces::FaceLump;
+use super::helpers::decode_records;
+use super::vertices::VerticesLump;
 use crate::types::{Result, RGB};
 
+/// The width & height of one LightMap, in texels.
+pub const LIGHTMAP_DIM: usize = 128;
+
 /// The size of one LightMap
-const LIGHTMAP_SIZE: usize = 128 * 128 * 3;
+pub(crate) const LIGHTMAP_SIZE: usize = LIGHTMAP_DIM * LIGHTMAP_DIM * 3;
 
 /// Stores light map textures that help make surface lighting more realistic
 #[derive(Clone)]
@@ -30,6 +36,42 @@ pub struct LightMap {
     pub map: [[RGB; 128]; 128],
 }
 
+impl LightMap {
+    /// Parse a single LightMap.
+    /// # Panics
+    /// If slice is not `LIGHTMAP_SIZE` bytes long.
+    pub fn from_slice(raw: &[u8]) -> LightMap {
+        let mut map: [[RGB; 128]; 128] = [[RGB::white(); 128]; 128];
+
+        for (x, column) in map.iter_mut().enumerate() {
+            for (y, texel) in column.iter_mut().enumerate() {
+                let offset = (x * 128 * 3) + (y * 3);
+                *texel = RGB::from_slice(&raw[offset..offset + 3]);
+            }
+        }
+
+        LightMap { map }
+    }
+
+    /// Get the texel at the given column & row.
+    /// `map` is indexed by row first, since that's the order they're stored in the file.
+    pub fn texel(&self, x: usize, y: usize) -> RGB {
+        self.map[y][x]
+    }
+
+    /// Convert to a row-major RGBA8 image, with full alpha.
+    pub fn to_rgba(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(LIGHTMAP_DIM * LIGHTMAP_DIM * 4);
+        for row in self.map.iter() {
+            for texel in row.iter() {
+                buf.extend_from_slice(&[texel.r, texel.g, texel.b, 255]);
+            }
+        }
+
+        buf
+    }
+}
+
 impl PartialEq for LightMap {
     fn eq(&self, other: &LightMap) -> bool {
         for x in 0..128 {
@@ -60,7 +102,7 @@ impl fmt::Debug for LightMap {
 }
 
 /// Stores all the LightMaps parsed from a BSP file.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct LightMapsLump {
     pub maps: Box<[LightMap]>,
 }
@@ -69,26 +111,164 @@ impl LightMapsLump {
     /// Parse the LightMap lump from a bsp file.
     pub fn from_lump(lump: &[u8]) -> Result<LightMapsLump> {
         if lump.len() % LIGHTMAP_SIZE != 0 {
-            return Err(invalid_error!("LightMap lump is incorrectly sized"));
+            return Err(size_error!("LightMaps", lump.len(), LIGHTMAP_SIZE));
         }
-        let length = lump.len() / LIGHTMAP_SIZE;
+        let maps = decode_records(lump, LIGHTMAP_SIZE, |_, raw| Ok(LightMap::from_slice(raw)))?;
+
+        Ok(LightMapsLump {
+            maps: maps.into_boxed_slice(),
+        })
+    }
 
-        let mut maps = Vec::with_capacity(length as usize);
-        for n in 0..length {
-            let raw = &lump[n * LIGHTMAP_SIZE..(n + 1) * LIGHTMAP_SIZE];
-            let mut map: [[RGB; 128]; 128] = [[RGB::white(); 128]; 128];
+    pub fn empty() -> LightMapsLump {
+        LightMapsLump {
+            maps: vec![].into_boxed_slice(),
+        }
+    }
 
-            for x in 0..128 {
-                for y in 0..128 {
-                    let offset = (x * 128 * 3) + (y * 3);
-                    map[x][y] = RGB::from_slice(&raw[offset..offset + 3]);
+    /// Serialise back into a LightMap lump.
+    pub fn to_lump(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(self.maps.len() * LIGHTMAP_SIZE);
+        for map in self.maps.iter() {
+            for column in map.map.iter() {
+                for texel in column.iter() {
+                    buf.extend_from_slice(&[texel.r, texel.g, texel.b]);
                 }
             }
-            maps.push(LightMap { map })
         }
 
-        Ok(LightMapsLump {
-            maps: maps.into_boxed_slice(),
+        buf
+    }
+}
+
+/// One atlas image made from several lightmaps.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LightMapAtlas {
+    pub width: usize,
+    pub height: usize,
+
+    /// Row-major RGBA8 texels. Unused space is black.
+    pub data: Box<[u8]>,
+}
+
+/// Where a lightmap was placed in a set of atlases.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AtlasRegion {
+    /// Index into `LightMapAtlases::atlases`.
+    pub atlas: usize,
+
+    /// The column of the lightmap's top left texel, not counting padding.
+    pub x: usize,
+
+    /// The row of the lightmap's top left texel, not counting padding.
+    pub y: usize,
+}
+
+/// The result of packing a `LightMapsLump` into atlases.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LightMapAtlases {
+    pub atlases: Vec<LightMapAtlas>,
+
+    /// The region of each lightmap, in the same order as `LightMapsLump::maps`.
+    pub regions: Box<[AtlasRegion]>,
+}
+
+impl LightMapsLump {
+    /// Pack every lightmap into as few atlases of the given size as possible.
+    /// Each lightmap has `padding` texels around it copied from its edges, so filtering doesn't bleed between them.
+    /// Fails if a single padded lightmap doesn't fit in an atlas.
+    pub fn pack_atlases(&self, width: usize, height: usize, padding: usize) -> Result<LightMapAtlases> {
+        let cell = LIGHTMAP_DIM + (padding * 2);
+        let columns = width / cell;
+        let per_atlas = columns * (height / cell);
+        if per_atlas == 0 {
+            return Err(invalid_error!("Lightmap atlas is too small to fit a lightmap"));
+        }
+
+        let mut atlases = Vec::with_capacity((self.maps.len() + per_atlas - 1) / per_atlas);
+        let mut regions = Vec::with_capacity(self.maps.len());
+        for (n, map) in self.maps.iter().enumerate() {
+            if n % per_atlas == 0 {
+                atlases.push(LightMapAtlas {
+                    width,
+                    height,
+                    data: vec![0; width * height * 4].into_boxed_slice(),
+                });
+            }
+
+            let slot = n % per_atlas;
+            let region = AtlasRegion {
+                atlas: atlases.len() - 1,
+                x: ((slot % columns) * cell) + padding,
+                y: ((slot / columns) * cell) + padding,
+            };
+
+            let atlas = atlases.last_mut().unwrap();
+            for y in 0..cell {
+                for x in 0..cell {
+                    let texel = map.texel(
+                        x.saturating_sub(padding).min(LIGHTMAP_DIM - 1),
+                        y.saturating_sub(padding).min(LIGHTMAP_DIM - 1),
+                    );
+
+                    let offset = (((region.y - padding + y) * width) + (region.x - padding + x)) * 4;
+                    atlas.data[offset..offset + 4].copy_from_slice(&[texel.r, texel.g, texel.b, 255]);
+                }
+            }
+
+            regions.push(region);
+        }
+
+        Ok(LightMapAtlases {
+            atlases,
+            regions: regions.into_boxed_slice(),
         })
     }
 }
+
+impl LightMapAtlases {
+    /// Convert a texcoord for the given lightmap into one for its atlas.
+    /// # Panics
+    /// If `lightmap_idx` isn't a lightmap that was packed.
+    pub fn remap(&self, lightmap_idx: usize, tex: [f32; 2]) -> [f32; 2] {
+        let region = self.regions[lightmap_idx];
+        let atlas = &self.atlases[region.atlas];
+
+        [
+            (region.x as f32 + (tex[0] * LIGHTMAP_DIM as f32)) / atlas.width as f32,
+            (region.y as f32 + (tex[1] * LIGHTMAP_DIM as f32)) / atlas.height as f32,
+        ]
+    }
+
+    /// The atlas the given face's lightmap is in, or `None` if it doesn't have one.
+    pub fn atlas_of(&self, lightmap_idx: Option<usize>) -> Option<usize> {
+        Some(self.regions.get(lightmap_idx?)?.atlas)
+    }
+
+    /// Copy the vertices lump, with the lightmap texcoord (`TexCoord::v`) of each face's vertices remapped into atlas space.
+    /// Vertices of faces without a lightmap are left as they are.
+    pub fn remap_vertices(&self, faces: &FaceLump, vertices: &VerticesLump) -> Result<VerticesLump> {
+        let mut remapped = vertices.vertices.clone();
+
+        for face in faces.faces.iter() {
+            let lightmap_idx = match face.lightmap_idx {
+                Some(idx) => idx,
+                None => continue,
+            };
+
+            if lightmap_idx >= self.regions.len() {
+                return Err(invalid_error!("Face references LightMap that wasn't packed"));
+            }
+
+            let original = vertices
+                .vertices
+                .get(face.vertices_idx.clone())
+                .ok_or_else(|| invalid_error!("Face references Vertex that doesn't exist"))?;
+            for (vertex, original) in remapped[face.vertices_idx.clone()].iter_mut().zip(original) {
+                vertex.tex.v = self.remap(lightmap_idx, original.tex.v);
+            }
+        }
+
+        Ok(VerticesLump { vertices: remapped })
+    }
+}
@@ -16,19 +16,45 @@
 // along with stockton-bsp.  If not, see <http://www.gnu.org/licenses/>.
 
 use std::convert::TryInto;
+use std::f32::consts::PI;
 
+use super::entities::EntitiesLump;
+use super::models::ModelsLump;
 use crate::types::{Result, RGB};
+use na::Vector3;
 
-const VOL_LENGTH: usize = (3 * 2) + 2;
+pub(crate) const VOL_LENGTH: usize = (3 * 2) + 2;
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct LightVol {
     pub ambient: RGB,
     pub directional: RGB,
     pub dir: [u8; 2],
 }
 
-#[derive(Debug, Clone)]
+impl LightVol {
+    /// Parse a single LightVol.
+    /// # Panics
+    /// If slice is not `VOL_LENGTH` bytes long.
+    pub fn from_slice(data: &[u8]) -> LightVol {
+        LightVol {
+            ambient: RGB::from_slice(&data[0..3]),
+            directional: RGB::from_slice(&data[3..6]),
+            dir: data[6..8].try_into().unwrap(),
+        }
+    }
+
+    /// Decode `dir` into a unit vector pointing towards the light.
+    /// `dir[0]` is the longitude (angle from the z axis) and `dir[1]` the latitude, each in 256ths of a turn.
+    pub fn direction(&self) -> Vector3<f32> {
+        let lng = f32::from(self.dir[0]) * (2.0 * PI / 256.0);
+        let lat = f32::from(self.dir[1]) * (2.0 * PI / 256.0);
+
+        Vector3::new(lat.cos() * lng.sin(), lat.sin() * lng.sin(), lng.cos())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct LightVolsLump {
     pub vols: Box<[LightVol]>,
 }
@@ -36,22 +62,190 @@ pub struct LightVolsLump {
 impl LightVolsLump {
     pub fn from_lump(lump: &[u8]) -> Result<LightVolsLump> {
         if lump.len() % VOL_LENGTH != 0 {
-            return Err(invalid_error!("LightVols lump isn't sized correctly"));
+            return Err(size_error!("LightVols", lump.len(), VOL_LENGTH));
         }
         let length = lump.len() / VOL_LENGTH;
 
         let mut vols = Vec::with_capacity(length);
         for n in 0..length {
-            let data = &lump[n * VOL_LENGTH..(n + 1) * VOL_LENGTH];
-            vols.push(LightVol {
-                ambient: RGB::from_slice(&data[0..3]),
-                directional: RGB::from_slice(&data[3..6]),
-                dir: data[6..8].try_into().unwrap(),
-            });
+            vols.push(LightVol::from_slice(&lump[n * VOL_LENGTH..(n + 1) * VOL_LENGTH]));
         }
 
         Ok(LightVolsLump {
             vols: vols.into_boxed_slice(),
         })
     }
+
+    pub fn empty() -> LightVolsLump {
+        LightVolsLump {
+            vols: vec![].into_boxed_slice(),
+        }
+    }
+
+    pub fn to_lump(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(self.vols.len() * VOL_LENGTH);
+        for vol in self.vols.iter() {
+            buf.extend_from_slice(&[vol.ambient.r, vol.ambient.g, vol.ambient.b]);
+            buf.extend_from_slice(&[vol.directional.r, vol.directional.g, vol.directional.b]);
+            buf.extend_from_slice(&vol.dir);
+        }
+
+        buf
+    }
+}
+
+/// The size of each light grid cell used by q3map2 if the worldspawn doesn't set `gridsize`.
+pub const DEFAULT_GRID_SIZE: [f32; 3] = [64.0, 64.0, 128.0];
+
+const BLACK: RGB = RGB { r: 0, g: 0, b: 0 };
+
+/// A view of a `LightVolsLump` as the 3D grid it represents, covering the world model's bounds.
+#[derive(Debug, Clone, Copy)]
+pub struct LightGrid<'a> {
+    vols: &'a LightVolsLump,
+
+    /// The position of the first volume.
+    pub origin: Vector3<f32>,
+
+    /// The size of each cell.
+    pub size: Vector3<f32>,
+
+    /// The number of volumes along each axis.
+    pub bounds: Vector3<usize>,
+}
+
+/// Lighting sampled from a `LightGrid`.
+/// Colours are on the same 0-255 scale as the lump, without any overbright shift.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LightSample {
+    pub ambient: Vector3<f32>,
+    pub directional: Vector3<f32>,
+
+    /// Unit vector pointing towards the light.
+    pub dir: Vector3<f32>,
+}
+
+impl LightVolsLump {
+    /// View this lump as a grid covering the world model (model 0), with cells of the given size.
+    /// Fails if there's no world model, or the number of volumes doesn't match the grid.
+    pub fn grid<'a>(&'a self, models: &ModelsLump, size: Vector3<f32>) -> Result<LightGrid<'a>> {
+        let world = models
+            .models
+            .first()
+            .ok_or_else(|| invalid_error!("Light grid needs a world model"))?;
+
+        if size.iter().any(|s| *s <= 0.0) {
+            return Err(invalid_error!("Light grid size must be positive"));
+        }
+
+        let origin = Vector3::new(
+            size.x * (world.mins.x / size.x).ceil(),
+            size.y * (world.mins.y / size.y).ceil(),
+            size.z * (world.mins.z / size.z).ceil(),
+        );
+        let maxs = Vector3::new(
+            size.x * (world.maxs.x / size.x).floor(),
+            size.y * (world.maxs.y / size.y).floor(),
+            size.z * (world.maxs.z / size.z).floor(),
+        );
+
+        let extent = (maxs - origin).component_div(&size);
+        if extent.iter().any(|e| *e < 0.0) {
+            return Err(invalid_error!("World model is smaller than one light grid cell"));
+        }
+        let bounds = extent.map(|e| e as usize + 1);
+
+        if bounds.x * bounds.y * bounds.z != self.vols.len() {
+            return Err(invalid_error!("LightVols lump doesn't match the light grid size"));
+        }
+
+        Ok(LightGrid {
+            vols: self,
+            origin,
+            size,
+            bounds,
+        })
+    }
+}
+
+impl<'a> LightGrid<'a> {
+    /// The grid size set by the worldspawn's `gridsize` key, or `DEFAULT_GRID_SIZE` if it isn't set.
+    pub fn size_from_entities(entities: &EntitiesLump) -> Result<Vector3<f32>> {
+        let size = match entities.worldspawn() {
+            Some(worldspawn) => worldspawn.get_vec3("gridsize")?,
+            None => None,
+        };
+
+        Ok(size.unwrap_or_else(|| Vector3::from(DEFAULT_GRID_SIZE)))
+    }
+
+    /// Get the volume at the given grid position.
+    pub fn get(&self, x: usize, y: usize, z: usize) -> Option<&'a LightVol> {
+        if x >= self.bounds.x || y >= self.bounds.y || z >= self.bounds.z {
+            return None;
+        }
+
+        self.vols.vols.get(x + (y * self.bounds.x) + (z * self.bounds.x * self.bounds.y))
+    }
+
+    /// Sample the lighting at a point, trilinearly interpolating between the 8 surrounding volumes like the Q3 renderer.
+    /// Volumes with no light at all are inside walls, so they're skipped and the rest are weighted up to make up for them.
+    /// Points outside the grid are clamped to its edges.
+    pub fn sample(&self, point: Vector3<f32>) -> LightSample {
+        let local = (point - self.origin).component_div(&self.size);
+
+        let mut pos = [0; 3];
+        let mut frac = [0.0; 3];
+        for i in 0..3 {
+            let max = self.bounds[i] - 1;
+            let floor = local[i].floor();
+
+            pos[i] = (floor.max(0.0) as usize).min(max);
+            frac[i] = if floor < 0.0 || pos[i] == max { 0.0 } else { local[i] - floor };
+        }
+
+        let mut ambient = Vector3::zeros();
+        let mut directional = Vector3::zeros();
+        let mut dir = Vector3::zeros();
+        let mut total = 0.0;
+
+        for corner in 0..8 {
+            let mut factor = 1.0;
+            let mut at = pos;
+            for i in 0..3 {
+                if corner & (1 << i) != 0 {
+                    factor *= frac[i];
+                    at[i] = (at[i] + 1).min(self.bounds[i] - 1);
+                } else {
+                    factor *= 1.0 - frac[i];
+                }
+            }
+
+            let vol = match self.get(at[0], at[1], at[2]) {
+                Some(vol) if vol.ambient != BLACK || vol.directional != BLACK => vol,
+                _ => continue,
+            };
+
+            total += factor;
+            ambient += colour(vol.ambient) * factor;
+            directional += colour(vol.directional) * factor;
+            dir += vol.direction() * factor;
+        }
+
+        if total > 0.0 && total < 0.99 {
+            ambient /= total;
+            directional /= total;
+        }
+
+        LightSample {
+            ambient,
+            directional,
+            dir: dir.try_normalize(0.0).unwrap_or_else(Vector3::z),
+        }
+    }
+}
+
+/// Internal function. Converts a colour to a vector for interpolation.
+fn colour(c: RGB) -> Vector3<f32> {
+    Vector3::new(f32::from(c.r), f32::from(c.g), f32::from(c.b))
 }
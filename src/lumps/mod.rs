@@ -16,12 +16,14 @@
 // along with stockton-bsp.  If not, see <http://www.gnu.org/licenses/>.
 
 pub mod brushes;
+pub mod edges;
 pub mod effects;
 pub mod entities;
+pub mod entity_graph;
 pub mod faces;
 
 pub mod advertisements;
-mod helpers;
+pub(crate) mod helpers;
 pub mod light_maps;
 pub mod light_vols;
 pub mod models;
@@ -31,8 +33,12 @@ pub mod tree;
 pub mod vertices;
 pub mod visdata;
 
+#[cfg(test)]
+pub(crate) mod test_util;
+
 pub use self::advertisements::AdvertisementsLump;
 pub use self::brushes::BrushesLump;
+pub use self::edges::{EdgeFacesLump, EdgesLump, SurfEdgesLump};
 pub use self::effects::EffectsLump;
 pub use self::entities::EntitiesLump;
 pub use self::faces::FaceLump;
@@ -41,6 +47,6 @@ pub use self::light_vols::LightVolsLump;
 pub use self::models::ModelsLump;
 pub use self::planes::PlanesLump;
 pub use self::textures::TexturesLump;
-pub use self::tree::BSPTree;
-pub use self::vertices::{MeshVertsLump, VerticesLump};
-pub use self::visdata::VisDataLump;
+pub use self::tree::{BSPTree, FlatTree, NodeRef};
+pub use self::vertices::{MeshVertsLump, PositionsLump, VerticesLump};
+pub use self::visdata::{ClusterVisLump, VisDataLump};
@@ -17,14 +17,14 @@
 
 use super::brushes::BrushesLump;
 use super::faces::FaceLump;
-use super::helpers::{slice_to_i32, slice_to_vec3};
-use crate::types::Result;
+use super::helpers::{push_i32, push_vec3, slice_to_u32, slice_to_vec3};
+use crate::types::{Location, Result};
 use na::Vector3;
 use std::ops::Range;
 
 const MODEL_SIZE: usize = (4 * 3 * 2) + (4 * 4);
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Model {
     pub mins: Vector3<f32>,
     pub maxs: Vector3<f32>,
@@ -32,7 +32,7 @@ pub struct Model {
     pub brushes_idx: Range<usize>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct ModelsLump {
     pub models: Box<[Model]>,
 }
@@ -44,7 +44,7 @@ impl ModelsLump {
         brushes_lump: &BrushesLump,
     ) -> Result<ModelsLump> {
         if data.len() % MODEL_SIZE != 0 {
-            return Err(invalid_error!("ModelsLump is incorrectly sized"));
+            return Err(size_error!("Models", data.len(), MODEL_SIZE));
         }
         let n_models = data.len() / MODEL_SIZE;
 
@@ -56,25 +56,27 @@ impl ModelsLump {
             let maxs = slice_to_vec3(&raw[12..24]);
 
             let faces_idx = {
-                let start = slice_to_i32(&raw[24..28]) as usize;
-                let n = slice_to_i32(&raw[28..32]) as usize;
+                let start = slice_to_u32(&raw[24..28]) as usize;
+                let len = slice_to_u32(&raw[28..32]) as usize;
 
-                if start + n > faces_lump.faces.len() {
-                    return Err(invalid_error!("Model references Face that doesn't exist"));
+                if start.saturating_add(len) > faces_lump.faces.len() {
+                    let at = Location::record("Models", n, n * MODEL_SIZE + 24);
+                    return Err(reference_error!(at, "faces", "Face", start + len));
                 }
 
-                start..start+n
+                start..start+len
             };
 
             let brushes_idx = {
-                let start = slice_to_i32(&raw[32..36]) as usize;
-                let n = slice_to_i32(&raw[36..40]) as usize;
+                let start = slice_to_u32(&raw[32..36]) as usize;
+                let len = slice_to_u32(&raw[36..40]) as usize;
 
-                if start + n > brushes_lump.brushes.len() {
-                    return Err(invalid_error!("Model references Brush that doesn't exist"));
+                if start.saturating_add(len) > brushes_lump.brushes.len() {
+                    let at = Location::record("Models", n, n * MODEL_SIZE + 32);
+                    return Err(reference_error!(at, "brushes", "Brush", start + len));
                 }
 
-                start..start+n
+                start..start+len
             };
 
             models.push(Model {
@@ -89,4 +91,18 @@ impl ModelsLump {
             models: models.into_boxed_slice(),
         })
     }
+
+    pub fn to_lump(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(self.models.len() * MODEL_SIZE);
+        for model in self.models.iter() {
+            push_vec3(&mut buf, &model.mins);
+            push_vec3(&mut buf, &model.maxs);
+            push_i32(&mut buf, model.faces_idx.start as i32);
+            push_i32(&mut buf, model.faces_idx.len() as i32);
+            push_i32(&mut buf, model.brushes_idx.start as i32);
+            push_i32(&mut buf, model.brushes_idx.len() as i32);
+        }
+
+        buf
+    }
 }
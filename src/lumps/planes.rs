@@ -17,16 +17,19 @@
 
 //! Parses the planes lump from a bsp file.
 
-const PLANE_SIZE: usize = (4 * 3) + 4;
+pub(crate) const PLANE_SIZE: usize = (4 * 3) + 4;
 
-use super::helpers::{slice_to_f32, slice_to_vec3};
+/// The size of a plane with a type field, as used by Quake 1, Quake 2 & Source.
+const TYPED_PLANE_SIZE: usize = PLANE_SIZE + 4;
+
+use super::helpers::{push_f32, push_vec3, slice_to_f32, slice_to_vec3};
 use crate::types::Result;
 
 use na::Vector3;
 
 /// The planes lump from a BSP file.
 /// Found at lump index 2 in a q3 bsp.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct PlanesLump {
     pub planes: Box<[Plane]>,
 }
@@ -37,25 +40,52 @@ impl PlanesLump {
     pub fn from_lump(lump: &[u8]) -> Result<PlanesLump> {
 
         let length = lump.len() / PLANE_SIZE;
-        if lump.is_empty() || lump.len() % PLANE_SIZE != 0 || length % 2 != 0 {
-            return Err(invalid_error!("PlanesLump incorrectly sized"));
+        if lump.is_empty() {
+            return Err(invalid_error!("Planes lump is empty"));
+        }
+        if lump.len() % (PLANE_SIZE * 2) != 0 {
+            // planes come in pairs
+            return Err(size_error!("Planes", lump.len(), PLANE_SIZE * 2));
         }
 
 
         let mut planes = Vec::with_capacity(length / 2);
         for n in 0..length {
             let offset = n * PLANE_SIZE;
-            let plane = &lump[offset..offset + PLANE_SIZE];
-            planes.push(Plane {
-                normal: slice_to_vec3(&plane[0..12]),
-                dist: slice_to_f32(&plane[12..16]),
-            });
+            planes.push(Plane::from_slice(&lump[offset..offset + PLANE_SIZE]));
         }
 
         Ok(PlanesLump {
             planes: planes.into_boxed_slice(),
         })
     }
+
+    /// Parse a lump of planes which each have a trailing type field, as used by Quake 1, Quake 2 & Source.
+    /// The type is discarded, since it can be worked out from the normal.
+    /// Unlike Q3, these planes don't have to come in pairs.
+    pub fn from_typed_lump(lump: &[u8]) -> Result<PlanesLump> {
+        if lump.len() % TYPED_PLANE_SIZE != 0 {
+            return Err(size_error!("Planes", lump.len(), TYPED_PLANE_SIZE));
+        }
+
+        Ok(PlanesLump {
+            planes: lump
+                .chunks_exact(TYPED_PLANE_SIZE)
+                .map(|plane| Plane::from_slice(&plane[..PLANE_SIZE]))
+                .collect(),
+        })
+    }
+
+    /// Serialise back into a lump of planes.
+    pub fn to_lump(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(self.planes.len() * PLANE_SIZE);
+        for plane in self.planes.iter() {
+            push_vec3(&mut buf, &plane.normal);
+            push_f32(&mut buf, plane.dist);
+        }
+
+        buf
+    }
 }
 
 /// Generic plane, referenced by nodes & brushsizes
@@ -66,4 +96,15 @@ pub struct Plane {
 
     /// Distance from origin to plane along normal
     pub dist: f32,
-}
\ No newline at end of file
+}
+impl Plane {
+    /// Parse a single plane record.
+    /// # Panics
+    /// If slice is not `PLANE_SIZE` bytes long.
+    pub fn from_slice(plane: &[u8]) -> Plane {
+        Plane {
+            normal: slice_to_vec3(&plane[0..12]),
+            dist: slice_to_f32(&plane[12..16]),
+        }
+    }
+}
@@ -0,0 +1,172 @@
+// Copyright (C) 2019 Oscar Shrimpton
+//
+// This file is part of stockton-bsp.
+//
+// stockton-bsp is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// stockton-bsp is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with stockton-bsp.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Builders for the values & byte buffers unit tests are made from.
+
+use na::{Vector2, Vector3};
+use std::ops::Range;
+
+use crate::lumps::faces::{Face, FaceType};
+use crate::lumps::{BSPTree, BrushesLump, PlanesLump, TexturesLump, VerticesLump};
+
+/// Pack ints as they'd appear in a lump, for building test files.
+pub fn ints(vals: &[i32]) -> Vec<u8> {
+    vals.iter().flat_map(|v| v.to_le_bytes().to_vec()).collect()
+}
+
+/// Pack shorts as they'd appear in a lump, for building test files.
+pub fn shorts(vals: &[i16]) -> Vec<u8> {
+    vals.iter().flat_map(|v| v.to_le_bytes().to_vec()).collect()
+}
+
+/// Pack floats as they'd appear in a lump, for building test files.
+pub fn floats(vals: &[f32]) -> Vec<u8> {
+    vals.iter().flat_map(|v| v.to_le_bytes().to_vec()).collect()
+}
+
+/// A face with everything but its type & ranges zeroed, for building test meshes.
+pub fn test_face(face_type: FaceType, vertices_idx: Range<usize>, meshverts_idx: Range<usize>) -> Face {
+    Face {
+        face_type,
+        texture_idx: 0,
+        effect_idx: None,
+        lightmap_idx: None,
+        vertices_idx,
+        meshverts_idx,
+        map_start: Vector2::new(0, 0),
+        map_size: Vector2::new(0, 0),
+        map_origin: Vector3::new(0.0, 0.0, 0.0),
+        map_vecs: [Vector3::new(0.0, 0.0, 0.0); 2],
+        normal: Vector3::new(0.0, 0.0, 1.0),
+        size: Vector2::new(0, 0),
+    }
+}
+
+/// A grid of vertices in the z = 0 plane, row by row.
+/// Each is at (x, y, 0), with (x, y) as its texture coordinates and x * 50 as its blue channel.
+pub fn test_vertices(size: Vector2<i32>) -> VerticesLump {
+    use crate::lumps::vertices::{TexCoord, Vertex};
+    use crate::types::RGBA;
+
+    let mut vertices = Vec::new();
+    for y in 0..size.y {
+        for x in 0..size.x {
+            vertices.push(Vertex {
+                position: Vector3::new(x as f32, y as f32, 0.0),
+                tex: TexCoord {
+                    u: [x as f32, y as f32],
+                    v: [0.0, 0.0],
+                },
+                normal: Vector3::new(0.0, 0.0, 1.0),
+                color: RGBA::from_bytes([255, 0, (x * 50) as u8, 255]),
+            });
+        }
+    }
+
+    VerticesLump {
+        vertices: vertices.into_boxed_slice(),
+    }
+}
+
+/// A patch face over a grid of control points from `test_vertices`.
+pub fn test_patch(size: Vector2<i32>) -> (Face, VerticesLump) {
+    let vertices = test_vertices(size);
+    let mut face = test_face(FaceType::Patch, 0..vertices.vertices.len(), 0..0);
+    face.size = size;
+
+    (face, vertices)
+}
+
+/// A single 32 unit cube brush centred on the origin, in the back leaf of a tree split along x = 1000.
+pub fn test_map() -> (BSPTree, PlanesLump, BrushesLump, TexturesLump) {
+    use crate::lumps::brushes::{Brush, BrushSide};
+    use crate::lumps::planes::Plane;
+    use crate::lumps::textures::{ContentsFlags, SurfaceFlags, Texture};
+    use crate::lumps::tree::{BSPLeaf, BSPNode};
+
+    let axis = |x, y, z, dist| Plane {
+        normal: Vector3::new(x, y, z),
+        dist,
+    };
+    let planes = PlanesLump {
+        planes: vec![
+            axis(1.0, 0.0, 0.0, 16.0),
+            axis(-1.0, 0.0, 0.0, 16.0),
+            axis(0.0, 1.0, 0.0, 16.0),
+            axis(0.0, -1.0, 0.0, 16.0),
+            axis(0.0, 0.0, 1.0, 16.0),
+            axis(0.0, 0.0, -1.0, 16.0),
+            axis(1.0, 0.0, 0.0, 1000.0),
+            axis(-1.0, 0.0, 0.0, -1000.0),
+        ]
+        .into_boxed_slice(),
+    };
+
+    let brushes = BrushesLump {
+        brushes: vec![Brush {
+            sides: (0..6)
+                .map(|plane_idx| BrushSide {
+                    plane_idx,
+                    texture_idx: 1,
+                    is_opposing: plane_idx % 2 != 0,
+                })
+                .collect(),
+            texture_idx: 0,
+        }]
+        .into_boxed_slice(),
+    };
+
+    let textures = TexturesLump {
+        textures: vec![
+            Texture {
+                name: "brush".to_owned(),
+                surface: SurfaceFlags::empty(),
+                contents: ContentsFlags::SOLID,
+            },
+            Texture {
+                name: "side".to_owned(),
+                surface: SurfaceFlags::METAL_STEPS,
+                contents: ContentsFlags::SOLID,
+            },
+        ]
+        .into_boxed_slice(),
+    };
+
+    let leaf = |brushes_idx: Vec<u32>| BSPNode {
+        plane_idx: 0,
+        children: None,
+        min: Vector3::new(0, 0, 0),
+        max: Vector3::new(0, 0, 0),
+        leaf: Some(BSPLeaf {
+            cluster_id: 0,
+            area: 0,
+            faces_idx: vec![].into_boxed_slice(),
+            brushes_idx: brushes_idx.into_boxed_slice(),
+        }),
+    };
+    let tree = BSPTree {
+        root: BSPNode {
+            plane_idx: 6,
+            children: Some(Box::new([leaf(vec![]), leaf(vec![0])])),
+            min: Vector3::new(0, 0, 0),
+            max: Vector3::new(0, 0, 0),
+            leaf: None,
+        },
+    };
+
+    (tree, planes, brushes, textures)
+}
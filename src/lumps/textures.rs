@@ -17,12 +17,12 @@
 
 use std::str;
 
-use super::helpers::slice_to_u32;
+use super::helpers::{push_fixed_str, push_u32, slice_to_u32};
 use crate::types::Result;
 
-const TEXTURE_LUMP_SIZE: usize = (64 + 4 + 4);
+pub(crate) const TEXTURE_LUMP_SIZE: usize = 64 + 4 + 4;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 /// Surface descriptions
 pub struct TexturesLump {
     pub textures: Box<[Texture]>,
@@ -45,8 +45,11 @@ impl TexturesLump {
     /// int contents        Content flags.
     /// Length of entities is total lump size / TEXTURE_LUMP_SIZE (64 + 4 + 4)
     pub fn from_lump(lump: &[u8]) -> Result<TexturesLump> {
-        if lump.is_empty() || lump.len() % TEXTURE_LUMP_SIZE != 0 {
-            return Err(invalid_error!("TexturesLump is incorrectly sized"));
+        if lump.is_empty() {
+            return Err(invalid_error!("Textures lump is empty"));
+        }
+        if lump.len() % TEXTURE_LUMP_SIZE != 0 {
+            return Err(size_error!("Textures", lump.len(), TEXTURE_LUMP_SIZE));
         }
         let length = lump.len() / TEXTURE_LUMP_SIZE;
 
@@ -54,17 +57,38 @@ impl TexturesLump {
         let mut textures = Vec::with_capacity(length);
         for n in 0..length {
             let offset = n * TEXTURE_LUMP_SIZE;
-            textures.push(Texture {
-                name: str::from_utf8(&lump[offset..offset + 64])?.to_owned(),
-                surface: SurfaceFlags::from_bits_truncate(slice_to_u32(&lump[offset + 64..offset + 68])),
-                contents: ContentsFlags::from_bits_truncate(slice_to_u32(&lump[offset + 68..offset + 72])),
-            });
+            textures.push(Texture::from_slice(&lump[offset..offset + TEXTURE_LUMP_SIZE])?);
         }
 
         Ok(TexturesLump {
             textures: textures.into_boxed_slice(),
         })
     }
+
+    /// Serialise back into the format described in `from_lump`.
+    pub fn to_lump(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(self.textures.len() * TEXTURE_LUMP_SIZE);
+        for texture in self.textures.iter() {
+            push_fixed_str(&mut buf, &texture.name, 64);
+            push_u32(&mut buf, texture.surface.bits());
+            push_u32(&mut buf, texture.contents.bits());
+        }
+
+        buf
+    }
+}
+
+impl Texture {
+    /// Parse a single texture record.
+    /// # Panics
+    /// If slice is not `TEXTURE_LUMP_SIZE` bytes long.
+    pub fn from_slice(texture: &[u8]) -> Result<Texture> {
+        Ok(Texture {
+            name: str::from_utf8(&texture[0..64])?.to_owned(),
+            surface: SurfaceFlags::from_bits_truncate(slice_to_u32(&texture[64..68])),
+            contents: ContentsFlags::from_bits_truncate(slice_to_u32(&texture[68..72])),
+        })
+    }
 }
 
 bitflags!(
@@ -19,15 +19,17 @@
 
 use super::brushes::BrushesLump;
 use super::faces::FaceLump;
-use crate::lumps::helpers::{slice_to_u32, slice_to_i32, slice_to_vec3i};
-use crate::types::Result;
+use super::planes::{Plane, PlanesLump};
+use super::visdata::VisDataLump;
+use crate::lumps::helpers::{decode_records, push_i32, push_u32, push_vec3i, slice_to_u32, slice_to_i32, slice_to_vec3i};
+use crate::types::{Location, Result};
 use na::Vector3;
 
 const NODE_SIZE: usize = 4 + (4 * 2) + (4 * 3) + (4 * 3);
 const LEAF_SIZE: usize = 4 * 6 + (4 * 3 * 2);
 
 /// Represents a BSP / binary tree.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct BSPTree {
     /// The root of this tree, first in the nodes lump for q3 files.
     pub root: BSPNode,
@@ -35,7 +37,7 @@ pub struct BSPTree {
 
 /// A node in a BSP tree.
 /// Either has two children *or* a leaf entry.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct BSPNode {
     pub plane_idx: u32,
     pub children: Option<Box<[BSPNode; 2]>>,
@@ -44,9 +46,18 @@ pub struct BSPNode {
     pub leaf: Option<BSPLeaf>,
 }
 
+/// The raw lumps produced when serialising a `BSPTree`.
+#[derive(Debug, Clone, Default)]
+pub struct TreeLumps {
+    pub nodes: Vec<u8>,
+    pub leaves: Vec<u8>,
+    pub leaf_faces: Vec<u8>,
+    pub leaf_brushes: Vec<u8>,
+}
+
 /// A leaf in a BSP tree.
 /// Will be under a `BSPNode`, min and max values are stored there.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct BSPLeaf {
     pub cluster_id: u32,
     pub area: i32,
@@ -54,8 +65,108 @@ pub struct BSPLeaf {
     pub brushes_idx: Box<[u32]>,
 }
 
-impl BSPTree {
-    /// Parses the nodes & leaves lumps into a usable BSP tree.
+impl BSPLeaf {
+    /// The visibility cluster this leaf is in, or `None` if it's outside the map or inside a wall.
+    pub fn cluster(&self) -> Option<usize> {
+        if (self.cluster_id as i32) < 0 {
+            None
+        } else {
+            Some(self.cluster_id as usize)
+        }
+    }
+}
+
+/// A reference to a node or leaf by its index in the file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum NodeRef {
+    Node(usize),
+    Leaf(usize),
+}
+
+impl NodeRef {
+    /// Interpret a child index from a file, where leaves are stored as `-(index + 1)`.
+    pub fn from_raw(i: i32) -> NodeRef {
+        if i < 0 {
+            NodeRef::Leaf((-(i as i64) - 1) as usize)
+        } else {
+            NodeRef::Node(i as usize)
+        }
+    }
+
+    /// The child index used to refer to this in a file.
+    pub fn to_raw(self) -> i32 {
+        match self {
+            NodeRef::Node(i) => i as i32,
+            NodeRef::Leaf(i) => -(i as i32) - 1,
+        }
+    }
+}
+
+/// Iterator over the nodes visited while walking down a `BSPTree` towards a point.
+/// Created by `BSPTree::walk`.
+#[derive(Debug, Clone)]
+pub struct PointWalk<'a, 'p> {
+    node: Option<&'a BSPNode>,
+    point: Vector3<f32>,
+    planes: &'p PlanesLump,
+}
+
+impl<'a, 'p> Iterator for PointWalk<'a, 'p> {
+    type Item = &'a BSPNode;
+
+    fn next(&mut self) -> Option<&'a BSPNode> {
+        let node = self.node?;
+
+        self.node = match (&node.children, self.planes.planes.get(node.plane_idx as usize)) {
+            (Some(children), Some(plane)) => {
+                if plane.normal.dot(&self.point) - plane.dist >= 0.0 {
+                    Some(&children[0])
+                } else {
+                    Some(&children[1])
+                }
+            }
+            _ => None,
+        };
+
+        Some(node)
+    }
+}
+
+/// The deepest a tree can go before it's rejected, so malformed files can't overflow the stack.
+/// Trees compiled by q3map2 are rarely more than a few dozen deep.
+pub const MAX_TREE_DEPTH: usize = 1024;
+
+/// A BSP tree stored as flat lists of nodes & leaves, which refer to each other by index.
+/// Indices are the same as in the file, so they can be matched up with other tools.
+/// The root is always `NodeRef::Node(0)`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FlatTree {
+    pub nodes: Vec<Node>,
+    pub leaves: Vec<Leaf>,
+}
+
+/// A node in a `FlatTree`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Node {
+    pub plane_idx: u32,
+
+    /// Front & back children.
+    pub children: [NodeRef; 2],
+    pub min: Vector3<i32>,
+    pub max: Vector3<i32>,
+}
+
+/// A leaf in a `FlatTree`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Leaf {
+    pub leaf: BSPLeaf,
+    pub min: Vector3<i32>,
+    pub max: Vector3<i32>,
+}
+
+impl FlatTree {
+    /// Parse every node & leaf, checking that children, leaf faces & leaf brushes all exist.
+    /// Unlike `BSPTree::from_lumps`, this doesn't check the nodes actually form a tree.
     pub fn from_lumps(
         nodes: &[u8],
         leaves: &[u8],
@@ -63,135 +174,386 @@ impl BSPTree {
         leaf_brushes: &[u8],
         faces: &FaceLump,
         brushes: &BrushesLump,
-    ) -> Result<BSPTree> {
-        if nodes.len() % NODE_SIZE != 0 || leaves.len() % LEAF_SIZE != 0 {
-            return Err(invalid_error!("BSPTree is incorrectly sized"));
+    ) -> Result<FlatTree> {
+        if nodes.len() % NODE_SIZE != 0 {
+            return Err(size_error!("Nodes", nodes.len(), NODE_SIZE));
+        }
+        if leaves.len() % LEAF_SIZE != 0 {
+            return Err(size_error!("Leaves", leaves.len(), LEAF_SIZE));
+        }
+        if nodes.is_empty() {
+            return Err(invalid_error!("Nodes lump is empty"));
         }
 
-        Ok(BSPTree {
-            root: BSPTree::compile_node(
-                0,
-                nodes,
-                leaves,
+        let n_nodes = nodes.len() / NODE_SIZE;
+        let n_leaves = leaves.len() / LEAF_SIZE;
+
+        let flat_nodes = decode_records(nodes, NODE_SIZE, |i, raw| {
+            let mut children = [NodeRef::Node(0); 2];
+            for (c, child) in children.iter_mut().enumerate() {
+                *child = NodeRef::from_raw(slice_to_i32(&raw[4 + c * 4..8 + c * 4]));
+
+                let (target, exists) = match *child {
+                    NodeRef::Node(n) => ("Node", n < n_nodes),
+                    NodeRef::Leaf(n) => ("Leaf", n < n_leaves),
+                };
+                if !exists {
+                    let at = Location::record("Nodes", i, i * NODE_SIZE + 4 + c * 4);
+                    return Err(reference_error!(at, "child", target, child.to_raw()));
+                }
+            }
+
+            Ok(Node {
+                plane_idx: slice_to_u32(&raw[0..4]),
+                children,
+                min: slice_to_vec3i(&raw[12..24]),
+                max: slice_to_vec3i(&raw[24..36]),
+            })
+        })?;
+
+        let flat_leaves = decode_records(leaves, LEAF_SIZE, |i, raw| {
+            let faces_idx = get_indices(
                 leaf_faces,
+                slice_to_u32(&raw[32..36]),
+                slice_to_u32(&raw[36..40]),
+                faces.faces.len(),
+            )
+            .map_err(|(index, value)| match index {
+                Some(index) => reference_error!(Location::record("LeafFaces", index, index * 4), "face", "Face", value),
+                None => reference_error!(Location::record("Leaves", i, i * LEAF_SIZE + 32), "leaf_faces", "LeafFace", value),
+            })?;
+
+            let brushes_idx = get_indices(
                 leaf_brushes,
-                faces,
-                brushes,
-            )?,
+                slice_to_u32(&raw[40..44]),
+                slice_to_u32(&raw[44..48]),
+                brushes.brushes.len(),
+            )
+            .map_err(|(index, value)| match index {
+                Some(index) => reference_error!(Location::record("LeafBrushes", index, index * 4), "brush", "Brush", value),
+                None => reference_error!(
+                    Location::record("Leaves", i, i * LEAF_SIZE + 40),
+                    "leaf_brushes",
+                    "LeafBrush",
+                    value
+                ),
+            })?;
+
+            Ok(Leaf {
+                leaf: BSPLeaf {
+                    cluster_id: slice_to_u32(&raw[0..4]),
+                    area: slice_to_i32(&raw[4..8]),
+                    faces_idx,
+                    brushes_idx,
+                },
+                min: slice_to_vec3i(&raw[8..20]),
+                max: slice_to_vec3i(&raw[20..32]),
+            })
+        })?;
+
+        Ok(FlatTree {
+            nodes: flat_nodes,
+            leaves: flat_leaves,
+        })
+    }
+
+    /// Flatten a `BSPTree`.
+    /// Nodes are numbered parents first, and leaves in the order they're reached, the same as `BSPTree::to_lumps`.
+    pub fn from_tree(tree: &BSPTree) -> FlatTree {
+        let mut flat = FlatTree {
+            nodes: Vec::new(),
+            leaves: Vec::new(),
+        };
+        flat.push_node(&tree.root);
+
+        flat
+    }
+
+    /// Internal function. Adds the given node and all its children, returning the reference to it.
+    fn push_node(&mut self, node: &BSPNode) -> NodeRef {
+        if let Some(leaf) = &node.leaf {
+            self.leaves.push(Leaf {
+                leaf: leaf.clone(),
+                min: node.min,
+                max: node.max,
+            });
+
+            return NodeRef::Leaf(self.leaves.len() - 1);
+        }
+
+        // Reserve our slot first so parents are numbered before their children.
+        let i = self.nodes.len();
+        self.nodes.push(Node {
+            plane_idx: node.plane_idx,
+            children: [NodeRef::Node(0); 2],
+            min: node.min,
+            max: node.max,
+        });
+
+        if let Some(children) = &node.children {
+            self.nodes[i].children = [self.push_node(&children[0]), self.push_node(&children[1])];
+        }
+
+        NodeRef::Node(i)
+    }
+
+    /// Build a `BSPTree` from the root.
+    /// Fails if a child doesn't exist, a node is reachable more than once, or the tree is deeper than `MAX_TREE_DEPTH`.
+    /// Nodes & leaves that can't be reached from the root are left out.
+    pub fn to_tree(&self) -> Result<BSPTree> {
+        let mut visited = vec![false; self.nodes.len()];
+
+        Ok(BSPTree {
+            root: self.build_node(NodeRef::Node(0), 0, &mut visited)?,
         })
     }
 
-    /// Internal function. Visits given node and all its children. Used to recursively build tree.
-    fn compile_node(
-        i: i32,
+    /// Internal function. Builds the given node and all its children.
+    fn build_node(&self, node: NodeRef, depth: usize, visited: &mut [bool]) -> Result<BSPNode> {
+        if depth > MAX_TREE_DEPTH {
+            return Err(invalid_error!("BSPTree is deeper than {} nodes", MAX_TREE_DEPTH));
+        }
+
+        match node {
+            NodeRef::Leaf(i) => {
+                let leaf = self
+                    .leaves
+                    .get(i)
+                    .ok_or_else(|| reference_error!(Location::lump("Nodes"), "child", "Leaf", node.to_raw()))?;
+
+                Ok(BSPNode {
+                    plane_idx: 0,
+                    children: None,
+                    min: leaf.min,
+                    max: leaf.max,
+                    leaf: Some(leaf.leaf.clone()),
+                })
+            }
+            NodeRef::Node(i) => {
+                let flat = match self.nodes.get(i) {
+                    Some(flat) if !visited[i] => flat,
+                    Some(_) => return Err(invalid_error!("Node {} is reachable more than once", i)),
+                    None => return Err(reference_error!(Location::lump("Nodes"), "child", "Node", i)),
+                };
+                visited[i] = true;
+
+                let children = [
+                    self.build_node(flat.children[0], depth + 1, visited)?,
+                    self.build_node(flat.children[1], depth + 1, visited)?,
+                ];
+
+                Ok(BSPNode {
+                    plane_idx: flat.plane_idx,
+                    children: Some(Box::new(children)),
+                    min: flat.min,
+                    max: flat.max,
+                    leaf: None,
+                })
+            }
+        }
+    }
+
+    /// Serialise back into the nodes, leaves, leaf faces & leaf brushes lumps, keeping every index the same.
+    pub fn to_lumps(&self) -> TreeLumps {
+        let mut lumps = TreeLumps::default();
+
+        for node in self.nodes.iter() {
+            push_u32(&mut lumps.nodes, node.plane_idx);
+            push_i32(&mut lumps.nodes, node.children[0].to_raw());
+            push_i32(&mut lumps.nodes, node.children[1].to_raw());
+            push_vec3i(&mut lumps.nodes, &node.min);
+            push_vec3i(&mut lumps.nodes, &node.max);
+        }
+
+        for leaf in self.leaves.iter() {
+            push_u32(&mut lumps.leaves, leaf.leaf.cluster_id);
+            push_i32(&mut lumps.leaves, leaf.leaf.area);
+            push_vec3i(&mut lumps.leaves, &leaf.min);
+            push_vec3i(&mut lumps.leaves, &leaf.max);
+
+            push_i32(&mut lumps.leaves, (lumps.leaf_faces.len() / 4) as i32);
+            push_i32(&mut lumps.leaves, leaf.leaf.faces_idx.len() as i32);
+            for face_idx in leaf.leaf.faces_idx.iter() {
+                push_u32(&mut lumps.leaf_faces, *face_idx);
+            }
+
+            push_i32(&mut lumps.leaves, (lumps.leaf_brushes.len() / 4) as i32);
+            push_i32(&mut lumps.leaves, leaf.leaf.brushes_idx.len() as i32);
+            for brush_idx in leaf.leaf.brushes_idx.iter() {
+                push_u32(&mut lumps.leaf_brushes, *brush_idx);
+            }
+        }
+
+        lumps
+    }
+
+    /// Find the index of the leaf containing `point`, like `BSPTree::find_leaf`.
+    /// Returns `None` if a node on the way references a plane that doesn't exist, or the nodes loop.
+    pub fn find_leaf(&self, point: Vector3<f32>, planes: &PlanesLump) -> Option<usize> {
+        let mut node = NodeRef::Node(0);
+        for _ in 0..=self.nodes.len() {
+            match node {
+                NodeRef::Leaf(i) => return Some(i),
+                NodeRef::Node(i) => {
+                    let flat = self.nodes.get(i)?;
+                    let plane = planes.planes.get(flat.plane_idx as usize)?;
+
+                    node = if plane.normal.dot(&point) - plane.dist >= 0.0 {
+                        flat.children[0]
+                    } else {
+                        flat.children[1]
+                    };
+                }
+            }
+        }
+
+        None
+    }
+}
+
+/// Internal function. Reads `n` indices starting at `start` from a lump of u32s, checking each is below `max`.
+/// On failure, gives the index of the bad entry (or `None` if the range itself is bad) and the bad value.
+fn get_indices(lump: &[u8], start: u32, n: u32, max: usize) -> std::result::Result<Box<[u32]>, (Option<usize>, u64)> {
+    let (start, n) = (start as usize, n as usize);
+    let end = start + n;
+    if n > 0 && end > lump.len() / 4 {
+        return Err((None, end as u64));
+    }
+
+    let mut indices = Vec::with_capacity(n);
+    for i in start..end {
+        let idx = slice_to_u32(&lump[i * 4..(i + 1) * 4]);
+        if idx as usize >= max {
+            return Err((Some(i), idx.into()));
+        }
+
+        indices.push(idx);
+    }
+
+    Ok(indices.into_boxed_slice())
+}
+
+impl BSPTree {
+    /// Parses the nodes & leaves lumps into a usable BSP tree.
+    /// Fails if a node is reachable more than once, or the tree is deeper than `MAX_TREE_DEPTH`.
+    pub fn from_lumps(
         nodes: &[u8],
         leaves: &[u8],
         leaf_faces: &[u8],
         leaf_brushes: &[u8],
-        faces_lump: &FaceLump,
-        brushes_lump: &BrushesLump,
-    ) -> Result<BSPNode> {
-        if i < 0 {
-            // Leaf.
-            let i = i.abs() - 1;
+        faces: &FaceLump,
+        brushes: &BrushesLump,
+    ) -> Result<BSPTree> {
+        FlatTree::from_lumps(nodes, leaves, leaf_faces, leaf_brushes, faces, brushes)?.to_tree()
+    }
 
-            let raw = &leaves[i as usize * LEAF_SIZE..(i as usize * LEAF_SIZE) + LEAF_SIZE];
+    /// A tree with a single leaf that's outside the map, with no faces or brushes.
+    /// Used when the tree isn't loaded. It can't be written back to a file, since the nodes lump would be empty.
+    pub fn empty() -> BSPTree {
+        BSPTree {
+            root: BSPNode {
+                plane_idx: 0,
+                children: None,
+                min: Vector3::zeros(),
+                max: Vector3::zeros(),
+                leaf: Some(BSPLeaf {
+                    cluster_id: u32::MAX,
+                    area: -1,
+                    faces_idx: Box::new([]),
+                    brushes_idx: Box::new([]),
+                }),
+            },
+        }
+    }
 
-            let faces_idx = {
-                let start = slice_to_u32(&raw[32..36]) as usize;
-                let n = slice_to_u32(&raw[36..40]) as usize;
+    /// Walk down the tree towards `point`, yielding every node visited from the root onwards.
+    /// The last node yielded is the leaf containing the point.
+    /// Points exactly on a plane are treated as being in front of it.
+    pub fn walk<'a, 'p>(&'a self, point: Vector3<f32>, planes: &'p PlanesLump) -> PointWalk<'a, 'p> {
+        PointWalk {
+            node: Some(&self.root),
+            point,
+            planes,
+        }
+    }
 
-                let mut faces = Vec::with_capacity(n);
-                if n > 0 {
-                    if start + n > leaf_faces.len() / 4 {
-                        return Err(invalid_error!("Leaf references LeafFace that doesn't exist"));
-                    }
+    /// Find the leaf containing `point`.
+    /// Returns `None` if a node on the way references a plane that doesn't exist.
+    pub fn find_leaf(&self, point: Vector3<f32>, planes: &PlanesLump) -> Option<&BSPLeaf> {
+        self.walk(point, planes).last()?.leaf.as_ref()
+    }
 
-                    for i in start..start + n {
-                        let face_idx = slice_to_u32(&leaf_faces[i * 4..(i + 1) * 4]);
-                        if face_idx as usize >= faces_lump.faces.len() {
-                            return Err(invalid_error!("LeafFace references Face that doesn't exist"));
-                        }
+    /// Get the index of every face potentially visible from `point`, according to the visdata.
+    /// Each face is only returned once, in ascending order.
+    /// If `frustum` is given, leaves whose bounds are entirely behind any of its planes are skipped, so the plane normals should point inwards.
+    /// If `point` isn't in a cluster, or there's no visdata, every leaf is considered visible.
+    pub fn visible_faces(
+        &self,
+        point: Vector3<f32>,
+        planes: &PlanesLump,
+        visdata: &VisDataLump,
+        frustum: Option<&[Plane]>,
+    ) -> Vec<u32> {
+        let cluster = self
+            .find_leaf(point, planes)
+            .and_then(BSPLeaf::cluster)
+            .filter(|c| *c < visdata.vecs.len());
 
-                        faces.push(face_idx);
-                    }
-                }
+        let mut faces = Vec::new();
+        BSPTree::collect_visible_faces(&self.root, cluster, visdata, frustum.unwrap_or(&[]), &mut faces);
 
-                faces.into_boxed_slice()
-            };
+        faces.sort_unstable();
+        faces.dedup();
 
-            let brushes_idx = {
-                let start = slice_to_u32(&raw[40..44]) as usize;
-                let n = slice_to_u32(&raw[44..48]) as usize;
-                let mut brushes = Vec::with_capacity(n);
-            
-                if n > 0 {
-                    if start + n > leaf_brushes.len() / 4 {
-                        return Err(invalid_error!("Leaf references LeafBrush that doesn't exist"));
-                    }
-
-                    for i in start..start + n {
-                        let brush_idx = slice_to_u32(&leaf_brushes[i * 4..(i + 1) * 4]);
-                        if brush_idx as usize >= brushes_lump.brushes.len() {
-                            return Err(invalid_error!("LeafBrush references Brush that doesn't exist"));
-                        }
-
-                        brushes.push(brush_idx);
-                    }
-                }
+        faces
+    }
 
-                brushes.into_boxed_slice()
-            };
+    /// Internal function. Adds the faces of all visible leaves under `node` to `faces`.
+    fn collect_visible_faces(
+        node: &BSPNode,
+        cluster: Option<usize>,
+        visdata: &VisDataLump,
+        frustum: &[Plane],
+        faces: &mut Vec<u32>,
+    ) {
+        if frustum.iter().any(|plane| BSPTree::box_behind(plane, &node.min, &node.max)) {
+            return;
+        }
 
-            let leaf = BSPLeaf {
-                cluster_id: slice_to_u32(&raw[0..4]),
-                area: slice_to_i32(&raw[4..8]),
-                // 8..20 = min
-                // 20..32 = max
-                faces_idx,
-                brushes_idx,
+        if let Some(leaf) = &node.leaf {
+            let visible = match (cluster, leaf.cluster()) {
+                (_, None) => false,
+                (None, Some(_)) => true,
+                (Some(from), Some(looking)) => {
+                    looking >= visdata.vecs[from].len() || visdata.visible_from(from, looking)
+                }
             };
 
-            Ok(BSPNode {
-                plane_idx: 0,
-                children: None,
-                min: slice_to_vec3i(&raw[8..20]),
-                max: slice_to_vec3i(&raw[20..32]),
-                leaf: Some(leaf),
-            })
-        } else {
-            // Node.
-            let raw = &nodes[i as usize * NODE_SIZE..(i as usize * NODE_SIZE) + NODE_SIZE];
-
-            let plane_idx = slice_to_u32(&raw[0..4]);
-            let child_one = BSPTree::compile_node(
-                slice_to_i32(&raw[4..8]),
-                nodes,
-                leaves,
-                leaf_faces,
-                leaf_brushes,
-                faces_lump,
-                brushes_lump,
-            )?;
-            let child_two = BSPTree::compile_node(
-                slice_to_i32(&raw[8..12]),
-                nodes,
-                leaves,
-                leaf_faces,
-                leaf_brushes,
-                faces_lump,
-                brushes_lump,
-            )?;
-            let min = slice_to_vec3i(&raw[12..24]);
-            let max = slice_to_vec3i(&raw[24..36]);
-
-            Ok(BSPNode {
-                plane_idx,
-                children: Some(Box::new([child_one, child_two])),
-                min,
-                max,
-                leaf: None,
-            })
+            if visible {
+                faces.extend_from_slice(&leaf.faces_idx);
+            }
+        } else if let Some(children) = &node.children {
+            for child in children.iter() {
+                BSPTree::collect_visible_faces(child, cluster, visdata, frustum, faces);
+            }
         }
     }
+
+    /// Internal function. Returns true if the given bounding box is entirely behind the plane.
+    fn box_behind(plane: &Plane, min: &Vector3<i32>, max: &Vector3<i32>) -> bool {
+        // The corner furthest along the plane normal
+        let corner = Vector3::new(
+            if plane.normal.x >= 0.0 { max.x } else { min.x } as f32,
+            if plane.normal.y >= 0.0 { max.y } else { min.y } as f32,
+            if plane.normal.z >= 0.0 { max.z } else { min.z } as f32,
+        );
+
+        plane.normal.dot(&corner) - plane.dist < 0.0
+    }
+
+    /// Serialise back into the nodes, leaves, leaf faces & leaf brushes lumps.
+    /// Nodes and leaves are numbered in depth-first order, so indices may not match the original file.
+    pub fn to_lumps(&self) -> TreeLumps {
+        FlatTree::from_tree(self).to_lumps()
+    }
 }
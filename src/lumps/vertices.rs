@@ -15,13 +15,13 @@
 // You should have received a copy of the GNU General Public License
 // along with stockton-bsp.  If not, see <http://www.gnu.org/licenses/>.
 
-use super::helpers::{slice_to_f32, slice_to_i32, slice_to_vec3};
+use super::helpers::{decode_records, push_f32, push_i32, push_vec3, slice_to_f32, slice_to_i32, slice_to_vec3};
 use crate::types::{Result, RGBA};
 use na::Vector3;
 use std::convert::TryInto;
 
 /// The size of one vertex
-const VERTEX_SIZE: usize = (4 * 3) + (2 * 2 * 4) + (4 * 3) + 4;
+pub(crate) const VERTEX_SIZE: usize = (4 * 3) + (2 * 2 * 4) + (4 * 3) + 4;
 
 /// A vertex, used to describe a face.
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -32,6 +32,20 @@ pub struct Vertex {
     pub color: RGBA,
 }
 
+impl Vertex {
+    /// Parse a single vertex record.
+    /// # Panics
+    /// If slice is not `VERTEX_SIZE` bytes long.
+    pub fn from_slice(vertex: &[u8]) -> Vertex {
+        Vertex {
+            position: slice_to_vec3(&vertex[0..12]),
+            tex: TexCoord::from_bytes(&vertex[12..28].try_into().unwrap()),
+            normal: slice_to_vec3(&vertex[28..40]),
+            color: RGBA::from_slice(&vertex[40..44]),
+        }
+    }
+}
+
 /// Represents a TexCoord. 0 = surface, 1= lightmap.
 /// This could also be written as [[f32; 2]; 2]
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -48,10 +62,18 @@ impl TexCoord {
             v: [slice_to_f32(&bytes[8..12]), slice_to_f32(&bytes[12..16])],
         }
     }
+
+    /// Internal function. Appends the TexCoord to a buffer.
+    fn push_bytes(&self, buf: &mut Vec<u8>) {
+        push_f32(buf, self.u[0]);
+        push_f32(buf, self.u[1]);
+        push_f32(buf, self.v[0]);
+        push_f32(buf, self.v[1]);
+    }
 }
 
 /// The Vertices Lump in a BSP file. Stores a list of vertices.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct VerticesLump {
     pub vertices: Box<[Vertex]>,
 }
@@ -60,27 +82,27 @@ impl VerticesLump {
     /// Parse a Vertices Lump from the data in a BSP file.
     pub fn from_lump(lump: &[u8]) -> Result<VerticesLump> {
         if lump.len() % VERTEX_SIZE != 0 {
-            return Err(invalid_error!("VerticesLump is incorrectly sized"));
-        }
-        let length = lump.len() / VERTEX_SIZE;
-
-        let mut vertices = Vec::with_capacity(length as usize);
-        for n in 0..length {
-            let offset = n * VERTEX_SIZE;
-            let vertex = &lump[offset..offset + VERTEX_SIZE];
-
-            vertices.push(Vertex {
-                position: slice_to_vec3(&vertex[0..12]),
-                tex: TexCoord::from_bytes(&vertex[12..28].try_into().unwrap()),
-                normal: slice_to_vec3(&vertex[28..40]),
-                color: RGBA::from_slice(&vertex[40..44]),
-            })
+            return Err(size_error!("Vertices", lump.len(), VERTEX_SIZE));
         }
+        let vertices = decode_records(lump, VERTEX_SIZE, |_, raw| Ok(Vertex::from_slice(raw)))?;
 
         Ok(VerticesLump {
             vertices: vertices.into_boxed_slice(),
         })
     }
+
+    /// Serialise back into a Vertices Lump.
+    pub fn to_lump(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(self.vertices.len() * VERTEX_SIZE);
+        for vertex in self.vertices.iter() {
+            push_vec3(&mut buf, &vertex.position);
+            vertex.tex.push_bytes(&mut buf);
+            push_vec3(&mut buf, &vertex.normal);
+            buf.extend_from_slice(&[vertex.color.r, vertex.color.g, vertex.color.b, vertex.color.a]);
+        }
+
+        buf
+    }
 }
 
 /// A vertex offset, used to describe generalised triangle meshes
@@ -90,7 +112,7 @@ pub struct MeshVert {
 }
 
 /// A list of MeshVerts
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct MeshVertsLump {
     pub meshverts: Box<[MeshVert]>,
 }
@@ -99,12 +121,12 @@ impl MeshVertsLump {
     /// Parse the given lump as a list of MeshVerts.
     pub fn from_lump(lump: &[u8]) -> Result<MeshVertsLump> {
         if lump.len() % 4 != 0 {
-            return Err(invalid_error!("MeshVertsLump is incorrectly sized"));
+            return Err(size_error!("MeshVerts", lump.len(), 4));
         }
         let length = lump.len() / 4;
 
 
-        let mut meshverts = Vec::with_capacity(length as usize);
+        let mut meshverts = Vec::with_capacity(length);
         for n in 0..length {
             meshverts.push(MeshVert {
                 offset: slice_to_i32(&lump[n * 4..(n + 1) * 4]),
@@ -115,4 +137,34 @@ impl MeshVertsLump {
             meshverts: meshverts.into_boxed_slice(),
         })
     }
+
+    /// Serialise back into a MeshVerts lump.
+    pub fn to_lump(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(self.meshverts.len() * 4);
+        for meshvert in self.meshverts.iter() {
+            push_i32(&mut buf, meshvert.offset);
+        }
+
+        buf
+    }
+}
+
+/// A list of vertex positions with no other data.
+/// Used by Quake 1, Quake 2 & Source, where faces are built from edges between these.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PositionsLump {
+    pub positions: Box<[Vector3<f32>]>,
+}
+
+impl PositionsLump {
+    /// Parse the given lump as a list of 3D float vectors.
+    pub fn from_lump(lump: &[u8]) -> Result<PositionsLump> {
+        if lump.len() % 12 != 0 {
+            return Err(size_error!("Positions", lump.len(), 12));
+        }
+
+        Ok(PositionsLump {
+            positions: lump.chunks_exact(12).map(slice_to_vec3).collect(),
+        })
+    }
 }
@@ -17,28 +17,42 @@
 
 use bit_vec::BitVec;
 
-use super::helpers::slice_to_i32;
-use crate::types::Result;
+use super::helpers::{push_i32, slice_to_i32, slice_to_u32};
+use crate::types::{Error, Location, Result};
 
 /// Stores cluster-to-cluster visibility information.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct VisDataLump {
     /// Each vector is an array of bools which states if that cluster is visible for this.
-    /// For example, if vecs[x][y] == true, then they are visible.
+    /// These are the raw bytes, so bits are most significant first: cluster `c` is at `(c & !7) | (7 - (c & 7))`,
+    /// not `c`. Use `visible_from` to look up by cluster, or index with the same swizzle.
     /// Every BitVec has the same length.
     pub vecs: Box<[BitVec]>,
 }
 impl VisDataLump {
     pub fn from_lump(data: &[u8]) -> Result<VisDataLump> {
         if data.len() < 8 {
-            return Err(invalid_error!("VisDataLump is incorrectly sized"));
+            return Err(Error::OutOfBounds {
+                lump: "VisData",
+                offset: 0,
+                length: 8,
+            });
         }
-        
-        let n_vecs = slice_to_i32(&data[0..4]) as usize;
-        let size_vecs = slice_to_i32(&data[4..8]) as usize;
 
-        if data.len() - 8 != (n_vecs * size_vecs) {
-            return Err(invalid_error!("VisDataLump is incorrectly sized"));
+        let n_vecs = slice_to_u32(&data[0..4]) as usize;
+        let size_vecs = slice_to_u32(&data[4..8]) as usize;
+
+        if n_vecs.checked_mul(size_vecs) != Some(data.len() - 8) {
+            return Err(value_error!(
+                Location::lump("VisData"),
+                "n_vecs",
+                format!("{} vectors of {} bytes in {} bytes", n_vecs, size_vecs, data.len() - 8)
+            ));
+        }
+
+        // each vector needs a bit for every cluster
+        if size_vecs * 8 < n_vecs {
+            return Err(value_error!(Location::lump("VisData"), "size_vecs", size_vecs));
         }
 
         let mut vecs = Vec::with_capacity(n_vecs);
@@ -53,8 +67,146 @@ impl VisDataLump {
         })
     }
 
+    /// No visdata, so every cluster is treated as visible by `BSPTree::visible_faces`.
+    pub fn empty() -> VisDataLump {
+        VisDataLump {
+            vecs: vec![].into_boxed_slice(),
+        }
+    }
+
+    pub fn to_lump(&self) -> Vec<u8> {
+        let size_vecs = self.vecs.first().map_or(0, |v| (v.len() + 7) / 8);
+
+        let mut buf = Vec::with_capacity(8 + (self.vecs.len() * size_vecs));
+        push_i32(&mut buf, self.vecs.len() as i32);
+        push_i32(&mut buf, size_vecs as i32);
+        for vec in self.vecs.iter() {
+            buf.extend_from_slice(&vec.to_bytes());
+        }
+
+        buf
+    }
+
     /// Returns true if `looking` is visible from `from` according to visdata.
     pub fn visible_from(&self, from: usize, looking: usize) -> bool {
-        self.vecs[from][looking]
+        // Clusters are numbered from the least significant bit of each byte, but BitVec starts at the most significant.
+        self.vecs[from][(looking & !7) | (7 - (looking & 7))]
+    }
+}
+
+/// Run-length decompress a visibility vector for `n_clusters` clusters, as used by Quake 1, Quake 2 & Source.
+/// Runs of zero bytes are stored as a zero followed by the number of zero bytes.
+/// The result is indexed by cluster, unlike `VisDataLump` where bits are in file order.
+/// Nothing is allocated up front, so a large `n_clusters` can't allocate more than the data decompresses to.
+pub fn decompress_vis(data: &[u8], n_clusters: usize) -> Result<BitVec> {
+    let n_bytes = (n_clusters + 7) / 8;
+    let mut bytes = Vec::new();
+
+    let mut i = 0;
+    while bytes.len() < n_bytes {
+        match data.get(i) {
+            Some(0) => {
+                let count = *data
+                    .get(i + 1)
+                    .ok_or_else(|| invalid_error!("Visibility run is truncated"))?;
+                bytes.resize((bytes.len() + count as usize).min(n_bytes), 0);
+                i += 2;
+            }
+            Some(b) => {
+                bytes.push(*b);
+                i += 1;
+            }
+            None => return Err(invalid_error!("Visibility data is truncated")),
+        }
+    }
+
+    Ok(BitVec::from_fn(n_clusters, |c| bytes[c / 8] & (1 << (c % 8)) != 0))
+}
+
+/// Cluster visibility stored as run-length compressed vectors, as used by Quake 2 & Source.
+/// Vectors are decompressed when loaded.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClusterVisLump {
+    /// Potentially visible set. The clusters that may be visible from each cluster.
+    pub pvs: Box<[BitVec]>,
+
+    /// Potentially hearable set. The clusters that sound from each cluster may reach.
+    pub phs: Box<[BitVec]>,
+}
+
+impl ClusterVisLump {
+    /// Parse the visibility lump.
+    /// # Format
+    /// int numclusters
+    /// int bitofs[numclusters][2]  Offsets of the compressed PVS & PHS for each cluster, from the start of the lump.
+    ///
+    /// `max_clusters` is the number of clusters the leaves use, and `numclusters` can't be more than it.
+    /// Vectors are decompressed to `numclusters` bits each, and may share their compressed data,
+    /// so without a limit a small lump could decompress to gigabytes.
+    pub fn from_lump(data: &[u8], max_clusters: usize) -> Result<ClusterVisLump> {
+        if data.is_empty() {
+            return Ok(ClusterVisLump {
+                pvs: Box::new([]),
+                phs: Box::new([]),
+            });
+        }
+
+        if data.len() < 4 {
+            return Err(Error::OutOfBounds {
+                lump: "Visibility",
+                offset: 0,
+                length: 4,
+            });
+        }
+
+        let n_clusters = slice_to_i32(&data[0..4]);
+        if n_clusters < 0 || n_clusters as usize > max_clusters {
+            return Err(value_error!(Location::lump("Visibility"), "numclusters", n_clusters));
+        }
+        let n_clusters = n_clusters as usize;
+        if (data.len() - 4) / 8 < n_clusters {
+            return Err(Error::OutOfBounds {
+                lump: "Visibility",
+                offset: 4,
+                length: n_clusters.saturating_mul(8),
+            });
+        }
+
+        let mut pvs = Vec::with_capacity(n_clusters);
+        let mut phs = Vec::with_capacity(n_clusters);
+        for n in 0..n_clusters {
+            let base = 4 + (n * 8);
+            pvs.push(ClusterVisLump::get_vec(data, n, base, n_clusters)?);
+            phs.push(ClusterVisLump::get_vec(data, n, base + 4, n_clusters)?);
+        }
+
+        Ok(ClusterVisLump {
+            pvs: pvs.into_boxed_slice(),
+            phs: phs.into_boxed_slice(),
+        })
+    }
+
+    /// Internal function. Decompresses the vector for `cluster` whose offset is stored at `field` in the lump.
+    fn get_vec(data: &[u8], cluster: usize, field: usize, n_clusters: usize) -> Result<BitVec> {
+        let offset = slice_to_i32(&data[field..field + 4]);
+        let compressed = data
+            .get(offset as usize..)
+            .ok_or_else(|| value_error!(Location::record("Visibility", cluster, field), "bitofs", offset))?;
+
+        // the run carries on past the end of the lump
+        decompress_vis(compressed, n_clusters).map_err(|_| Error::OutOfBounds {
+            lump: "Visibility",
+            offset: offset as usize,
+            length: compressed.len(),
+        })
+    }
+
+    /// Returns true if `looking` is potentially visible from `from`.
+    /// Clusters with no visibility information are always visible.
+    pub fn visible_from(&self, from: usize, looking: usize) -> bool {
+        self.pvs
+            .get(from)
+            .and_then(|v| v.get(looking))
+            .unwrap_or(true)
     }
 }
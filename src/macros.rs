@@ -20,5 +20,33 @@
 macro_rules! invalid_error {
 	($e:expr) => (crate::types::Error::Invalid {
 		error: $e.to_owned()
+	});
+	($fmt:expr, $($arg:tt)+) => (crate::types::Error::Invalid {
+		error: format!($fmt, $($arg)+)
 	})
-}
\ No newline at end of file
+}
+
+macro_rules! size_error {
+	($lump:expr, $length:expr, $size:expr) => (crate::types::Error::BadSize {
+		lump: $lump,
+		length: $length,
+		record_size: $size
+	})
+}
+
+macro_rules! reference_error {
+	($at:expr, $field:expr, $target:expr, $value:expr) => (crate::types::Error::BadReference {
+		at: $at,
+		field: $field,
+		target: $target,
+		value: $value as i64
+	})
+}
+
+macro_rules! value_error {
+	($at:expr, $field:expr, $value:expr) => (crate::types::Error::BadValue {
+		at: $at,
+		field: $field,
+		value: $value.to_string()
+	})
+}
@@ -0,0 +1,421 @@
+// Copyright (C) 2019 Oscar Shrimpton
+//
+// This file is part of stockton-bsp.
+//
+// stockton-bsp is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// stockton-bsp is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with stockton-bsp.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Indexed triangle meshes built from faces, ready to be uploaded for rendering.
+//!
+//! Polygon & mesh faces list their triangles in the meshverts lump. Each meshvert is an offset from the
+//! face's *first vertex*, not an index into the vertices lump, so the two have to be added together.
+
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+use na::Vector3;
+
+use crate::lumps::faces::{Face, FaceType};
+#[cfg(test)]
+use crate::lumps::test_util::{test_face, test_patch, test_vertices};
+use crate::lumps::vertices::{TexCoord, Vertex};
+use crate::lumps::{MeshVertsLump, VerticesLump};
+use crate::types::{Location, Result, RGBA};
+use crate::BSPFile;
+
+/// What to do with `FaceType::Billboard` faces, which are a single point rather than any triangles.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Billboards {
+    /// Leave them out.
+    Skip,
+
+    /// Add a `Point` for each, and leave drawing them to the caller.
+    Points,
+
+    /// Add a square quad `size` units across for each, facing `camera`.
+    Quads { camera: Vector3<f32>, size: f32 },
+}
+
+/// Options for building meshes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MeshOptions {
+    /// How many times to subdivide each patch in each direction. See `Face::tessellate`.
+    pub tessellation: usize,
+
+    pub billboards: Billboards,
+}
+
+impl Default for MeshOptions {
+    fn default() -> MeshOptions {
+        MeshOptions {
+            tessellation: 5,
+            billboards: Billboards::Points,
+        }
+    }
+}
+
+/// A billboard face, as a single point.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Point {
+    /// Index of the face in the faces lump.
+    pub face_idx: usize,
+    pub position: Vector3<f32>,
+    pub normal: Vector3<f32>,
+
+    /// From 0.0 to 1.0.
+    pub color: Vector3<f32>,
+}
+
+/// The triangles of a single face.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct FaceMesh {
+    pub vertices: Vec<Vertex>,
+
+    /// Triangle list indexing into `vertices`, wound the same way as the file.
+    pub indices: Vec<u32>,
+
+    /// Only used for billboards, with `Billboards::Points`.
+    pub points: Vec<Point>,
+}
+
+/// The render state shared by every face in a batch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct BatchKey {
+    pub texture_idx: usize,
+    pub lightmap_idx: Option<usize>,
+    pub effect_idx: Option<usize>,
+}
+
+impl BatchKey {
+    pub fn of(face: &Face) -> BatchKey {
+        BatchKey {
+            texture_idx: face.texture_idx,
+            lightmap_idx: face.lightmap_idx,
+            effect_idx: face.effect_idx,
+        }
+    }
+}
+
+/// A group of faces that can be drawn together, as ranges of the buffers in a `Mesh`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Batch {
+    pub key: BatchKey,
+    pub vertices: Range<usize>,
+    pub indices: Range<usize>,
+    pub points: Range<usize>,
+
+    /// The faces in this batch, in the order they were added.
+    pub faces: Vec<usize>,
+}
+
+/// Faces grouped into batches, with every batch's vertices, indices & points stored contiguously.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Mesh {
+    pub vertices: Vec<Vertex>,
+
+    /// Triangle list indexing into `vertices`. Indices are from the start of `vertices`, not the batch.
+    pub indices: Vec<u32>,
+
+    pub points: Vec<Point>,
+
+    /// Sorted by key.
+    pub batches: Vec<Batch>,
+}
+
+impl Mesh {
+    /// Build a mesh of every face in the file.
+    pub fn new(file: &BSPFile, options: &MeshOptions) -> Result<Mesh> {
+        Mesh::from_faces(file, 0..file.faces.faces.len(), options)
+    }
+
+    /// Build a mesh of the given faces, eg. a model's `faces_idx` or the output of `BSPTree::visible_faces`.
+    /// Faces that don't exist are ignored.
+    pub fn from_faces<I>(file: &BSPFile, faces: I, options: &MeshOptions) -> Result<Mesh>
+    where
+        I: IntoIterator<Item = usize>,
+    {
+        let mut batches: BTreeMap<BatchKey, (Vec<usize>, Vec<FaceMesh>)> = BTreeMap::new();
+        for face_idx in faces {
+            let face = match file.faces.faces.get(face_idx) {
+                Some(face) => face,
+                None => continue,
+            };
+
+            let mut mesh = face_mesh(face, &file.vertices, &file.meshverts, options)?;
+            for point in mesh.points.iter_mut() {
+                point.face_idx = face_idx;
+            }
+
+            let batch = batches.entry(BatchKey::of(face)).or_default();
+            batch.0.push(face_idx);
+            batch.1.push(mesh);
+        }
+
+        let mut mesh = Mesh::default();
+        for (key, (faces, meshes)) in batches {
+            let vertices_start = mesh.vertices.len();
+            let indices_start = mesh.indices.len();
+            let points_start = mesh.points.len();
+
+            for face in meshes {
+                let base = mesh.vertices.len() as u32;
+                mesh.indices.extend(face.indices.iter().map(|i| i + base));
+                mesh.vertices.extend(face.vertices);
+                mesh.points.extend(face.points);
+            }
+
+            mesh.batches.push(Batch {
+                key,
+                vertices: vertices_start..mesh.vertices.len(),
+                indices: indices_start..mesh.indices.len(),
+                points: points_start..mesh.points.len(),
+                faces,
+            });
+        }
+
+        Ok(mesh)
+    }
+}
+
+/// Build the triangles for a single face.
+/// Polygons & meshes use their meshverts, or a triangle fan if they have none.
+/// `Point::face_idx` is left as 0, since faces don't know their own index.
+pub fn face_mesh(
+    face: &Face,
+    vertices: &VerticesLump,
+    meshverts: &MeshVertsLump,
+    options: &MeshOptions,
+) -> Result<FaceMesh> {
+    match face.face_type {
+        FaceType::Polygon | FaceType::Mesh => {
+            let face_vertices = vertices
+                .vertices
+                .get(face.vertices_idx.clone())
+                .ok_or_else(|| invalid_error!("Face references vertices that don't exist"))?;
+            let n_vertices = face_vertices.len() as u32;
+
+            let indices = if face.meshverts_idx.is_empty() {
+                (1..n_vertices.saturating_sub(1))
+                    .flat_map(|i| vec![0, i, i + 1])
+                    .collect()
+            } else {
+                let mut indices = Vec::with_capacity(face.meshverts_idx.len());
+                for n in face.meshverts_idx.clone() {
+                    let offset = meshverts
+                        .meshverts
+                        .get(n)
+                        .ok_or_else(|| invalid_error!("Face references meshverts that don't exist"))?
+                        .offset;
+
+                    // relative to the face's first vertex
+                    if offset < 0 || offset as u32 >= n_vertices {
+                        let at = Location::record("MeshVerts", n, n * 4);
+                        return Err(reference_error!(at, "offset", "Vertex", offset));
+                    }
+
+                    indices.push(offset as u32);
+                }
+
+                indices
+            };
+
+            Ok(FaceMesh {
+                vertices: face_vertices.to_vec(),
+                indices,
+                points: Vec::new(),
+            })
+        }
+        FaceType::Patch => {
+            let patch = face.tessellate(vertices, options.tessellation)?;
+
+            Ok(FaceMesh {
+                vertices: patch.vertices,
+                indices: patch.indices,
+                points: Vec::new(),
+            })
+        }
+        FaceType::Billboard => {
+            // Billboards keep their position & colour where the lightmap origin & first lightmap vector would be.
+            let point = Point {
+                face_idx: 0,
+                position: face.map_origin,
+                normal: face.normal,
+                color: face.map_vecs[0],
+            };
+
+            match options.billboards {
+                Billboards::Skip => Ok(FaceMesh::default()),
+                Billboards::Points => Ok(FaceMesh {
+                    points: vec![point],
+                    ..FaceMesh::default()
+                }),
+                Billboards::Quads { camera, size } => Ok(billboard_quad(&point, camera, size)),
+            }
+        }
+    }
+}
+
+/// Internal function. Builds a quad centred on the point, facing the camera and upright if possible.
+fn billboard_quad(point: &Point, camera: Vector3<f32>, size: f32) -> FaceMesh {
+    let mut forward = point.position - camera;
+    if forward.norm() <= f32::EPSILON {
+        // camera is on the point, so face along -x
+        forward = Vector3::x();
+    }
+    let mut right = forward.cross(&Vector3::z());
+    if right.norm() <= f32::EPSILON {
+        // looking straight up or down
+        right = Vector3::x();
+    }
+    let right = right.normalize() * (size / 2.0);
+    let up = right.cross(&forward).normalize() * (size / 2.0);
+
+    let channel = |c: f32| (c * 255.0).round().clamp(0.0, 255.0) as u8;
+    let color = RGBA {
+        r: channel(point.color.x),
+        g: channel(point.color.y),
+        b: channel(point.color.z),
+        a: 255,
+    };
+    let normal = -forward.normalize();
+
+    let corner = |x: f32, y: f32| Vertex {
+        position: point.position + (right * x) + (up * y),
+        tex: TexCoord {
+            u: [(x + 1.0) / 2.0, (1.0 - y) / 2.0],
+            v: [0.0, 0.0],
+        },
+        normal,
+        color,
+    };
+
+    FaceMesh {
+        // bottom left, top left, top right, bottom right
+        vertices: vec![corner(-1.0, -1.0), corner(-1.0, 1.0), corner(1.0, 1.0), corner(1.0, -1.0)],
+
+        // clockwise as seen from the camera, like the faces in the file
+        indices: vec![0, 1, 2, 0, 2, 3],
+        points: Vec::new(),
+    }
+}
+
+#[test]
+fn meshverts_are_relative_to_first_vertex() {
+    use crate::lumps::vertices::MeshVert;
+
+    let vertices = test_vertices(na::Vector2::new(8, 1));
+    let meshverts = MeshVertsLump {
+        meshverts: vec![MeshVert { offset: 9 }, MeshVert { offset: 0 }, MeshVert { offset: 2 }, MeshVert { offset: 1 }]
+            .into_boxed_slice(),
+    };
+
+    let face = test_face(FaceType::Polygon, 4..7, 1..4);
+    let mesh = face_mesh(&face, &vertices, &meshverts, &MeshOptions::default()).unwrap();
+    assert_eq!(mesh.vertices, &vertices.vertices[4..7]);
+    assert_eq!(mesh.indices, vec![0, 2, 1]);
+
+    // offsets past the face's own vertices are rejected, even if the vertex exists
+    let face = test_face(FaceType::Mesh, 4..7, 0..3);
+    assert!(face_mesh(&face, &vertices, &meshverts, &MeshOptions::default()).is_err());
+
+    // no meshverts gives a fan
+    let face = test_face(FaceType::Polygon, 2..7, 0..0);
+    let mesh = face_mesh(&face, &vertices, &meshverts, &MeshOptions::default()).unwrap();
+    assert_eq!(mesh.indices, vec![0, 1, 2, 0, 2, 3, 0, 3, 4]);
+}
+
+#[test]
+fn billboard_quad_faces_camera() {
+    let vertices = test_vertices(na::Vector2::new(0, 0));
+    let meshverts = MeshVertsLump {
+        meshverts: Box::new([]),
+    };
+
+    let mut face = test_face(FaceType::Billboard, 0..0, 0..0);
+    face.map_origin = Vector3::new(10.0, 0.0, 0.0);
+    face.map_vecs[0] = Vector3::new(1.0, 0.5, 0.0);
+
+    let options = MeshOptions {
+        billboards: Billboards::Points,
+        ..MeshOptions::default()
+    };
+    let mesh = face_mesh(&face, &vertices, &meshverts, &options).unwrap();
+    assert!(mesh.indices.is_empty());
+    assert_eq!(mesh.points[0].position, face.map_origin);
+
+    let options = MeshOptions {
+        billboards: Billboards::Quads {
+            camera: Vector3::new(0.0, 0.0, 0.0),
+            size: 2.0,
+        },
+        ..MeshOptions::default()
+    };
+    let mesh = face_mesh(&face, &vertices, &meshverts, &options).unwrap();
+    assert_eq!(mesh.vertices.len(), 4);
+    for vertex in mesh.vertices.iter() {
+        // in a plane facing the camera, one unit from the centre in each direction
+        assert!((vertex.position.x - 10.0).abs() < 1e-5);
+        assert!((vertex.position.y.abs() - 1.0).abs() < 1e-5);
+        assert!((vertex.position.z.abs() - 1.0).abs() < 1e-5);
+        assert_eq!(vertex.normal, Vector3::new(-1.0, 0.0, 0.0));
+        assert_eq!((vertex.color.r, vertex.color.g, vertex.color.b), (255, 128, 0));
+    }
+
+    // clockwise from the camera: seen from -x looking along +x, left is +y
+    let a = mesh.vertices[mesh.indices[0] as usize].position;
+    let b = mesh.vertices[mesh.indices[1] as usize].position;
+    let c = mesh.vertices[mesh.indices[2] as usize].position;
+    assert!((b - a).cross(&(c - a)).x > 0.0);
+
+    let options = MeshOptions {
+        billboards: Billboards::Skip,
+        ..MeshOptions::default()
+    };
+    assert_eq!(face_mesh(&face, &vertices, &meshverts, &options).unwrap(), FaceMesh::default());
+}
+
+#[test]
+fn billboard_quad_at_camera() {
+    let point = Point {
+        face_idx: 0,
+        position: Vector3::new(10.0, 0.0, 0.0),
+        normal: Vector3::new(0.0, 0.0, 1.0),
+        color: Vector3::new(1.0, 1.0, 1.0),
+    };
+
+    // with no direction to the camera, it still gets a finite quad of the right size
+    let mesh = billboard_quad(&point, point.position, 2.0);
+    for vertex in mesh.vertices.iter() {
+        assert!(vertex.position.iter().all(|c| c.is_finite()));
+        assert!(vertex.normal.iter().all(|c| c.is_finite()));
+        assert!(((vertex.position - point.position).norm() - 2f32.sqrt()).abs() < 1e-5);
+    }
+}
+
+#[test]
+fn patches_are_tessellated() {
+    use na::Vector2;
+
+    let (face, vertices) = test_patch(Vector2::new(3, 3));
+    let meshverts = MeshVertsLump {
+        meshverts: Box::new([]),
+    };
+
+    let options = MeshOptions {
+        tessellation: 4,
+        ..MeshOptions::default()
+    };
+    let mesh = face_mesh(&face, &vertices, &meshverts, &options).unwrap();
+    assert_eq!(mesh.vertices.len(), 5 * 5);
+    assert_eq!(mesh.indices.len(), 4 * 4 * 6);
+}
@@ -0,0 +1,115 @@
+// Copyright (C) 2019 Oscar Shrimpton
+//
+// This file is part of stockton-bsp.
+//
+// stockton-bsp is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// stockton-bsp is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with stockton-bsp.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Choosing which lumps to load.
+
+use std::io::{Read, Seek};
+use std::path::Path;
+
+use crate::reader::BSPReader;
+use crate::types::Result;
+use crate::{BSPFile, BSPFileRef};
+
+/// Which lumps to load into a `BSPFile`. Everything is loaded by default.
+///
+/// Skipped lumps are left empty, or `None` for the advertisements, and aren't read at all by `from_reader`.
+/// References to them aren't checked, so faces may refer to lightmaps that weren't loaded.
+/// A file loaded without the tree or lightmaps can't be written back, and `to_buffer` returns an error for it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LoadOptions {
+    pub(crate) light_maps: bool,
+    pub(crate) light_vols: bool,
+    pub(crate) visdata: bool,
+    pub(crate) advertisements: bool,
+    pub(crate) tree: bool,
+}
+
+impl Default for LoadOptions {
+    fn default() -> LoadOptions {
+        LoadOptions {
+            light_maps: true,
+            light_vols: true,
+            visdata: true,
+            advertisements: true,
+            tree: true,
+        }
+    }
+}
+
+impl LoadOptions {
+    /// Load everything.
+    pub fn new() -> LoadOptions {
+        LoadOptions::default()
+    }
+
+    /// Whether to load the lightmaps. If not, face lightmap indices aren't checked.
+    pub fn light_maps(mut self, load: bool) -> LoadOptions {
+        self.light_maps = load;
+        self
+    }
+
+    /// Whether to load the light volumes.
+    pub fn light_vols(mut self, load: bool) -> LoadOptions {
+        self.light_vols = load;
+        self
+    }
+
+    /// Whether to load the visdata. If not, `BSPTree::visible_faces` treats everything as visible.
+    pub fn visdata(mut self, load: bool) -> LoadOptions {
+        self.visdata = load;
+        self
+    }
+
+    /// Whether to load the advertisements, for Quake Live maps.
+    pub fn advertisements(mut self, load: bool) -> LoadOptions {
+        self.advertisements = load;
+        self
+    }
+
+    /// Whether to load the nodes, leaves, leaf faces & leaf brushes. If not, the tree is `BSPTree::empty()`.
+    pub fn tree(mut self, load: bool) -> LoadOptions {
+        self.tree = load;
+        self
+    }
+
+    /// If the lump at the given index will be loaded.
+    pub fn loads_lump(&self, index: usize) -> bool {
+        match index {
+            3..=6 => self.tree,
+            14 => self.light_maps,
+            15 => self.light_vols,
+            16 => self.visdata,
+            17 => self.advertisements,
+            _ => true,
+        }
+    }
+
+    /// Parse the given buffer.
+    pub fn from_buffer(&self, buf: &[u8]) -> Result<BSPFile> {
+        BSPFileRef::from_buffer(buf)?.parse_with(self)
+    }
+
+    /// Read & parse the file at the given path, like `BSPFile::open`.
+    pub fn open<P: AsRef<Path>>(&self, path: P) -> Result<BSPFile> {
+        self.from_buffer(&std::fs::read(path)?)
+    }
+
+    /// Parse a file from anything that can seek, only reading the lumps that are loaded.
+    pub fn from_reader<R: Read + Seek>(&self, reader: R) -> Result<BSPFile> {
+        BSPReader::new(reader)?.parse_with(self)
+    }
+}
@@ -0,0 +1,196 @@
+// Copyright (C) 2019 Oscar Shrimpton
+//
+// This file is part of stockton-bsp.
+//
+// stockton-bsp is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// stockton-bsp is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with stockton-bsp.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Parses the miptex lump of a Quake 1 or GoldSrc file
+
+use crate::lumps::helpers::{slice_to_cstr, slice_to_i32, slice_to_u16, slice_to_u32};
+use crate::types::{Error, Location, Result};
+
+const MIPTEX_HEADER_SIZE: usize = 16 + 4 + 4 + (4 * 4);
+
+/// The number of mip levels stored for each texture.
+pub const MIP_LEVELS: usize = 4;
+
+/// Textures, which may have their pixels embedded in the file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MipTexLump {
+    /// `None` where the file has a missing texture.
+    pub textures: Box<[Option<MipTex>]>,
+}
+
+/// A texture, along with its pixels if they're embedded in the file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MipTex {
+    pub name: String,
+    pub width: u32,
+    pub height: u32,
+
+    /// Palette indices for each mip level, each half the size of the last.
+    /// `None` if the texture is stored externally, e.g. in a WAD file.
+    pub mips: Option<[Box<[u8]>; MIP_LEVELS]>,
+
+    /// The RGB palette stored after the pixels in GoldSrc files.
+    /// Quake 1 files use an external palette (`gfx/palette.lmp`).
+    pub palette: Option<Box<[u8]>>,
+}
+
+impl MipTexLump {
+    /// Parse the miptex lump. Palettes are only read if `has_palettes` is set, as in GoldSrc files.
+    /// # Format
+    /// int nummiptex
+    /// int dataofs[nummiptex]   Offset of each texture from the start of the lump, or -1 if missing.
+    /// Each texture is:
+    /// string[16] name
+    /// uint width
+    /// uint height
+    /// uint offsets[4]          Offset of each mip level from the start of the texture, or 0 if external.
+    pub fn from_lump(lump: &[u8], has_palettes: bool) -> Result<MipTexLump> {
+        if lump.is_empty() {
+            return Ok(MipTexLump {
+                textures: Box::new([]),
+            });
+        }
+
+        if lump.len() < 4 {
+            return Err(Error::OutOfBounds {
+                lump: "MipTex",
+                offset: 0,
+                length: 4,
+            });
+        }
+
+        let n_textures = slice_to_i32(&lump[0..4]);
+        if n_textures < 0 {
+            return Err(value_error!(Location::lump("MipTex"), "nummiptex", n_textures));
+        }
+        if (lump.len() - 4) / 4 < n_textures as usize {
+            return Err(Error::OutOfBounds {
+                lump: "MipTex",
+                offset: 4,
+                length: n_textures as usize * 4,
+            });
+        }
+
+        let mut textures = Vec::with_capacity(n_textures as usize);
+        for n in 0..n_textures as usize {
+            let offset = slice_to_i32(&lump[4 + (n * 4)..8 + (n * 4)]);
+            if offset < 0 {
+                textures.push(None);
+                continue;
+            }
+
+            let at = Location::record("MipTex", n, 4 + (n * 4));
+            let data = lump
+                .get(offset as usize..)
+                .ok_or_else(|| value_error!(at, "dataofs", offset))?;
+            textures.push(Some(
+                MipTex::from_slice(data, has_palettes).map_err(|e| e.at_record(n, offset as usize))?,
+            ));
+        }
+
+        Ok(MipTexLump {
+            textures: textures.into_boxed_slice(),
+        })
+    }
+}
+
+impl MipTex {
+    /// Parse a texture from the start of the given slice, which may carry on past the end of the texture.
+    /// Error locations are relative to the start of the slice.
+    pub fn from_slice(data: &[u8], has_palette: bool) -> Result<MipTex> {
+        if data.len() < MIPTEX_HEADER_SIZE {
+            return Err(value_error!(Location::field("MipTex", 0), "length", data.len()));
+        }
+
+        let name = slice_to_cstr(&data[0..16])?.to_owned();
+        let width = slice_to_u32(&data[16..20]);
+        let height = slice_to_u32(&data[20..24]);
+
+        let mut offsets = [0; MIP_LEVELS];
+        for (n, offset) in offsets.iter_mut().enumerate() {
+            *offset = slice_to_u32(&data[24 + (n * 4)..28 + (n * 4)]) as usize;
+        }
+
+        if offsets[0] == 0 {
+            return Ok(MipTex {
+                name,
+                width,
+                height,
+                mips: None,
+                palette: None,
+            });
+        }
+
+        let mut mips: [Box<[u8]>; MIP_LEVELS] = Default::default();
+        let mut end = 0;
+        for (level, mip) in mips.iter_mut().enumerate() {
+            let size = ((width >> level) as usize)
+                .checked_mul((height >> level) as usize)
+                .ok_or_else(|| value_error!(Location::field("MipTex", 16), "width", width))?;
+
+            *mip = offsets[level]
+                .checked_add(size)
+                .and_then(|end| data.get(offsets[level]..end))
+                .ok_or_else(|| value_error!(Location::field("MipTex", 24 + (level * 4)), "offsets", offsets[level]))?
+                .into();
+            end = offsets[level] + size;
+        }
+
+        let palette = if has_palette {
+            let n_colours = data
+                .get(end..end + 2)
+                .map(slice_to_u16)
+                .ok_or_else(|| value_error!(Location::field("MipTex", end), "palette", "missing"))? as usize;
+
+            Some(
+                data.get(end + 2..end + 2 + (n_colours * 3))
+                    .ok_or_else(|| value_error!(Location::field("MipTex", end), "palette", n_colours))?
+                    .into(),
+            )
+        } else {
+            None
+        };
+
+        Ok(MipTex {
+            name,
+            width,
+            height,
+            mips: Some(mips),
+            palette,
+        })
+    }
+
+    /// Decode the given mip level to RGBA8 pixels, using the embedded palette or `palette` if there isn't one.
+    /// Textures whose name starts with `{` use the last palette entry for transparency, as in GoldSrc.
+    /// Returns `None` if the pixels or a palette aren't available.
+    pub fn to_rgba(&self, level: usize, palette: Option<&[u8]>) -> Option<Vec<u8>> {
+        let pixels = self.mips.as_ref()?.get(level)?;
+        let palette = self.palette.as_deref().or(palette)?;
+        let transparent = self.name.starts_with('{');
+
+        let mut rgba = Vec::with_capacity(pixels.len() * 4);
+        for index in pixels.iter() {
+            let i = *index as usize * 3;
+            let colour = palette.get(i..i + 3).unwrap_or(&[0, 0, 0]);
+
+            rgba.extend_from_slice(colour);
+            rgba.push(if transparent && *index == 255 { 0 } else { 255 });
+        }
+
+        Some(rgba)
+    }
+}
@@ -0,0 +1,316 @@
+// Copyright (C) 2019 Oscar Shrimpton
+//
+// This file is part of stockton-bsp.
+//
+// stockton-bsp is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// stockton-bsp is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with stockton-bsp.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Quake 1 (BSP29) and GoldSrc (BSP30) files.
+//! These have no magic number, and store texture pixels & clipping hulls in the file.
+
+mod miptex;
+mod models;
+mod texinfo;
+mod tree;
+mod visibility;
+
+pub use self::miptex::{MipTex, MipTexLump, MIP_LEVELS};
+pub use self::models::{Model, ModelsLump, MAX_HULLS};
+pub use self::texinfo::{TexInfo, TexInfoLump};
+pub use self::tree::{ClipNode, ClipRef, Contents, Leaf, Node, Tree};
+pub use self::visibility::LeafVisLump;
+
+use crate::directory::{read_header, DirEntry};
+use crate::lumps::*;
+use crate::types::{Error, Result};
+
+/// The version number used by Quake 1 files.
+pub const Q1_VERSION: u32 = 29;
+
+/// The version number used by GoldSrc (Half-Life) files.
+pub const GOLDSRC_VERSION: u32 = 30;
+
+/// The name of each lump in a Quake 1 or GoldSrc file, in directory order.
+pub const Q1_LUMP_NAMES: [&str; 15] = [
+    "Entities",
+    "Planes",
+    "MipTex",
+    "Vertices",
+    "Visibility",
+    "Nodes",
+    "TexInfo",
+    "Faces",
+    "Lighting",
+    "ClipNodes",
+    "Leaves",
+    "MarkSurfaces",
+    "Edges",
+    "SurfEdges",
+    "Models",
+];
+
+/// The header found at the start of a Quake 1 or GoldSrc bsp file.
+#[derive(Clone, Copy, Debug)]
+pub struct Q1Header {
+    pub version: u32,
+    pub dir_entries: [DirEntry; 15],
+}
+
+impl Q1Header {
+    /// Deserialise from buffer.
+    /// # Format
+    /// int version                 Version number. 29 for Quake 1, 30 for GoldSrc.
+    /// direntry[15] direntries     Lump directory, fifteen entries.
+    pub fn from(v: &[u8]) -> Result<Q1Header> {
+        let (version, dir_entries) = read_header(v, &[])?;
+
+        Ok(Q1Header {
+            version,
+            dir_entries,
+        })
+    }
+
+    /// Get the lump at given index from the buffer, checking it's inside the buffer.
+    pub fn get_lump<'l>(&self, buf: &'l [u8], index: usize) -> Result<&'l [u8]> {
+        let entry = self.dir_entries[index];
+        entry.get_lump(buf).ok_or(Error::OutOfBounds {
+            lump: Q1_LUMP_NAMES[index],
+            offset: entry.offset as usize,
+            length: entry.length as usize,
+        })
+    }
+}
+
+/// Represents a parsed Quake 1 or GoldSrc BSP file.
+#[derive(Debug, Clone)]
+pub struct Q1BSPFile {
+    pub directory: Q1Header,
+    pub entities: EntitiesLump,
+    pub planes: PlanesLump,
+    pub textures: MipTexLump,
+    pub vertices: PositionsLump,
+    pub visibility: LeafVisLump,
+    pub tree: Tree,
+    pub texinfo: TexInfoLump,
+    pub faces: EdgeFacesLump,
+
+    /// Raw lightmap samples, referred to by `EdgeFace::light_offset`.
+    /// One byte per sample for Quake 1, three (RGB) for GoldSrc.
+    pub lighting: Box<[u8]>,
+    pub edges: EdgesLump,
+    pub surf_edges: SurfEdgesLump,
+    pub models: ModelsLump,
+}
+
+impl Q1BSPFile {
+    /// Try to parse the given buffer as a Quake 1 or GoldSrc BSP file
+    pub fn from_buffer(buf: Box<[u8]>) -> Result<Q1BSPFile> {
+        let header = Q1Header::from(&buf)?;
+        if header.version != Q1_VERSION && header.version != GOLDSRC_VERSION {
+            return Err(Error::Unsupported {
+                version: header.version,
+            });
+        }
+
+        let lump = |i| header.get_lump(&buf, i);
+
+        let entities = EntitiesLump::from_lump(lump(0)?)?;
+        let planes = PlanesLump::from_typed_lump(lump(1)?)?;
+        let textures = MipTexLump::from_lump(lump(2)?, header.version == GOLDSRC_VERSION)?;
+        let vertices = PositionsLump::from_lump(lump(3)?)?;
+        let texinfo = TexInfoLump::from_lump(lump(6)?, &textures)?;
+        let edges = EdgesLump::from_lump(lump(12)?, &vertices)?;
+        let surf_edges = SurfEdgesLump::from_lump(lump(13)?, &edges)?;
+        let faces = EdgeFacesLump::from_lump(lump(7)?, &planes, &surf_edges, texinfo.texinfo.len())?;
+        let tree = Tree::from_lumps(lump(5)?, lump(10)?, lump(11)?, lump(9)?, &planes, &faces)?;
+        let models = ModelsLump::from_lump(lump(14)?, &faces, &tree)?;
+        let vis_leaves = models.models.first().map_or(0, |m| m.vis_leaves);
+        let visibility = LeafVisLump::from_lump(lump(4)?, &tree, vis_leaves)?;
+
+        Ok(Q1BSPFile {
+            directory: header,
+            entities,
+            planes,
+            textures,
+            vertices,
+            visibility,
+            tree,
+            texinfo,
+            faces,
+            lighting: lump(8)?.into(),
+            edges,
+            surf_edges,
+            models,
+        })
+    }
+}
+
+#[cfg(test)]
+fn synthetic_file(version: u32) -> Box<[u8]> {
+    use crate::directory::assemble;
+    use crate::lumps::test_util::{floats, ints, shorts};
+
+    // One 16x16 texture, with each mip level filled with its level number.
+    let mut miptex = ints(&[2, 12, -1]);
+    let mut name = b"{fence".to_vec();
+    name.resize(16, 0);
+    miptex.extend(name);
+    miptex.extend(ints(&[16, 16, 40, 40 + 256, 40 + 256 + 64, 40 + 256 + 64 + 16]));
+    for (level, size) in [256, 64, 16, 4].iter().enumerate() {
+        miptex.extend(vec![level as u8; *size]);
+    }
+    if version == GOLDSRC_VERSION {
+        miptex.extend(shorts(&[256]));
+        miptex.extend((0..768).map(|i| (i / 3) as u8));
+    }
+
+    let mut texinfo = floats(&[1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0]);
+    texinfo.extend(ints(&[0, 0]));
+
+    let mut face = shorts(&[0, 0]);
+    face.extend(ints(&[0]));
+    face.extend(shorts(&[3, 0]));
+    face.extend(&[0, 255, 255, 255]);
+    face.extend(ints(&[0]));
+
+    let mut node = ints(&[0]);
+    node.extend(shorts(&[-2, -1, -64, -64, -64, 64, 64, 64, 0, 1]));
+
+    // Leaf 0 is the shared solid leaf, leaf 1 is the empty space above the floor.
+    let mut leaves = ints(&[-2, -1]);
+    leaves.extend(shorts(&[0, 0, 0, 0, 0, 0, 0, 0]));
+    leaves.extend(&[0, 0, 0, 0]);
+    leaves.extend(ints(&[-1, 0]));
+    leaves.extend(shorts(&[-64, -64, 0, 64, 64, 64, 0, 1]));
+    leaves.extend(&[0, 64, 0, 0]);
+
+    let mut model = floats(&[-64.0, -64.0, -64.0, 64.0, 64.0, 64.0, 0.0, 0.0, 0.0]);
+    model.extend(ints(&[0, 0, 0, 0, 1, 0, 1]));
+
+    let lumps = vec![
+        b"{\n\"classname\" \"worldspawn\"\n}\n\0".to_vec(),
+        floats(&[0.0, 0.0, 1.0, 0.0, 2.0]),
+        miptex,
+        floats(&[0.0, 0.0, 0.0, 64.0, 0.0, 0.0, 0.0, 64.0, 0.0]),
+        vec![0x01],
+        node,
+        texinfo,
+        face,
+        vec![128; 4],
+        [ints(&[0]), shorts(&[-1, -2])].concat(),
+        leaves,
+        shorts(&[0]),
+        shorts(&[0, 0, 0, 1, 1, 2, 0, 2]),
+        ints(&[1, 2, -3]),
+        model,
+    ];
+
+    assemble(&[], version, &lumps)
+}
+
+#[test]
+fn q1_synthetic_file() {
+    use na::Vector3;
+
+    let file = Q1BSPFile::from_buffer(synthetic_file(Q1_VERSION)).unwrap();
+
+    assert_eq!(file.entities.entities[0].classname(), Some("worldspawn"));
+    assert_eq!(file.textures.textures.len(), 2);
+    assert_eq!(file.textures.textures[1], None);
+
+    let texture = file.textures.textures[0].as_ref().unwrap();
+    assert_eq!(texture.name, "{fence");
+    assert_eq!((texture.width, texture.height), (16, 16));
+    assert_eq!(texture.palette, None);
+    let mips = texture.mips.as_ref().unwrap();
+    for (level, mip) in mips.iter().enumerate() {
+        assert_eq!(mip.len(), 256 >> (level * 2));
+        assert!(mip.iter().all(|p| *p as usize == level));
+    }
+
+    // Quake 1 needs an external palette
+    assert_eq!(texture.to_rgba(0, None), None);
+    let palette: Vec<u8> = (0..768).map(|i| (i % 3) as u8 * 100).collect();
+    assert_eq!(&texture.to_rgba(1, Some(&palette)).unwrap()[0..4], &[0, 100, 200, 255]);
+
+    assert_eq!(
+        file.faces.faces[0].vertex_indices(&file.surf_edges, &file.edges).collect::<Vec<_>>(),
+        vec![0, 1, 2]
+    );
+
+    let model = &file.models.models[0];
+    assert_eq!(model.head_nodes, [0; MAX_HULLS]);
+    assert_eq!(model.vis_leaves, 1);
+
+    let above = file.tree.find_leaf(0, Vector3::new(0.0, 0.0, 8.0), &file.planes).unwrap();
+    assert_eq!(above.contents, Contents::Empty);
+    assert_eq!(above.ambient_levels, [0, 64, 0, 0]);
+    let below = file.tree.find_leaf(0, Vector3::new(0.0, 0.0, -8.0), &file.planes).unwrap();
+    assert_eq!(below.contents, Contents::Solid);
+
+    assert_eq!(
+        file.tree.hull_contents(model.head_nodes[1], Vector3::new(0.0, 0.0, 8.0), &file.planes),
+        Some(Contents::Empty)
+    );
+    assert_eq!(
+        file.tree.hull_contents(model.head_nodes[1], Vector3::new(0.0, 0.0, -8.0), &file.planes),
+        Some(Contents::Solid)
+    );
+
+    assert!(file.visibility.visible_from(1, 1));
+    assert!(!file.visibility.visible_from(1, 0));
+    assert_eq!(file.visibility.pvs[0], None);
+}
+
+#[test]
+fn goldsrc_synthetic_file() {
+    let file = Q1BSPFile::from_buffer(synthetic_file(GOLDSRC_VERSION)).unwrap();
+
+    let texture = file.textures.textures[0].as_ref().unwrap();
+    assert_eq!(texture.palette.as_ref().unwrap().len(), 768);
+
+    // The embedded palette is used, and index 255 is transparent for '{' textures
+    let rgba = texture.to_rgba(2, None).unwrap();
+    assert_eq!(rgba.len(), 16 * 4);
+    assert_eq!(&rgba[0..4], &[2, 2, 2, 255]);
+
+    let mut transparent = texture.clone();
+    transparent.mips.as_mut().unwrap()[3] = Box::new([255; 4]);
+    assert_eq!(&transparent.to_rgba(3, None).unwrap()[0..4], &[255, 255, 255, 0]);
+}
+
+#[test]
+fn q1_bad_file() {
+    use crate::types::Location;
+
+    let file = synthetic_file(Q1_VERSION);
+    let header = Q1Header::from(&file).unwrap();
+
+    // A mark surface pointing past the faces lump
+    let mut buf = file.to_vec();
+    buf[header.dir_entries[11].offset as usize] = 5;
+    match Q1BSPFile::from_buffer(buf.into()) {
+        Err(e @ Error::BadReference { field: "face", target: "Face", value: 5, .. }) => {
+            assert_eq!(e.location(), Some(Location::record("MarkSurfaces", 0, 0)))
+        }
+        other => panic!("expected a bad face reference, got {:?}", other),
+    }
+
+    // The lighting lump running off the end of the file
+    let mut buf = file.to_vec();
+    buf[4 + (8 * 8) + 4..4 + (8 * 8) + 8].copy_from_slice(&u32::MAX.to_le_bytes());
+    match Q1BSPFile::from_buffer(buf.into()) {
+        Err(Error::OutOfBounds { lump: "Lighting", .. }) => {}
+        other => panic!("expected the lighting lump to be out of bounds, got {:?}", other),
+    }
+}
@@ -0,0 +1,116 @@
+// Copyright (C) 2019 Oscar Shrimpton
+//
+// This file is part of stockton-bsp.
+//
+// stockton-bsp is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// stockton-bsp is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with stockton-bsp.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Parses the models lump of a Quake 1 or GoldSrc file
+
+use super::tree::Tree;
+use crate::lumps::helpers::{slice_to_i32, slice_to_vec3};
+use crate::lumps::EdgeFacesLump;
+use crate::types::{Location, Result};
+use na::Vector3;
+use std::ops::Range;
+
+const MODEL_SIZE: usize = (4 * 3 * 3) + (4 * 4) + (4 * 3);
+
+/// The number of hulls each model has.
+/// Hull 0 is the rendering tree, and the rest are clipping hulls for different box sizes.
+pub const MAX_HULLS: usize = 4;
+
+/// The world model & brush entities (doors, platforms, etc).
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModelsLump {
+    pub models: Box<[Model]>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Model {
+    pub mins: Vector3<f32>,
+    pub maxs: Vector3<f32>,
+    pub origin: Vector3<f32>,
+
+    /// The node each hull starts at.
+    /// The first is an index into `Tree::nodes`, and the rest are into `Tree::clip_nodes`.
+    pub head_nodes: [usize; MAX_HULLS],
+
+    /// The number of leaves with visibility information, not counting leaf 0.
+    pub vis_leaves: usize,
+    pub faces_idx: Range<usize>,
+}
+
+impl ModelsLump {
+    /// Parse the models lump.
+    /// # Format
+    /// float mins[3]
+    /// float maxs[3]
+    /// float origin[3]
+    /// int headnode[4]
+    /// int visleafs
+    /// int firstface
+    /// int numfaces
+    pub fn from_lump(lump: &[u8], faces: &EdgeFacesLump, tree: &Tree) -> Result<ModelsLump> {
+        if lump.len() % MODEL_SIZE != 0 {
+            return Err(size_error!("Models", lump.len(), MODEL_SIZE));
+        }
+
+        let mut models = Vec::with_capacity(lump.len() / MODEL_SIZE);
+        for (n, raw) in lump.chunks_exact(MODEL_SIZE).enumerate() {
+            let offset = n * MODEL_SIZE;
+            let mut head_nodes = [0; MAX_HULLS];
+            for (hull, head_node) in head_nodes.iter_mut().enumerate() {
+                let i = slice_to_i32(&raw[36 + (hull * 4)..40 + (hull * 4)]);
+                let (n_nodes, target) = if hull == 0 {
+                    (tree.nodes.len(), "Node")
+                } else {
+                    (tree.clip_nodes.len(), "ClipNode")
+                };
+
+                // Models with no clipping hull (such as triggers in some compilers) leave these zeroed.
+                if i < 0 || (i as usize >= n_nodes && i != 0) {
+                    let at = Location::record("Models", n, offset + 36 + (hull * 4));
+                    return Err(reference_error!(at, "headnode", target, i));
+                }
+
+                *head_node = i as usize;
+            }
+
+            let vis_leaves = slice_to_i32(&raw[52..56]);
+            if vis_leaves < 0 || vis_leaves as usize >= tree.leaves.len().max(1) {
+                return Err(value_error!(Location::record("Models", n, offset + 52), "visleafs", vis_leaves));
+            }
+
+            let start = slice_to_i32(&raw[56..60]);
+            let n_faces = slice_to_i32(&raw[60..64]);
+            if start < 0 || n_faces < 0 || start as usize + n_faces as usize > faces.faces.len() {
+                let at = Location::record("Models", n, offset + 56);
+                return Err(reference_error!(at, "faces", "Face", i64::from(start) + i64::from(n_faces)));
+            }
+
+            models.push(Model {
+                mins: slice_to_vec3(&raw[0..12]),
+                maxs: slice_to_vec3(&raw[12..24]),
+                origin: slice_to_vec3(&raw[24..36]),
+                head_nodes,
+                vis_leaves: vis_leaves as usize,
+                faces_idx: start as usize..(start + n_faces) as usize,
+            });
+        }
+
+        Ok(ModelsLump {
+            models: models.into_boxed_slice(),
+        })
+    }
+}
@@ -0,0 +1,78 @@
+// Copyright (C) 2019 Oscar Shrimpton
+//
+// This file is part of stockton-bsp.
+//
+// stockton-bsp is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// stockton-bsp is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with stockton-bsp.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Parses the texinfo lump of a Quake 1 or GoldSrc file
+
+use super::miptex::MipTexLump;
+use crate::lumps::helpers::{slice_to_f32, slice_to_i32, slice_to_u32};
+use crate::types::{Location, Result};
+
+const TEXINFO_SIZE: usize = (4 * 4 * 2) + 4 + 4;
+
+/// Texture projections.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TexInfoLump {
+    pub texinfo: Box<[TexInfo]>,
+}
+
+/// A record from a `TexInfoLump`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TexInfo {
+    /// The s & t projection vectors, with the offset as the last element.
+    pub vecs: [[f32; 4]; 2],
+    pub miptex_idx: usize,
+
+    /// Set for sky & liquid surfaces, which have no lightmap.
+    pub special: bool,
+}
+
+impl TexInfoLump {
+    /// Parse the texinfo lump.
+    /// # Format
+    /// float vecs[2][4]    s & t projection vectors, each with an offset.
+    /// int miptex          Index into the miptex lump.
+    /// int flags           1 if sky or liquid.
+    pub fn from_lump(lump: &[u8], miptex: &MipTexLump) -> Result<TexInfoLump> {
+        if lump.len() % TEXINFO_SIZE != 0 {
+            return Err(size_error!("TexInfo", lump.len(), TEXINFO_SIZE));
+        }
+
+        let mut texinfo = Vec::with_capacity(lump.len() / TEXINFO_SIZE);
+        for (i, raw) in lump.chunks_exact(TEXINFO_SIZE).enumerate() {
+            let mut vecs = [[0.0; 4]; 2];
+            for (n, v) in vecs.iter_mut().flatten().enumerate() {
+                *v = slice_to_f32(&raw[n * 4..(n + 1) * 4]);
+            }
+
+            let miptex_idx = slice_to_i32(&raw[32..36]);
+            if miptex_idx < 0 || miptex_idx as usize >= miptex.textures.len() {
+                let at = Location::record("TexInfo", i, (i * TEXINFO_SIZE) + 32);
+                return Err(reference_error!(at, "miptex", "MipTex", miptex_idx));
+            }
+
+            texinfo.push(TexInfo {
+                vecs,
+                miptex_idx: miptex_idx as usize,
+                special: slice_to_u32(&raw[36..40]) & 1 != 0,
+            });
+        }
+
+        Ok(TexInfoLump {
+            texinfo: texinfo.into_boxed_slice(),
+        })
+    }
+}
@@ -0,0 +1,318 @@
+// Copyright (C) 2019 Oscar Shrimpton
+//
+// This file is part of stockton-bsp.
+//
+// stockton-bsp is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// stockton-bsp is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with stockton-bsp.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Parses the nodes, leaves & clipnodes lumps of a Quake 1 or GoldSrc file
+
+use crate::lumps::helpers::{slice_to_i16, slice_to_i32, slice_to_u16, slice_to_vec3s};
+use crate::lumps::{EdgeFacesLump, NodeRef, PlanesLump};
+use crate::types::{Location, Result};
+use na::Vector3;
+use std::ops::Range;
+
+const NODE_SIZE: usize = 4 + (2 * 2) + (2 * 3 * 2) + (2 * 2);
+pub(super) const LEAF_SIZE: usize = 4 + 4 + (2 * 3 * 2) + (2 * 2) + 4;
+const CLIP_NODE_SIZE: usize = 4 + (2 * 2);
+
+/// The contents of a leaf or clipping hull region.
+/// Unlike later games these are values, not flags.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Contents {
+    Empty,
+    Solid,
+    Water,
+    Slime,
+    Lava,
+    Sky,
+    Origin,
+    Clip,
+
+    /// A current moving in the given direction, with up as 360 and down as 450 (GoldSrc only).
+    Current(u16),
+    Translucent,
+}
+
+impl Contents {
+    /// Interpret a contents value from a file.
+    pub fn from_i32(val: i32) -> Option<Contents> {
+        Some(match val {
+            -1 => Contents::Empty,
+            -2 => Contents::Solid,
+            -3 => Contents::Water,
+            -4 => Contents::Slime,
+            -5 => Contents::Lava,
+            -6 => Contents::Sky,
+            -7 => Contents::Origin,
+            -8 => Contents::Clip,
+            -14..=-9 => Contents::Current((-9 - val) as u16 * 90),
+            -15 => Contents::Translucent,
+            _ => return None,
+        })
+    }
+}
+
+/// The BSP tree used for rendering, and the clipping hulls used for collision.
+/// Nodes & leaves are kept in the order they're stored in the file, and refer to each other by index.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tree {
+    pub nodes: Box<[Node]>,
+    pub leaves: Box<[Leaf]>,
+    pub clip_nodes: Box<[ClipNode]>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Node {
+    pub plane_idx: usize,
+
+    /// Front & back children.
+    pub children: [NodeRef; 2],
+    pub mins: Vector3<i32>,
+    pub maxs: Vector3<i32>,
+
+    /// The faces on this node's plane.
+    pub faces_idx: Range<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Leaf {
+    pub contents: Contents,
+
+    /// Offset of this leaf's compressed visibility in the visibility lump, or `None` if it has none.
+    pub vis_offset: Option<usize>,
+    pub mins: Vector3<i32>,
+    pub maxs: Vector3<i32>,
+    pub faces_idx: Box<[usize]>,
+
+    /// Ambient sound levels for water, sky, slime & lava.
+    pub ambient_levels: [u8; 4],
+}
+
+/// A node in one of the clipping hulls, which are used for collision against boxes of fixed sizes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClipNode {
+    pub plane_idx: usize,
+
+    /// Front & back children.
+    pub children: [ClipRef; 2],
+}
+
+/// A child of a clip node.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ClipRef {
+    Node(usize),
+    Contents(Contents),
+}
+
+impl Tree {
+    /// Parse the nodes, leaves, mark surfaces & clipnodes lumps.
+    /// # Format
+    /// Each node is:
+    /// int planenum
+    /// short children[2]   Negative values are leaves, as `-(leaf + 1)`.
+    /// short mins[3]
+    /// short maxs[3]
+    /// ushort firstface
+    /// ushort numfaces
+    /// Each leaf is:
+    /// int contents
+    /// int visofs          -1 if no visibility info.
+    /// short mins[3]
+    /// short maxs[3]
+    /// ushort firstmarksurface
+    /// ushort nummarksurfaces
+    /// byte ambient_level[4]
+    /// Each clipnode is:
+    /// int planenum
+    /// short children[2]   Negative values are contents.
+    pub fn from_lumps(
+        nodes_lump: &[u8],
+        leaves_lump: &[u8],
+        mark_surfaces: &[u8],
+        clip_nodes_lump: &[u8],
+        planes: &PlanesLump,
+        faces: &EdgeFacesLump,
+    ) -> Result<Tree> {
+        if nodes_lump.len() % NODE_SIZE != 0 {
+            return Err(size_error!("Nodes", nodes_lump.len(), NODE_SIZE));
+        }
+        if leaves_lump.len() % LEAF_SIZE != 0 {
+            return Err(size_error!("Leaves", leaves_lump.len(), LEAF_SIZE));
+        }
+        if mark_surfaces.len() % 2 != 0 {
+            return Err(size_error!("MarkSurfaces", mark_surfaces.len(), 2));
+        }
+        if clip_nodes_lump.len() % CLIP_NODE_SIZE != 0 {
+            return Err(size_error!("ClipNodes", clip_nodes_lump.len(), CLIP_NODE_SIZE));
+        }
+        let n_nodes = nodes_lump.len() / NODE_SIZE;
+        let n_leaves = leaves_lump.len() / LEAF_SIZE;
+        let n_clip_nodes = clip_nodes_lump.len() / CLIP_NODE_SIZE;
+
+        let mut nodes = Vec::with_capacity(n_nodes);
+        for (i, raw) in nodes_lump.chunks_exact(NODE_SIZE).enumerate() {
+            let offset = i * NODE_SIZE;
+            let plane_idx = Tree::get_plane(&raw[0..4], planes, Location::record("Nodes", i, offset))?;
+
+            let children = [
+                NodeRef::from_raw(i32::from(slice_to_i16(&raw[4..6]))),
+                NodeRef::from_raw(i32::from(slice_to_i16(&raw[6..8]))),
+            ];
+            for (c, child) in children.iter().enumerate() {
+                let (exists, target) = match *child {
+                    NodeRef::Node(n) => (n < n_nodes, "Node"),
+                    NodeRef::Leaf(n) => (n < n_leaves, "Leaf"),
+                };
+                if !exists {
+                    let at = Location::record("Nodes", i, offset + 4 + c * 2);
+                    return Err(reference_error!(at, "child", target, child.to_raw()));
+                }
+            }
+
+            let start = slice_to_u16(&raw[20..22]) as usize;
+            let n = slice_to_u16(&raw[22..24]) as usize;
+            if start + n > faces.faces.len() {
+                let at = Location::record("Nodes", i, offset + 20);
+                return Err(reference_error!(at, "faces", "Face", start + n));
+            }
+
+            nodes.push(Node {
+                plane_idx,
+                children,
+                mins: slice_to_vec3s(&raw[8..14]),
+                maxs: slice_to_vec3s(&raw[14..20]),
+                faces_idx: start..start + n,
+            });
+        }
+
+        let mut leaves = Vec::with_capacity(n_leaves);
+        for (i, raw) in leaves_lump.chunks_exact(LEAF_SIZE).enumerate() {
+            let offset = i * LEAF_SIZE;
+            let vis_offset = slice_to_i32(&raw[4..8]);
+
+            let start = slice_to_u16(&raw[20..22]) as usize;
+            let n = slice_to_u16(&raw[22..24]) as usize;
+            let marks = mark_surfaces.get(start * 2..(start + n) * 2).ok_or_else(|| {
+                reference_error!(Location::record("Leaves", i, offset + 20), "mark_surfaces", "MarkSurface", start + n)
+            })?;
+            let mut faces_idx = Vec::with_capacity(n);
+            for (m, mark) in marks.chunks_exact(2).enumerate() {
+                let face = slice_to_u16(mark) as usize;
+                if face >= faces.faces.len() {
+                    let at = Location::record("MarkSurfaces", start + m, (start + m) * 2);
+                    return Err(reference_error!(at, "face", "Face", face));
+                }
+                faces_idx.push(face);
+            }
+
+            let contents = slice_to_i32(&raw[0..4]);
+            leaves.push(Leaf {
+                contents: Contents::from_i32(contents)
+                    .ok_or_else(|| value_error!(Location::record("Leaves", i, offset), "contents", contents))?,
+                vis_offset: if vis_offset < 0 { None } else { Some(vis_offset as usize) },
+                mins: slice_to_vec3s(&raw[8..14]),
+                maxs: slice_to_vec3s(&raw[14..20]),
+                faces_idx: faces_idx.into_boxed_slice(),
+                ambient_levels: [raw[24], raw[25], raw[26], raw[27]],
+            });
+        }
+
+        let mut clip_nodes = Vec::with_capacity(n_clip_nodes);
+        for (i, raw) in clip_nodes_lump.chunks_exact(CLIP_NODE_SIZE).enumerate() {
+            let offset = i * CLIP_NODE_SIZE;
+            let plane_idx = Tree::get_plane(&raw[0..4], planes, Location::record("ClipNodes", i, offset))?;
+
+            let mut children = [ClipRef::Contents(Contents::Empty); 2];
+            for (n, child) in children.iter_mut().enumerate() {
+                let raw = slice_to_i16(&raw[4 + (n * 2)..6 + (n * 2)]);
+                let at = Location::record("ClipNodes", i, offset + 4 + (n * 2));
+                *child = if raw >= 0 {
+                    if raw as usize >= n_clip_nodes {
+                        return Err(reference_error!(at, "child", "ClipNode", raw));
+                    }
+
+                    ClipRef::Node(raw as usize)
+                } else {
+                    ClipRef::Contents(Contents::from_i32(i32::from(raw)).ok_or_else(|| value_error!(at, "child", raw))?)
+                };
+            }
+
+            clip_nodes.push(ClipNode {
+                plane_idx,
+                children,
+            });
+        }
+
+        Ok(Tree {
+            nodes: nodes.into_boxed_slice(),
+            leaves: leaves.into_boxed_slice(),
+            clip_nodes: clip_nodes.into_boxed_slice(),
+        })
+    }
+
+    /// Internal function. Reads a plane index, checking it exists. `at` is where the index is.
+    fn get_plane(raw: &[u8], planes: &PlanesLump, at: Location) -> Result<usize> {
+        let plane_idx = slice_to_i32(raw);
+        if plane_idx < 0 || plane_idx as usize >= planes.planes.len() {
+            return Err(reference_error!(at, "plane", "Plane", plane_idx));
+        }
+
+        Ok(plane_idx as usize)
+    }
+
+    /// Find the leaf containing `point`, starting from the given node.
+    /// Returns `None` if the tree is empty or has a cycle in it.
+    pub fn find_leaf(&self, head_node: usize, point: Vector3<f32>, planes: &PlanesLump) -> Option<&Leaf> {
+        let mut current = NodeRef::Node(head_node);
+
+        // Any path longer than the number of nodes must have gone round a cycle.
+        for _ in 0..=self.nodes.len() {
+            match current {
+                NodeRef::Node(i) => {
+                    let node = self.nodes.get(i)?;
+                    current = node.children[Tree::side(node.plane_idx, point, planes)?];
+                }
+                NodeRef::Leaf(i) => return self.leaves.get(i),
+            }
+        }
+
+        None
+    }
+
+    /// Find the contents of `point` in the clipping hull starting at the given clip node.
+    /// Returns `None` if the hull has a cycle in it.
+    pub fn hull_contents(&self, head_node: usize, point: Vector3<f32>, planes: &PlanesLump) -> Option<Contents> {
+        let mut current = ClipRef::Node(head_node);
+
+        for _ in 0..=self.clip_nodes.len() {
+            match current {
+                ClipRef::Node(i) => {
+                    let node = self.clip_nodes.get(i)?;
+                    current = node.children[Tree::side(node.plane_idx, point, planes)?];
+                }
+                ClipRef::Contents(contents) => return Some(contents),
+            }
+        }
+
+        None
+    }
+
+    /// Internal function. 0 if the point is in front of the plane, 1 if it's behind.
+    fn side(plane_idx: usize, point: Vector3<f32>, planes: &PlanesLump) -> Option<usize> {
+        let plane = planes.planes.get(plane_idx)?;
+
+        Some(if plane.normal.dot(&point) - plane.dist >= 0.0 { 0 } else { 1 })
+    }
+}
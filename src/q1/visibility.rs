@@ -0,0 +1,75 @@
+// Copyright (C) 2019 Oscar Shrimpton
+//
+// This file is part of stockton-bsp.
+//
+// stockton-bsp is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// stockton-bsp is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with stockton-bsp.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Decompresses the visibility lump of a Quake 1 or GoldSrc file
+
+use bit_vec::BitVec;
+
+use super::tree::{Tree, LEAF_SIZE};
+use crate::lumps::visdata::decompress_vis;
+use crate::types::{Error, Location, Result};
+
+/// Leaf-to-leaf visibility information.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LeafVisLump {
+    /// The leaves potentially visible from each leaf, or `None` if that leaf has no visibility info.
+    /// Bit `n` refers to leaf `n + 1`, since leaf 0 is the shared solid leaf.
+    pub pvs: Box<[Option<BitVec>]>,
+}
+
+impl LeafVisLump {
+    /// Decompress the visibility of each leaf in the tree.
+    /// `vis_leaves` is the number of leaves with visibility information, from the world model.
+    pub fn from_lump(lump: &[u8], tree: &Tree, vis_leaves: usize) -> Result<LeafVisLump> {
+        let mut pvs = Vec::with_capacity(tree.leaves.len());
+        for (i, leaf) in tree.leaves.iter().enumerate() {
+            pvs.push(match leaf.vis_offset {
+                // Maps that haven't been vised have no visibility lump, but may still have offsets.
+                Some(_) if lump.is_empty() => None,
+                Some(offset) => {
+                    let at = Location::record("Leaves", i, (i * LEAF_SIZE) + 4);
+                    let compressed = lump.get(offset..).ok_or_else(|| value_error!(at, "visofs", offset))?;
+
+                    // the run carries on past the end of the lump
+                    Some(decompress_vis(compressed, vis_leaves).map_err(|_| Error::OutOfBounds {
+                        lump: "Visibility",
+                        offset,
+                        length: compressed.len(),
+                    })?)
+                }
+                None => None,
+            });
+        }
+
+        Ok(LeafVisLump {
+            pvs: pvs.into_boxed_slice(),
+        })
+    }
+
+    /// Returns true if leaf `looking` is potentially visible from leaf `from`.
+    /// Leaves with no visibility information can see everything, and leaf 0 is never visible.
+    pub fn visible_from(&self, from: usize, looking: usize) -> bool {
+        if looking == 0 {
+            return false;
+        }
+
+        match self.pvs.get(from) {
+            Some(Some(vec)) => vec.get(looking - 1).unwrap_or(false),
+            _ => true,
+        }
+    }
+}
@@ -0,0 +1,107 @@
+// Copyright (C) 2019 Oscar Shrimpton
+//
+// This file is part of stockton-bsp.
+//
+// stockton-bsp is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// stockton-bsp is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with stockton-bsp.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Parses the areas & area portals lumps of a Quake 2 file
+
+use crate::lumps::helpers::slice_to_i32;
+use crate::types::{Location, Result};
+use std::ops::Range;
+
+const AREA_SIZE: usize = 4 * 2;
+const PORTAL_SIZE: usize = 4 * 2;
+
+/// Areas are groups of leaves which can be closed off from each other by doors.
+/// Area portals are also stored in here.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AreasLump {
+    pub areas: Box<[Area]>,
+    pub portals: Box<[AreaPortal]>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Area {
+    /// Indices into `AreasLump::portals`.
+    pub portals_idx: Range<usize>,
+}
+
+/// A connection from an area to another one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AreaPortal {
+    /// The portal number, which is set by the game as doors open and close.
+    pub portal_num: usize,
+    pub other_area: usize,
+}
+
+impl AreasLump {
+    /// Parse the areas & area portals lumps.
+    /// # Format
+    /// Each area is:
+    /// int numareaportals
+    /// int firstareaportal
+    /// Each area portal is:
+    /// int portalnum
+    /// int otherarea
+    pub fn from_lump(areas_lump: &[u8], portals_lump: &[u8]) -> Result<AreasLump> {
+        if areas_lump.len() % AREA_SIZE != 0 {
+            return Err(size_error!("Areas", areas_lump.len(), AREA_SIZE));
+        }
+        if portals_lump.len() % PORTAL_SIZE != 0 {
+            return Err(size_error!("AreaPortals", portals_lump.len(), PORTAL_SIZE));
+        }
+        let n_areas = areas_lump.len() / AREA_SIZE;
+        let n_portals = portals_lump.len() / PORTAL_SIZE;
+
+        let mut areas = Vec::with_capacity(n_areas);
+        for (i, raw) in areas_lump.chunks_exact(AREA_SIZE).enumerate() {
+            let n = slice_to_i32(&raw[0..4]);
+            let start = slice_to_i32(&raw[4..8]);
+            if start < 0 || n < 0 || start as usize + n as usize > n_portals {
+                let at = Location::record("Areas", i, i * AREA_SIZE);
+                return Err(reference_error!(at, "portals", "AreaPortal", i64::from(start) + i64::from(n)));
+            }
+
+            areas.push(Area {
+                portals_idx: start as usize..(start + n) as usize,
+            });
+        }
+
+        let mut portals = Vec::with_capacity(n_portals);
+        for (i, raw) in portals_lump.chunks_exact(PORTAL_SIZE).enumerate() {
+            let offset = i * PORTAL_SIZE;
+            let portal_num = slice_to_i32(&raw[0..4]);
+            if portal_num < 0 {
+                return Err(value_error!(Location::record("AreaPortals", i, offset), "portalnum", portal_num));
+            }
+
+            let other_area = slice_to_i32(&raw[4..8]);
+            if other_area < 0 || other_area as usize >= n_areas {
+                let at = Location::record("AreaPortals", i, offset + 4);
+                return Err(reference_error!(at, "otherarea", "Area", other_area));
+            }
+
+            portals.push(AreaPortal {
+                portal_num: portal_num as usize,
+                other_area: other_area as usize,
+            });
+        }
+
+        Ok(AreasLump {
+            areas: areas.into_boxed_slice(),
+            portals: portals.into_boxed_slice(),
+        })
+    }
+}
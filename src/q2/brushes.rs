@@ -0,0 +1,157 @@
+// Copyright (C) 2019 Oscar Shrimpton
+//
+// This file is part of stockton-bsp.
+//
+// stockton-bsp is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// stockton-bsp is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with stockton-bsp.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Parses the brushes & brushsides lumps of a Quake 2 file
+
+use super::texinfo::TexInfoLump;
+use crate::lumps::helpers::{slice_to_i16, slice_to_i32, slice_to_u16, slice_to_u32};
+use crate::lumps::PlanesLump;
+use crate::types::{Location, Result};
+
+const BRUSH_SIZE: usize = 4 * 3;
+const SIDE_SIZE: usize = 2 * 2;
+
+/// A brushes lump from a Quake 2 file.
+/// BrushSides are also stored inside here.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BrushesLump {
+    pub brushes: Box<[Brush]>,
+}
+
+/// A convex volume used for collision detection.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Brush {
+    pub sides: Box<[BrushSide]>,
+    pub contents: ContentsFlags,
+}
+
+/// Bounding surface for a brush.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BrushSide {
+    pub plane_idx: usize,
+
+    /// `None` for sides generated by the compiler, such as bevels.
+    pub texinfo_idx: Option<usize>,
+}
+
+impl BrushesLump {
+    /// Parse the brushes & brushsides lumps.
+    /// # Format
+    /// Each brush is:
+    /// int firstside
+    /// int numsides
+    /// int contents
+    /// Each brush side is:
+    /// ushort planenum
+    /// short texinfo       -1 if none.
+    pub fn from_lump(
+        brushes_lump: &[u8],
+        sides_lump: &[u8],
+        planes: &PlanesLump,
+        texinfo: &TexInfoLump,
+    ) -> Result<BrushesLump> {
+        if brushes_lump.len() % BRUSH_SIZE != 0 {
+            return Err(size_error!("Brushes", brushes_lump.len(), BRUSH_SIZE));
+        }
+        if sides_lump.len() % SIDE_SIZE != 0 {
+            return Err(size_error!("BrushSides", sides_lump.len(), SIDE_SIZE));
+        }
+        let n_sides = sides_lump.len() / SIDE_SIZE;
+
+        let mut brushes = Vec::with_capacity(brushes_lump.len() / BRUSH_SIZE);
+        for (i, raw) in brushes_lump.chunks_exact(BRUSH_SIZE).enumerate() {
+            let start = slice_to_i32(&raw[0..4]);
+            let n = slice_to_i32(&raw[4..8]);
+            if start < 0 || n < 0 || start as usize + n as usize > n_sides {
+                let at = Location::record("Brushes", i, i * BRUSH_SIZE);
+                return Err(reference_error!(at, "sides", "BrushSide", i64::from(start) + i64::from(n)));
+            }
+
+            let mut sides = Vec::with_capacity(n as usize);
+            let sides_raw = &sides_lump[start as usize * SIDE_SIZE..(start + n) as usize * SIDE_SIZE];
+            for (j, side) in sides_raw.chunks_exact(SIDE_SIZE).enumerate() {
+                let side_idx = start as usize + j;
+                let plane_idx = slice_to_u16(&side[0..2]) as usize;
+                if plane_idx >= planes.planes.len() {
+                    let at = Location::record("BrushSides", side_idx, side_idx * SIDE_SIZE);
+                    return Err(reference_error!(at, "plane", "Plane", plane_idx));
+                }
+
+                let texinfo_idx = slice_to_i16(&side[2..4]);
+                if texinfo_idx as i32 >= texinfo.texinfo.len() as i32 {
+                    let at = Location::record("BrushSides", side_idx, (side_idx * SIDE_SIZE) + 2);
+                    return Err(reference_error!(at, "texinfo", "TexInfo", texinfo_idx));
+                }
+
+                sides.push(BrushSide {
+                    plane_idx,
+                    texinfo_idx: if texinfo_idx < 0 { None } else { Some(texinfo_idx as usize) },
+                });
+            }
+
+            brushes.push(Brush {
+                sides: sides.into_boxed_slice(),
+                contents: ContentsFlags::from_bits_truncate(slice_to_u32(&raw[8..12])),
+            });
+        }
+
+        Ok(BrushesLump {
+            brushes: brushes.into_boxed_slice(),
+        })
+    }
+}
+
+bitflags!(
+    /// Extracted from the Q2 engine code.
+    /// https://github.com/id-Software/Quake-2/blob/master/qcommon/qfiles.h
+    pub struct ContentsFlags: u32 {
+        /// an eye is never valid in a solid
+        const SOLID = 0x1;
+        const WINDOW = 0x2;
+        const AUX = 0x4;
+        const LAVA = 0x8;
+        const SLIME = 0x10;
+        const WATER = 0x20;
+        const MIST = 0x40;
+
+        const AREA_PORTAL = 0x80_00;
+        const PLAYER_CLIP = 0x01_00_00;
+        const MONSTER_CLIP = 0x02_00_00;
+
+        /// currents can be added to any other contents, and may be mixed
+        const CURRENT_0 = 0x04_00_00;
+        const CURRENT_90 = 0x08_00_00;
+        const CURRENT_180 = 0x10_00_00;
+        const CURRENT_270 = 0x20_00_00;
+        const CURRENT_UP = 0x40_00_00;
+        const CURRENT_DOWN = 0x80_00_00;
+
+        /// removed before bsping an entity
+        const ORIGIN = 0x01_00_00_00;
+
+        /// should never be on a brush, only in game
+        const MONSTER = 0x02_00_00_00;
+        const DEAD_MONSTER = 0x04_00_00_00;
+
+        /// brushes to be added after vis leafs
+        const DETAIL = 0x08_00_00_00;
+
+        /// auto set if any surface has trans
+        const TRANSLUCENT = 0x10_00_00_00;
+        const LADDER = 0x20_00_00_00;
+    }
+);
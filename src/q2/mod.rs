@@ -0,0 +1,247 @@
+// Copyright (C) 2019 Oscar Shrimpton
+//
+// This file is part of stockton-bsp.
+//
+// stockton-bsp is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// stockton-bsp is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with stockton-bsp.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Quake 2 (IBSP version 38) files.
+//! These share some lumps with Q3 files, but faces are built from edges and visibility is compressed.
+
+mod areas;
+mod brushes;
+mod models;
+mod texinfo;
+mod tree;
+
+pub use self::areas::{Area, AreaPortal, AreasLump};
+pub use self::brushes::{Brush, BrushSide, BrushesLump, ContentsFlags};
+pub use self::models::{Model, ModelsLump};
+pub use self::texinfo::{SurfaceFlags, TexInfo, TexInfoLump};
+pub use self::tree::{Leaf, Node, Tree};
+
+use crate::directory::{read_header, DirEntry, MAGIC_HEADER};
+use crate::lumps::*;
+use crate::types::{Error, Result};
+
+/// The version number used by Quake 2 files.
+pub const Q2_VERSION: u32 = 38;
+
+/// The name of each lump in a Quake 2 file, in directory order.
+pub const Q2_LUMP_NAMES: [&str; 19] = [
+    "Entities",
+    "Planes",
+    "Vertices",
+    "Visibility",
+    "Nodes",
+    "TexInfo",
+    "Faces",
+    "Lighting",
+    "Leaves",
+    "LeafFaces",
+    "LeafBrushes",
+    "Edges",
+    "SurfEdges",
+    "Models",
+    "Brushes",
+    "BrushSides",
+    "Pop",
+    "Areas",
+    "AreaPortals",
+];
+
+/// The header found at the start of a Quake 2 bsp file.
+#[derive(Clone, Copy, Debug)]
+pub struct Q2Header {
+    pub version: u32,
+    pub dir_entries: [DirEntry; 19],
+}
+
+impl Q2Header {
+    /// Deserialise from buffer.
+    /// # Format
+    /// string[4] magic             Magic number. Always "IBSP".
+    /// int version                 Version number. Always 38.
+    /// direntry[19] direntries     Lump directory, nineteen entries.
+    pub fn from(v: &[u8]) -> Result<Q2Header> {
+        let (version, dir_entries) = read_header(v, MAGIC_HEADER)?;
+
+        Ok(Q2Header {
+            version,
+            dir_entries,
+        })
+    }
+
+    /// Get the lump at given index from the buffer, checking it's inside the buffer.
+    pub fn get_lump<'l>(&self, buf: &'l [u8], index: usize) -> Result<&'l [u8]> {
+        let entry = self.dir_entries[index];
+        entry.get_lump(buf).ok_or(Error::OutOfBounds {
+            lump: Q2_LUMP_NAMES[index],
+            offset: entry.offset as usize,
+            length: entry.length as usize,
+        })
+    }
+}
+
+/// Represents a parsed Quake 2 BSP file.
+#[derive(Debug, Clone)]
+pub struct Q2BSPFile {
+    pub directory: Q2Header,
+    pub entities: EntitiesLump,
+    pub planes: PlanesLump,
+    pub vertices: PositionsLump,
+    pub visibility: ClusterVisLump,
+    pub tree: Tree,
+    pub texinfo: TexInfoLump,
+    pub faces: EdgeFacesLump,
+
+    /// Raw RGB lightmap samples, referred to by `EdgeFace::light_offset`.
+    pub lighting: Box<[u8]>,
+    pub edges: EdgesLump,
+    pub surf_edges: SurfEdgesLump,
+    pub models: ModelsLump,
+    pub brushes: BrushesLump,
+    pub areas: AreasLump,
+}
+
+impl Q2BSPFile {
+    /// Try to parse the given buffer as a Quake 2 BSP file
+    pub fn from_buffer(buf: Box<[u8]>) -> Result<Q2BSPFile> {
+        let header = Q2Header::from(&buf)?;
+        if header.version != Q2_VERSION {
+            return Err(Error::Unsupported {
+                version: header.version,
+            });
+        }
+
+        let lump = |i| header.get_lump(&buf, i);
+
+        let entities = EntitiesLump::from_lump(lump(0)?)?;
+        let planes = PlanesLump::from_typed_lump(lump(1)?)?;
+        let vertices = PositionsLump::from_lump(lump(2)?)?;
+        let texinfo = TexInfoLump::from_lump(lump(5)?)?;
+        let edges = EdgesLump::from_lump(lump(11)?, &vertices)?;
+        let surf_edges = SurfEdgesLump::from_lump(lump(12)?, &edges)?;
+        let faces = EdgeFacesLump::from_lump(lump(6)?, &planes, &surf_edges, texinfo.texinfo.len())?;
+        let brushes = BrushesLump::from_lump(lump(14)?, lump(15)?, &planes, &texinfo)?;
+        let tree = Tree::from_lumps(lump(4)?, lump(8)?, lump(9)?, lump(10)?, &planes, &faces, &brushes)?;
+        let n_clusters = tree.leaves.iter().filter_map(|l| l.cluster).max().map_or(0, |c| c + 1);
+        let visibility = ClusterVisLump::from_lump(lump(3)?, n_clusters)?;
+        let models = ModelsLump::from_lump(lump(13)?, &faces, &tree)?;
+        let areas = AreasLump::from_lump(lump(17)?, lump(18)?)?;
+
+        Ok(Q2BSPFile {
+            directory: header,
+            entities,
+            planes,
+            vertices,
+            visibility,
+            tree,
+            texinfo,
+            faces,
+            lighting: lump(7)?.into(),
+            edges,
+            surf_edges,
+            models,
+            brushes,
+            areas,
+        })
+    }
+}
+
+#[test]
+fn q2_synthetic_file() {
+    use crate::directory::assemble;
+    use crate::lumps::test_util::{floats, ints, shorts};
+    use na::Vector3;
+
+    let mut texinfo = floats(&[1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0]);
+    texinfo.extend(ints(&[0x80, 0]));
+    let mut name = b"e1u1/floor".to_vec();
+    name.resize(32, 0);
+    texinfo.extend(name);
+    texinfo.extend(ints(&[-1]));
+
+    let mut face = shorts(&[0, 0]);
+    face.extend(ints(&[0]));
+    face.extend(shorts(&[3, 0]));
+    face.extend(&[0, 255, 255, 255]);
+    face.extend(ints(&[-1]));
+
+    let mut node = ints(&[0, -1, -2]);
+    node.extend(shorts(&[-64, -64, -64, 64, 64, 64, 0, 1]));
+
+    let mut leaves = ints(&[0]);
+    leaves.extend(shorts(&[0, 1, -64, -64, 0, 64, 64, 64, 0, 1, 0, 0]));
+    leaves.extend(ints(&[1]));
+    leaves.extend(shorts(&[-1, 0, -64, -64, -64, 64, 64, 0, 0, 0, 0, 1]));
+
+    let mut visibility = ints(&[1, 12, 13]);
+    visibility.extend(&[0x01, 0x00, 0x01]);
+
+    let mut model = floats(&[-64.0, -64.0, -64.0, 64.0, 64.0, 64.0, 0.0, 0.0, 0.0]);
+    model.extend(ints(&[0, 0, 1]));
+
+    let lumps = vec![
+        b"{\n\"classname\" \"worldspawn\"\n}\n\0".to_vec(),
+        floats(&[0.0, 0.0, 1.0, 0.0, 2.0]),
+        floats(&[0.0, 0.0, 0.0, 64.0, 0.0, 0.0, 0.0, 64.0, 0.0]),
+        visibility,
+        node,
+        texinfo,
+        face,
+        vec![],
+        leaves,
+        shorts(&[0]),
+        shorts(&[0]),
+        shorts(&[0, 0, 0, 1, 1, 2, 0, 2]),
+        ints(&[1, 2, -3]),
+        model,
+        ints(&[0, 1, 1]),
+        shorts(&[0, -1]),
+        vec![],
+        ints(&[0, 0, 1, 0]),
+        ints(&[1, 0]),
+    ];
+
+    let file = Q2BSPFile::from_buffer(assemble(MAGIC_HEADER, Q2_VERSION, &lumps)).unwrap();
+
+    assert_eq!(file.entities.entities[0].classname(), Some("worldspawn"));
+    assert_eq!(file.texinfo.texinfo[0].texture, "e1u1/floor");
+    assert_eq!(file.texinfo.texinfo[0].flags, SurfaceFlags::NODRAW);
+
+    let face = &file.faces.faces[0];
+    assert_eq!(face.styles, [0, 255, 255, 255]);
+    assert_eq!(face.light_offset, None);
+    assert_eq!(
+        face.vertex_indices(&file.surf_edges, &file.edges).collect::<Vec<_>>(),
+        vec![0, 1, 2]
+    );
+
+    let above = file.tree.find_leaf(0, Vector3::new(0.0, 0.0, 8.0), &file.planes).unwrap();
+    assert_eq!(above.cluster, Some(0));
+    assert_eq!(&*above.faces_idx, &[0]);
+
+    let below = file.tree.find_leaf(0, Vector3::new(0.0, 0.0, -8.0), &file.planes).unwrap();
+    assert_eq!(below.contents, ContentsFlags::SOLID);
+    assert_eq!(below.cluster, None);
+    assert_eq!(&*below.brushes_idx, &[0]);
+    assert_eq!(file.brushes.brushes[0].sides[0].texinfo_idx, None);
+
+    assert!(file.visibility.visible_from(0, 0));
+    assert!(!file.visibility.phs[0][0]);
+
+    assert_eq!(file.models.models[0].faces_idx, 0..1);
+    assert_eq!(file.areas.areas[1].portals_idx, 0..1);
+    assert_eq!(file.areas.portals[0].other_area, 0);
+}
@@ -0,0 +1,89 @@
+// Copyright (C) 2019 Oscar Shrimpton
+//
+// This file is part of stockton-bsp.
+//
+// stockton-bsp is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// stockton-bsp is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with stockton-bsp.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Parses the models lump of a Quake 2 file
+
+use super::tree::Tree;
+use crate::lumps::helpers::{slice_to_i32, slice_to_vec3};
+use crate::lumps::EdgeFacesLump;
+use crate::types::{Location, Result};
+use na::Vector3;
+use std::ops::Range;
+
+const MODEL_SIZE: usize = (4 * 3 * 3) + (4 * 3);
+
+/// The world model & brush entities (doors, platforms, etc).
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModelsLump {
+    pub models: Box<[Model]>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Model {
+    pub mins: Vector3<f32>,
+    pub maxs: Vector3<f32>,
+    pub origin: Vector3<f32>,
+
+    /// The node this model's part of the tree starts at.
+    pub head_node: usize,
+    pub faces_idx: Range<usize>,
+}
+
+impl ModelsLump {
+    /// Parse the models lump.
+    /// # Format
+    /// float mins[3]
+    /// float maxs[3]
+    /// float origin[3]
+    /// int headnode
+    /// int firstface
+    /// int numfaces
+    pub fn from_lump(lump: &[u8], faces: &EdgeFacesLump, tree: &Tree) -> Result<ModelsLump> {
+        if lump.len() % MODEL_SIZE != 0 {
+            return Err(size_error!("Models", lump.len(), MODEL_SIZE));
+        }
+
+        let mut models = Vec::with_capacity(lump.len() / MODEL_SIZE);
+        for (i, raw) in lump.chunks_exact(MODEL_SIZE).enumerate() {
+            let offset = i * MODEL_SIZE;
+            let head_node = slice_to_i32(&raw[36..40]);
+            if head_node < 0 || head_node as usize >= tree.nodes.len() {
+                let at = Location::record("Models", i, offset + 36);
+                return Err(reference_error!(at, "headnode", "Node", head_node));
+            }
+
+            let start = slice_to_i32(&raw[40..44]);
+            let n = slice_to_i32(&raw[44..48]);
+            if start < 0 || n < 0 || start as usize + n as usize > faces.faces.len() {
+                let at = Location::record("Models", i, offset + 40);
+                return Err(reference_error!(at, "faces", "Face", i64::from(start) + i64::from(n)));
+            }
+
+            models.push(Model {
+                mins: slice_to_vec3(&raw[0..12]),
+                maxs: slice_to_vec3(&raw[12..24]),
+                origin: slice_to_vec3(&raw[24..36]),
+                head_node: head_node as usize,
+                faces_idx: start as usize..(start + n) as usize,
+            });
+        }
+
+        Ok(ModelsLump {
+            models: models.into_boxed_slice(),
+        })
+    }
+}
@@ -0,0 +1,121 @@
+// Copyright (C) 2019 Oscar Shrimpton
+//
+// This file is part of stockton-bsp.
+//
+// stockton-bsp is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// stockton-bsp is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with stockton-bsp.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Parses the texinfo lump of a Quake 2 file
+
+use crate::lumps::helpers::{slice_to_cstr, slice_to_f32, slice_to_i32, slice_to_u32};
+use crate::types::{Location, Result};
+
+const TEXINFO_SIZE: usize = (4 * 4 * 2) + 4 + 4 + 32 + 4;
+
+/// Texture projections & surface properties.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TexInfoLump {
+    pub texinfo: Box<[TexInfo]>,
+}
+
+/// A record from a `TexInfoLump`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TexInfo {
+    /// The s & t projection vectors, with the offset as the last element.
+    pub vecs: [[f32; 4]; 2],
+    pub flags: SurfaceFlags,
+
+    /// Light emission, etc.
+    pub value: i32,
+
+    /// Texture name, relative to `textures/` and without an extension.
+    pub texture: String,
+
+    /// The next texinfo in an animation, if any.
+    pub next: Option<usize>,
+}
+
+impl TexInfoLump {
+    /// Parse the texinfo lump.
+    /// # Format
+    /// float vecs[2][4]    s & t projection vectors, each with an offset.
+    /// int flags           Surface flags.
+    /// int value           Light emission, etc.
+    /// string[32] texture  Texture name.
+    /// int nexttexinfo     Next texinfo in animation, or -1.
+    pub fn from_lump(lump: &[u8]) -> Result<TexInfoLump> {
+        if lump.len() % TEXINFO_SIZE != 0 {
+            return Err(size_error!("TexInfo", lump.len(), TEXINFO_SIZE));
+        }
+        let length = lump.len() / TEXINFO_SIZE;
+
+        let mut texinfo = Vec::with_capacity(length);
+        for (i, raw) in lump.chunks_exact(TEXINFO_SIZE).enumerate() {
+            let mut vecs = [[0.0; 4]; 2];
+            for (n, v) in vecs.iter_mut().flatten().enumerate() {
+                *v = slice_to_f32(&raw[n * 4..(n + 1) * 4]);
+            }
+
+            let next = slice_to_i32(&raw[72..76]);
+            if next >= length as i32 {
+                let at = Location::record("TexInfo", i, (i * TEXINFO_SIZE) + 72);
+                return Err(reference_error!(at, "nexttexinfo", "TexInfo", next));
+            }
+
+            texinfo.push(TexInfo {
+                vecs,
+                flags: SurfaceFlags::from_bits_truncate(slice_to_u32(&raw[32..36])),
+                value: slice_to_i32(&raw[36..40]),
+                texture: slice_to_cstr(&raw[40..72])?.to_owned(),
+                next: if next < 0 { None } else { Some(next as usize) },
+            });
+        }
+
+        Ok(TexInfoLump {
+            texinfo: texinfo.into_boxed_slice(),
+        })
+    }
+}
+
+bitflags!(
+    /// Extracted from the Q2 engine code.
+    /// https://github.com/id-Software/Quake-2/blob/master/qcommon/qfiles.h
+    pub struct SurfaceFlags: u32 {
+        /// value will hold the light strength
+        const LIGHT = 0x1;
+
+        /// effects game physics
+        const SLICK = 0x2;
+
+        /// don't draw, but add to skybox
+        const SKY = 0x4;
+
+        /// turbulent water warp
+        const WARP = 0x8;
+
+        const TRANS33 = 0x10;
+        const TRANS66 = 0x20;
+
+        /// scroll towards angle
+        const FLOWING = 0x40;
+
+        /// don't bother referencing the texture
+        const NODRAW = 0x80;
+
+        /// make a primary bsp splitter
+        const HINT = 0x01_00;
+
+        /// completely ignore, allowing non-closed brushes
+        const SKIP = 0x02_00;
+    }
+);
@@ -0,0 +1,233 @@
+// Copyright (C) 2019 Oscar Shrimpton
+//
+// This file is part of stockton-bsp.
+//
+// stockton-bsp is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// stockton-bsp is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with stockton-bsp.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Parses the nodes & leaves lumps of a Quake 2 file
+
+use super::brushes::{BrushesLump, ContentsFlags};
+use crate::lumps::helpers::{slice_to_i16, slice_to_i32, slice_to_u16, slice_to_u32, slice_to_vec3s};
+use crate::lumps::{EdgeFacesLump, NodeRef, PlanesLump};
+use crate::types::{Location, Result};
+use na::Vector3;
+use std::ops::Range;
+
+const NODE_SIZE: usize = 4 + (4 * 2) + (2 * 3 * 2) + (2 * 2);
+const LEAF_SIZE: usize = 4 + 2 + 2 + (2 * 3 * 2) + (2 * 4);
+
+/// The BSP tree of a Quake 2 file.
+/// Nodes & leaves are kept in the order they're stored in the file, and refer to each other by index.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tree {
+    pub nodes: Box<[Node]>,
+    pub leaves: Box<[Leaf]>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Node {
+    pub plane_idx: usize,
+
+    /// Front & back children.
+    pub children: [NodeRef; 2],
+    pub mins: Vector3<i32>,
+    pub maxs: Vector3<i32>,
+
+    /// The faces on this node's plane.
+    pub faces_idx: Range<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Leaf {
+    pub contents: ContentsFlags,
+
+    /// The visibility cluster, or `None` if this leaf isn't in one.
+    pub cluster: Option<usize>,
+    pub area: usize,
+    pub mins: Vector3<i32>,
+    pub maxs: Vector3<i32>,
+    pub faces_idx: Box<[usize]>,
+    pub brushes_idx: Box<[usize]>,
+}
+
+impl Tree {
+    /// Parse the nodes, leaves, leaf faces & leaf brushes lumps.
+    /// # Format
+    /// Each node is:
+    /// int planenum
+    /// int children[2]     Negative values are leaves, as `-(leaf + 1)`.
+    /// short mins[3]
+    /// short maxs[3]
+    /// ushort firstface
+    /// ushort numfaces
+    /// Each leaf is:
+    /// int contents
+    /// short cluster
+    /// short area
+    /// short mins[3]
+    /// short maxs[3]
+    /// ushort firstleafface
+    /// ushort numleaffaces
+    /// ushort firstleafbrush
+    /// ushort numleafbrushes
+    pub fn from_lumps(
+        nodes_lump: &[u8],
+        leaves_lump: &[u8],
+        leaf_faces: &[u8],
+        leaf_brushes: &[u8],
+        planes: &PlanesLump,
+        faces: &EdgeFacesLump,
+        brushes: &BrushesLump,
+    ) -> Result<Tree> {
+        if nodes_lump.len() % NODE_SIZE != 0 {
+            return Err(size_error!("Nodes", nodes_lump.len(), NODE_SIZE));
+        }
+        if leaves_lump.len() % LEAF_SIZE != 0 {
+            return Err(size_error!("Leaves", leaves_lump.len(), LEAF_SIZE));
+        }
+        if leaf_faces.len() % 2 != 0 {
+            return Err(size_error!("LeafFaces", leaf_faces.len(), 2));
+        }
+        if leaf_brushes.len() % 2 != 0 {
+            return Err(size_error!("LeafBrushes", leaf_brushes.len(), 2));
+        }
+        let n_nodes = nodes_lump.len() / NODE_SIZE;
+        let n_leaves = leaves_lump.len() / LEAF_SIZE;
+
+        let mut nodes = Vec::with_capacity(n_nodes);
+        for (i, raw) in nodes_lump.chunks_exact(NODE_SIZE).enumerate() {
+            let offset = i * NODE_SIZE;
+            let plane_idx = slice_to_i32(&raw[0..4]);
+            if plane_idx < 0 || plane_idx as usize >= planes.planes.len() {
+                return Err(reference_error!(Location::record("Nodes", i, offset), "plane", "Plane", plane_idx));
+            }
+
+            let children = [
+                NodeRef::from_raw(slice_to_i32(&raw[4..8])),
+                NodeRef::from_raw(slice_to_i32(&raw[8..12])),
+            ];
+            for (c, child) in children.iter().enumerate() {
+                let (exists, target) = match *child {
+                    NodeRef::Node(n) => (n < n_nodes, "Node"),
+                    NodeRef::Leaf(n) => (n < n_leaves, "Leaf"),
+                };
+                if !exists {
+                    let at = Location::record("Nodes", i, offset + 4 + c * 4);
+                    return Err(reference_error!(at, "child", target, child.to_raw()));
+                }
+            }
+
+            let start = slice_to_u16(&raw[24..26]) as usize;
+            let n = slice_to_u16(&raw[26..28]) as usize;
+            if start + n > faces.faces.len() {
+                let at = Location::record("Nodes", i, offset + 24);
+                return Err(reference_error!(at, "faces", "Face", start + n));
+            }
+
+            nodes.push(Node {
+                plane_idx: plane_idx as usize,
+                children,
+                mins: slice_to_vec3s(&raw[12..18]),
+                maxs: slice_to_vec3s(&raw[18..24]),
+                faces_idx: start..start + n,
+            });
+        }
+
+        let mut leaves = Vec::with_capacity(n_leaves);
+        for (i, raw) in leaves_lump.chunks_exact(LEAF_SIZE).enumerate() {
+            let offset = i * LEAF_SIZE;
+            let cluster = slice_to_i16(&raw[4..6]);
+            let area = slice_to_i16(&raw[6..8]);
+            if area < 0 {
+                return Err(value_error!(Location::record("Leaves", i, offset + 6), "area", area));
+            }
+
+            let faces_idx = Tree::get_indices(&raw[20..24], leaf_faces, faces.faces.len())
+                .map_err(|(index, value)| match index {
+                    Some(index) => reference_error!(Location::record("LeafFaces", index, index * 2), "face", "Face", value),
+                    None => reference_error!(Location::record("Leaves", i, offset + 20), "leaf_faces", "LeafFace", value),
+                })?;
+            let brushes_idx = Tree::get_indices(&raw[24..28], leaf_brushes, brushes.brushes.len())
+                .map_err(|(index, value)| match index {
+                    Some(index) => {
+                        reference_error!(Location::record("LeafBrushes", index, index * 2), "brush", "Brush", value)
+                    }
+                    None => {
+                        reference_error!(Location::record("Leaves", i, offset + 24), "leaf_brushes", "LeafBrush", value)
+                    }
+                })?;
+
+            leaves.push(Leaf {
+                contents: ContentsFlags::from_bits_truncate(slice_to_u32(&raw[0..4])),
+                cluster: if cluster < 0 { None } else { Some(cluster as usize) },
+                area: area as usize,
+                mins: slice_to_vec3s(&raw[8..14]),
+                maxs: slice_to_vec3s(&raw[14..20]),
+                faces_idx,
+                brushes_idx,
+            });
+        }
+
+        Ok(Tree {
+            nodes: nodes.into_boxed_slice(),
+            leaves: leaves.into_boxed_slice(),
+        })
+    }
+
+    /// Internal function. Reads the u16 indices referenced by a start & count pair, checking they're below `max`.
+    /// On failure, returns the index of the bad entry (or `None` if the range itself is bad) & the bad value.
+    fn get_indices(range: &[u8], lump: &[u8], max: usize) -> std::result::Result<Box<[usize]>, (Option<usize>, usize)> {
+        let start = slice_to_u16(&range[0..2]) as usize;
+        let n = slice_to_u16(&range[2..4]) as usize;
+
+        lump.get(start * 2..(start + n) * 2)
+            .ok_or((None, start + n))?
+            .chunks_exact(2)
+            .enumerate()
+            .map(|(m, raw)| {
+                let idx = slice_to_u16(raw) as usize;
+                if idx < max {
+                    Ok(idx)
+                } else {
+                    Err((Some(start + m), idx))
+                }
+            })
+            .collect()
+    }
+
+    /// Find the leaf containing `point`, starting from the given node.
+    /// Returns `None` if the tree is empty or has a cycle in it.
+    pub fn find_leaf(&self, head_node: usize, point: Vector3<f32>, planes: &PlanesLump) -> Option<&Leaf> {
+        let mut current = NodeRef::Node(head_node);
+
+        // Any path longer than the number of nodes must have gone round a cycle.
+        for _ in 0..=self.nodes.len() {
+            match current {
+                NodeRef::Node(i) => {
+                    let node = self.nodes.get(i)?;
+                    let plane = planes.planes.get(node.plane_idx)?;
+
+                    current = if plane.normal.dot(&point) - plane.dist >= 0.0 {
+                        node.children[0]
+                    } else {
+                        node.children[1]
+                    };
+                }
+                NodeRef::Leaf(i) => return self.leaves.get(i),
+            }
+        }
+
+        None
+    }
+}
@@ -0,0 +1,206 @@
+// Copyright (C) 2019 Oscar Shrimpton
+//
+// This file is part of stockton-bsp.
+//
+// stockton-bsp is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// stockton-bsp is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with stockton-bsp.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Parses the faces lump of an RBSP file
+
+use crate::lumps::faces::{Face, FaceLump, FaceType};
+use crate::lumps::helpers::{slice_to_i32, slice_to_vec2i, slice_to_vec3};
+use crate::lumps::{EffectsLump, LightMapsLump, MeshVertsLump, TexturesLump};
+use crate::types::{Location, Result};
+use na::{Vector2, Vector3};
+use std::convert::TryInto;
+use std::ops::Range;
+
+use super::vertices::StyledVerticesLump;
+use super::MAX_LIGHT_MAPS;
+
+const FACE_SIZE: usize = (4 * 7) + (MAX_LIGHT_MAPS * 2) + (4 * MAX_LIGHT_MAPS * 3) + (4 * 2) + (4 * 3 * 4) + (4 * 2);
+
+/// A face with up to four lightmaps, one for each light style.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StyledFace {
+    pub face_type: FaceType,
+    pub texture_idx: usize,
+    pub effect_idx: Option<usize>,
+    pub vertices_idx: Range<usize>,
+    pub meshverts_idx: Range<usize>,
+
+    /// The light style of each lightmap slot, with 255 meaning unused.
+    pub lightmap_styles: [u8; MAX_LIGHT_MAPS],
+
+    /// The light style of each vertex colour slot, with 255 meaning unused.
+    pub vertex_styles: [u8; MAX_LIGHT_MAPS],
+    pub lightmap_idx: [Option<usize>; MAX_LIGHT_MAPS],
+    pub map_starts: [Vector2<i32>; MAX_LIGHT_MAPS],
+
+    pub map_size: Vector2<i32>,
+    pub map_origin: Vector3<f32>,
+    pub map_vecs: [Vector3<f32>; 2],
+
+    pub normal: Vector3<f32>,
+    pub size: Vector2<i32>,
+}
+
+impl StyledFace {
+    /// Get a Q3-style face using the lightmap from the given style slot.
+    /// # Panics
+    /// If `slot` is not less than `MAX_LIGHT_MAPS`.
+    pub fn to_face(&self, slot: usize) -> Face {
+        Face {
+            face_type: self.face_type,
+            texture_idx: self.texture_idx,
+            effect_idx: self.effect_idx,
+            lightmap_idx: self.lightmap_idx[slot],
+            vertices_idx: self.vertices_idx.clone(),
+            meshverts_idx: self.meshverts_idx.clone(),
+            map_start: self.map_starts[slot],
+            map_size: self.map_size,
+            map_origin: self.map_origin,
+            map_vecs: self.map_vecs,
+            normal: self.normal,
+            size: self.size,
+        }
+    }
+}
+
+/// The faces lump of an RBSP file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StyledFaceLump {
+    pub faces: Box<[StyledFace]>,
+}
+
+impl StyledFaceLump {
+    /// Parse the faces lump.
+    /// # Format
+    /// int shaderNum
+    /// int fogNum
+    /// int surfaceType
+    /// int firstVert
+    /// int numVerts
+    /// int firstIndex
+    /// int numIndexes
+    /// byte lightmapStyles[4]
+    /// byte vertexStyles[4]
+    /// int lightmapNum[4]      Negative if unused.
+    /// int lightmapX[4]
+    /// int lightmapY[4]
+    /// int lightmapWidth
+    /// int lightmapHeight
+    /// float lightmapOrigin[3]
+    /// float lightmapVecs[3][3]    The last is the normal.
+    /// int patchWidth
+    /// int patchHeight
+    pub fn from_lump(
+        data: &[u8],
+        textures: &TexturesLump,
+        effects: &EffectsLump,
+        vertices_lump: &StyledVerticesLump,
+        meshverts_lump: &MeshVertsLump,
+        light_maps: &LightMapsLump,
+    ) -> Result<StyledFaceLump> {
+        if data.len() % FACE_SIZE != 0 {
+            return Err(size_error!("Faces", data.len(), FACE_SIZE));
+        }
+
+        let mut faces = Vec::with_capacity(data.len() / FACE_SIZE);
+        for (i, raw) in data.chunks_exact(FACE_SIZE).enumerate() {
+            let at = |offset| Location::record("Faces", i, (i * FACE_SIZE) + offset);
+            let texture_idx = slice_to_i32(&raw[0..4]);
+            if texture_idx < 0 || texture_idx as usize >= textures.textures.len() {
+                return Err(reference_error!(at(0), "texture", "Texture", texture_idx));
+            }
+
+            let effect_idx = slice_to_i32(&raw[4..8]);
+            let effect_idx = if effect_idx < 0 {
+                None
+            } else if effect_idx as usize >= effects.effects.len() {
+                return Err(reference_error!(at(4), "effect", "Effect", effect_idx));
+            } else {
+                Some(effect_idx as usize)
+            };
+
+            let face_type = slice_to_i32(&raw[8..12]);
+            let face_type = FaceType::from_i32(face_type).ok_or_else(|| value_error!(at(8), "type", face_type))?;
+
+            let vertices_idx = StyledFaceLump::get_range(&raw[12..20], vertices_lump.vertices.len())
+                .map_err(|end| reference_error!(at(12), "vertices", "Vertex", end))?;
+            let meshverts_idx = StyledFaceLump::get_range(&raw[20..28], meshverts_lump.meshverts.len())
+                .map_err(|end| reference_error!(at(20), "meshverts", "MeshVert", end))?;
+
+            let mut lightmap_idx = [None; MAX_LIGHT_MAPS];
+            let mut map_starts = [Vector2::new(0, 0); MAX_LIGHT_MAPS];
+            for slot in 0..MAX_LIGHT_MAPS {
+                // Negative values have special meanings (vertex lit, fullbright, etc), but all mean there's no lightmap.
+                let idx = slice_to_i32(&raw[36 + (slot * 4)..40 + (slot * 4)]);
+                if idx >= 0 {
+                    if idx as usize >= light_maps.maps.len() {
+                        return Err(reference_error!(at(36 + (slot * 4)), "lightmap", "LightMap", idx));
+                    }
+
+                    lightmap_idx[slot] = Some(idx as usize);
+                }
+
+                map_starts[slot] = Vector2::new(
+                    slice_to_i32(&raw[52 + (slot * 4)..56 + (slot * 4)]),
+                    slice_to_i32(&raw[68 + (slot * 4)..72 + (slot * 4)]),
+                );
+            }
+
+            faces.push(StyledFace {
+                face_type,
+                texture_idx: texture_idx as usize,
+                effect_idx,
+                vertices_idx,
+                meshverts_idx,
+                lightmap_styles: raw[28..32].try_into().unwrap(),
+                vertex_styles: raw[32..36].try_into().unwrap(),
+                lightmap_idx,
+                map_starts,
+                map_size: slice_to_vec2i(&raw[84..92]),
+                map_origin: slice_to_vec3(&raw[92..104]),
+                map_vecs: [slice_to_vec3(&raw[104..116]), slice_to_vec3(&raw[116..128])],
+                normal: slice_to_vec3(&raw[128..140]),
+                size: slice_to_vec2i(&raw[140..148]),
+            });
+        }
+
+        Ok(StyledFaceLump {
+            faces: faces.into_boxed_slice(),
+        })
+    }
+
+    /// Internal function. Reads a start & count pair, checking it's within `len`.
+    /// On failure, returns the end of the range that was asked for.
+    fn get_range(raw: &[u8], len: usize) -> std::result::Result<Range<usize>, i64> {
+        let start = slice_to_i32(&raw[0..4]);
+        let n = slice_to_i32(&raw[4..8]);
+        if start < 0 || n < 0 || start as usize + n as usize > len {
+            return Err(i64::from(start) + i64::from(n));
+        }
+
+        Ok(start as usize..start as usize + n as usize)
+    }
+
+    /// Get a Q3-style faces lump using the lightmaps from the given style slot.
+    /// # Panics
+    /// If `slot` is not less than `MAX_LIGHT_MAPS`.
+    pub fn to_face_lump(&self, slot: usize) -> FaceLump {
+        FaceLump {
+            faces: self.faces.iter().map(|f| f.to_face(slot)).collect(),
+        }
+    }
+}
@@ -0,0 +1,129 @@
+// Copyright (C) 2019 Oscar Shrimpton
+//
+// This file is part of stockton-bsp.
+//
+// stockton-bsp is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// stockton-bsp is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with stockton-bsp.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Parses the light grid & light array lumps of an RBSP file
+
+use std::convert::TryInto;
+
+use crate::lumps::helpers::slice_to_u16;
+use crate::lumps::light_vols::LightVol;
+use crate::types::{Location, Result, RGB};
+
+use super::MAX_LIGHT_MAPS;
+
+const VOL_LENGTH: usize = (3 * MAX_LIGHT_MAPS * 2) + MAX_LIGHT_MAPS + 2;
+
+/// A light volume with ambient & directional light for each style.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StyledLightVol {
+    pub ambient: [RGB; MAX_LIGHT_MAPS],
+    pub directional: [RGB; MAX_LIGHT_MAPS],
+
+    /// The light style of each slot, with 255 meaning unused.
+    pub styles: [u8; MAX_LIGHT_MAPS],
+    pub dir: [u8; 2],
+}
+
+impl StyledLightVol {
+    /// Parse a single light volume.
+    /// # Panics
+    /// If slice is not `VOL_LENGTH` bytes long.
+    pub fn from_slice(data: &[u8]) -> StyledLightVol {
+        let mut ambient = [RGB::white(); MAX_LIGHT_MAPS];
+        let mut directional = [RGB::white(); MAX_LIGHT_MAPS];
+        for n in 0..MAX_LIGHT_MAPS {
+            ambient[n] = RGB::from_slice(&data[n * 3..(n + 1) * 3]);
+            directional[n] = RGB::from_slice(&data[12 + (n * 3)..12 + ((n + 1) * 3)]);
+        }
+
+        StyledLightVol {
+            ambient,
+            directional,
+            styles: data[24..28].try_into().unwrap(),
+            dir: data[28..30].try_into().unwrap(),
+        }
+    }
+
+    /// Get a Q3-style light volume using the light from the given style slot.
+    /// # Panics
+    /// If `slot` is not less than `MAX_LIGHT_MAPS`.
+    pub fn to_light_vol(&self, slot: usize) -> LightVol {
+        LightVol {
+            ambient: self.ambient[slot],
+            directional: self.directional[slot],
+            dir: self.dir,
+        }
+    }
+}
+
+/// The distinct light volumes in the map, which are referenced by the `LightArrayLump`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LightGridLump {
+    pub vols: Box<[StyledLightVol]>,
+}
+
+impl LightGridLump {
+    /// Parse the light grid lump.
+    /// # Format
+    /// byte ambientLight[4][3]
+    /// byte directLight[4][3]
+    /// byte styles[4]
+    /// byte latLong[2]
+    pub fn from_lump(lump: &[u8]) -> Result<LightGridLump> {
+        if lump.len() % VOL_LENGTH != 0 {
+            return Err(size_error!("LightGrid", lump.len(), VOL_LENGTH));
+        }
+
+        Ok(LightGridLump {
+            vols: lump.chunks_exact(VOL_LENGTH).map(StyledLightVol::from_slice).collect(),
+        })
+    }
+}
+
+/// An index into the light grid for each point in the grid, in the same order as Q3's light volumes.
+/// Points with the same lighting share a volume to save space.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LightArrayLump {
+    pub indices: Box<[u16]>,
+}
+
+impl LightArrayLump {
+    pub fn from_lump(lump: &[u8], grid: &LightGridLump) -> Result<LightArrayLump> {
+        if lump.len() % 2 != 0 {
+            return Err(size_error!("LightArray", lump.len(), 2));
+        }
+
+        let mut indices = Vec::with_capacity(lump.len() / 2);
+        for (i, raw) in lump.chunks_exact(2).enumerate() {
+            let idx = slice_to_u16(raw);
+            if idx as usize >= grid.vols.len() {
+                return Err(reference_error!(Location::record("LightArray", i, i * 2), "index", "LightGrid", idx));
+            }
+
+            indices.push(idx);
+        }
+
+        Ok(LightArrayLump {
+            indices: indices.into_boxed_slice(),
+        })
+    }
+
+    /// Get the light volume for the given grid point.
+    pub fn get<'a>(&self, point: usize, grid: &'a LightGridLump) -> Option<&'a StyledLightVol> {
+        grid.vols.get(*self.indices.get(point)? as usize)
+    }
+}
@@ -0,0 +1,235 @@
+// Copyright (C) 2019 Oscar Shrimpton
+//
+// This file is part of stockton-bsp.
+//
+// stockton-bsp is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// stockton-bsp is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with stockton-bsp.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Raven (RBSP version 1) files, used by Jedi Outcast, Jedi Academy & Soldier of Fortune 2.
+//! These are Q3 files with up to four light styles per face, vertex & light volume.
+//! Lumps which are the same as Q3 are parsed with the Q3 parsers.
+
+mod faces;
+mod light_grid;
+mod vertices;
+
+pub use self::faces::{StyledFace, StyledFaceLump};
+pub use self::light_grid::{LightArrayLump, LightGridLump, StyledLightVol};
+pub use self::vertices::{StyledVertex, StyledVerticesLump};
+
+use crate::directory::{read_header, DirEntry, LUMP_NAMES};
+use crate::lumps::*;
+use crate::types::{Error, Result};
+
+/// "RBSP"
+pub const RBSP_MAGIC: &[u8] = b"RBSP";
+
+/// The version number used by RBSP files.
+pub const RBSP_VERSION: u32 = 1;
+
+/// The number of light styles each face, vertex & light volume can have.
+pub const MAX_LIGHT_MAPS: usize = 4;
+
+/// The size of one brushside, which has an extra face index compared to Q3.
+const BRUSH_SIDE_SIZE: usize = 4 * 3;
+
+/// The header found at the start of an RBSP file.
+#[derive(Clone, Copy, Debug)]
+pub struct RBSPHeader {
+    pub version: u32,
+    pub dir_entries: [DirEntry; 18],
+}
+
+impl RBSPHeader {
+    /// Deserialise from buffer.
+    /// # Format
+    /// string[4] magic             Magic number. Always "RBSP".
+    /// int version                 Version number. Always 1.
+    /// direntry[18] direntries     Lump directory, eighteen entries.
+    pub fn from(v: &[u8]) -> Result<RBSPHeader> {
+        let (version, dir_entries) = read_header(v, RBSP_MAGIC)?;
+
+        Ok(RBSPHeader {
+            version,
+            dir_entries,
+        })
+    }
+
+    /// Get the lump at given index from the buffer, checking it's inside the buffer.
+    pub fn get_lump<'l>(&self, buf: &'l [u8], index: usize) -> Result<&'l [u8]> {
+        let entry = self.dir_entries[index];
+        entry.get_lump(buf).ok_or(Error::OutOfBounds {
+            lump: LUMP_NAMES.get(index).copied().unwrap_or("LightArray"),
+            offset: entry.offset as usize,
+            length: entry.length as usize,
+        })
+    }
+}
+
+/// Represents a parsed RBSP file.
+#[derive(Debug, Clone)]
+pub struct RBSPFile {
+    pub directory: RBSPHeader,
+    pub entities: EntitiesLump,
+    pub textures: TexturesLump,
+    pub planes: PlanesLump,
+    pub brushes: BrushesLump,
+    pub vertices: StyledVerticesLump,
+    pub meshverts: MeshVertsLump,
+    pub light_maps: LightMapsLump,
+    pub effects: EffectsLump,
+    pub faces: StyledFaceLump,
+    pub tree: BSPTree,
+    pub visdata: VisDataLump,
+    pub models: ModelsLump,
+    pub light_grid: LightGridLump,
+    pub light_array: LightArrayLump,
+}
+
+impl RBSPFile {
+    /// Try to parse the given buffer as an RBSP file
+    pub fn from_buffer(buf: Box<[u8]>) -> Result<RBSPFile> {
+        let header = RBSPHeader::from(&buf)?;
+        if header.version != RBSP_VERSION {
+            return Err(Error::Unsupported {
+                version: header.version,
+            });
+        }
+
+        let lump = |i| header.get_lump(&buf, i);
+
+        let entities = EntitiesLump::from_lump(lump(0)?)?;
+        let textures = TexturesLump::from_lump(lump(1)?)?;
+        let planes = PlanesLump::from_lump(lump(2)?)?;
+        let vertices = StyledVerticesLump::from_lump(lump(10)?)?;
+        let meshverts = MeshVertsLump::from_lump(lump(11)?)?;
+        let light_maps = LightMapsLump::from_lump(lump(14)?)?;
+        let light_grid = LightGridLump::from_lump(lump(15)?)?;
+        let light_array = LightArrayLump::from_lump(lump(17)?, &light_grid)?;
+        let visdata = VisDataLump::from_lump(lump(16)?)?;
+        let brushes = BrushesLump::from_sized_lump(lump(8)?, lump(9)?, BRUSH_SIDE_SIZE, &textures, &planes)?;
+        let effects = EffectsLump::from_lump(lump(12)?, &brushes)?;
+        let faces = StyledFaceLump::from_lump(lump(13)?, &textures, &effects, &vertices, &meshverts, &light_maps)?;
+
+        // The tree & models only check face indices, which are the same for every style.
+        let q3_faces = faces.to_face_lump(0);
+        let tree = BSPTree::from_lumps(lump(3)?, lump(4)?, lump(5)?, lump(6)?, &q3_faces, &brushes)?;
+        let models = ModelsLump::from_lump(lump(7)?, &q3_faces, &brushes)?;
+
+        Ok(RBSPFile {
+            directory: header,
+            entities,
+            textures,
+            planes,
+            brushes,
+            vertices,
+            meshverts,
+            light_maps,
+            effects,
+            faces,
+            tree,
+            visdata,
+            models,
+            light_grid,
+            light_array,
+        })
+    }
+}
+
+#[test]
+fn rbsp_synthetic_file() {
+    use crate::directory::assemble;
+    use crate::lumps::faces::FaceType;
+    use crate::lumps::test_util::{floats, ints};
+    use crate::lumps::light_maps::LIGHTMAP_SIZE;
+    use crate::types::RGB;
+
+    let mut texture = b"textures/test".to_vec();
+    texture.resize(64, 0);
+    texture.extend(ints(&[0, 1]));
+
+    let mut node = ints(&[0, -1, -2]);
+    node.extend(ints(&[-64, -64, -64, 64, 64, 64]));
+
+    let mut leaves = Vec::new();
+    for (cluster, faces) in [(0, 1), (-1, 0)].iter() {
+        leaves.extend(ints(&[*cluster, 0, -64, -64, -64, 64, 64, 64, 0, *faces, 0, 1]));
+    }
+
+    let mut vertices = Vec::new();
+    for n in 0..3 {
+        vertices.extend(floats(&[n as f32 * 64.0, 0.0, 0.0, 0.5, 0.5]));
+        vertices.extend(floats(&[0.1, 0.1, 0.2, 0.2, 0.3, 0.3, 0.4, 0.4]));
+        vertices.extend(floats(&[0.0, 0.0, 1.0]));
+        vertices.extend(&[n, 0, 0, 255, 0, n, 0, 255, 0, 0, n, 255, 0, 0, 0, 0]);
+    }
+
+    let mut face = ints(&[0, -1, 1, 0, 3, 0, 3]);
+    face.extend(&[0, 1, 255, 255, 0, 255, 255, 255]);
+    face.extend(ints(&[0, -3, -1, -1, 0, 64, 0, 0, 0, 32, 0, 0, 16, 16]));
+    face.extend(floats(&[0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0]));
+    face.extend(ints(&[0, 0]));
+
+    let mut light_grid = vec![10; 12];
+    light_grid.extend(vec![20; 12]);
+    light_grid.extend(&[0, 255, 255, 255, 1, 2]);
+    light_grid.extend(light_grid.clone());
+    light_grid[30] = 99;
+
+    let lumps = vec![
+        b"{\n\"classname\" \"worldspawn\"\n}\n\0".to_vec(),
+        texture,
+        floats(&[0.0, 0.0, 1.0, 0.0, 0.0, 0.0, -1.0, 0.0]),
+        node,
+        leaves,
+        ints(&[0]),
+        ints(&[0]),
+        [floats(&[-64.0, -64.0, -64.0, 64.0, 64.0, 64.0]), ints(&[0, 1, 0, 1])].concat(),
+        ints(&[0, 1, 0]),
+        ints(&[0, 0, 0]),
+        vertices,
+        ints(&[0, 1, 2]),
+        vec![],
+        face,
+        vec![0; LIGHTMAP_SIZE],
+        light_grid,
+        [ints(&[1, 1]), vec![0xff]].concat(),
+        vec![1, 0, 0, 0, 1, 0],
+    ];
+
+    let file = RBSPFile::from_buffer(assemble(RBSP_MAGIC, RBSP_VERSION, &lumps)).unwrap();
+
+    assert_eq!(file.brushes.brushes[0].sides.len(), 1);
+
+    let face = &file.faces.faces[0];
+    assert_eq!(face.face_type, FaceType::Polygon);
+    assert_eq!(face.lightmap_styles, [0, 1, 255, 255]);
+    assert_eq!(face.lightmap_idx, [Some(0), None, None, None]);
+    assert_eq!(face.map_starts[1].x, 64);
+    assert_eq!(face.map_starts[1].y, 32);
+    assert_eq!(face.to_face(1).map_start, face.map_starts[1]);
+
+    let vertex = &file.vertices.vertices[2];
+    assert_eq!(vertex.lightmap_tex[3], [0.4, 0.4]);
+    assert_eq!(vertex.to_vertex(1).tex.v, [0.2, 0.2]);
+    assert_eq!(vertex.to_vertex(2).color.b, 2);
+    assert_eq!(file.vertices.to_vertices_lump(0).vertices.len(), 3);
+
+    assert_eq!(file.light_grid.vols.len(), 2);
+    let vol = file.light_array.get(0, &file.light_grid).unwrap();
+    assert_eq!(vol.ambient[0], RGB { r: 99, g: 10, b: 10 });
+    assert_eq!(vol.to_light_vol(0).directional, RGB { r: 20, g: 20, b: 20 });
+    assert_eq!(vol.styles, [0, 255, 255, 255]);
+    assert_eq!(vol.dir, [1, 2]);
+    assert!(file.light_array.get(3, &file.light_grid).is_none());
+}
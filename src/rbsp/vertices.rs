@@ -0,0 +1,122 @@
+// Copyright (C) 2019 Oscar Shrimpton
+//
+// This file is part of stockton-bsp.
+//
+// stockton-bsp is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// stockton-bsp is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with stockton-bsp.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Parses the vertices lump of an RBSP file
+
+use crate::lumps::helpers::{slice_to_f32, slice_to_vec3};
+use crate::lumps::vertices::{TexCoord, Vertex, VerticesLump};
+use crate::types::{Result, RGBA};
+use na::Vector3;
+
+use super::MAX_LIGHT_MAPS;
+
+/// The size of one vertex
+const VERTEX_SIZE: usize = (4 * 3) + (4 * 2) + (4 * 2 * MAX_LIGHT_MAPS) + (4 * 3) + (4 * MAX_LIGHT_MAPS);
+
+/// A vertex with a lightmap coordinate & colour for each light style.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StyledVertex {
+    pub position: Vector3<f32>,
+
+    /// Surface texture coordinate.
+    pub tex: [f32; 2],
+
+    /// Lightmap texture coordinate for each style.
+    pub lightmap_tex: [[f32; 2]; MAX_LIGHT_MAPS],
+    pub normal: Vector3<f32>,
+
+    /// Vertex colour for each style.
+    pub colors: [RGBA; MAX_LIGHT_MAPS],
+}
+
+impl StyledVertex {
+    /// Parse a single vertex record.
+    /// # Panics
+    /// If slice is not `VERTEX_SIZE` bytes long.
+    pub fn from_slice(vertex: &[u8]) -> StyledVertex {
+        let mut lightmap_tex = [[0.0; 2]; MAX_LIGHT_MAPS];
+        for (n, tex) in lightmap_tex.iter_mut().enumerate() {
+            let offset = 20 + (n * 8);
+            *tex = [
+                slice_to_f32(&vertex[offset..offset + 4]),
+                slice_to_f32(&vertex[offset + 4..offset + 8]),
+            ];
+        }
+
+        let mut colors = [RGBA::from_bytes([0; 4]); MAX_LIGHT_MAPS];
+        for (n, color) in colors.iter_mut().enumerate() {
+            *color = RGBA::from_slice(&vertex[64 + (n * 4)..68 + (n * 4)]);
+        }
+
+        StyledVertex {
+            position: slice_to_vec3(&vertex[0..12]),
+            tex: [slice_to_f32(&vertex[12..16]), slice_to_f32(&vertex[16..20])],
+            lightmap_tex,
+            normal: slice_to_vec3(&vertex[52..64]),
+            colors,
+        }
+    }
+
+    /// Get a Q3-style vertex using the lightmap coordinate & colour from the given style slot.
+    /// # Panics
+    /// If `slot` is not less than `MAX_LIGHT_MAPS`.
+    pub fn to_vertex(&self, slot: usize) -> Vertex {
+        Vertex {
+            position: self.position,
+            tex: TexCoord {
+                u: self.tex,
+                v: self.lightmap_tex[slot],
+            },
+            normal: self.normal,
+            color: self.colors[slot],
+        }
+    }
+}
+
+/// The vertices lump of an RBSP file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StyledVerticesLump {
+    pub vertices: Box<[StyledVertex]>,
+}
+
+impl StyledVerticesLump {
+    /// Parse the vertices lump.
+    /// # Format
+    /// float xyz[3]
+    /// float st[2]
+    /// float lightmap[4][2]
+    /// float normal[3]
+    /// byte color[4][4]
+    pub fn from_lump(lump: &[u8]) -> Result<StyledVerticesLump> {
+        if lump.len() % VERTEX_SIZE != 0 {
+            return Err(size_error!("Vertices", lump.len(), VERTEX_SIZE));
+        }
+
+        Ok(StyledVerticesLump {
+            vertices: lump.chunks_exact(VERTEX_SIZE).map(StyledVertex::from_slice).collect(),
+        })
+    }
+
+    /// Get a Q3-style vertices lump using the given style slot, for use with `Face::tessellate` etc.
+    /// # Panics
+    /// If `slot` is not less than `MAX_LIGHT_MAPS`.
+    pub fn to_vertices_lump(&self, slot: usize) -> VerticesLump {
+        VerticesLump {
+            vertices: self.vertices.iter().map(|v| v.to_vertex(slot)).collect(),
+        }
+    }
+}
@@ -0,0 +1,262 @@
+// Copyright (C) 2019 Oscar Shrimpton
+//
+// This file is part of stockton-bsp.
+//
+// stockton-bsp is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// stockton-bsp is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with stockton-bsp.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Loading BSP files without reading them into memory first.
+
+use std::io::{Read, Seek, SeekFrom};
+
+#[cfg(feature = "mmap")]
+use std::fs::File;
+#[cfg(feature = "mmap")]
+use std::path::Path;
+
+#[cfg(feature = "mmap")]
+use memmap2::Mmap;
+
+use crate::directory::{Header, LUMP_NAMES};
+use crate::lumps::*;
+use crate::options::LoadOptions;
+use crate::types::{Error, Result};
+use crate::BSPFile;
+#[cfg(feature = "mmap")]
+use crate::BSPFileRef;
+
+/// The longest a Q3 header can be: the magic, the version and 18 directory entries.
+const MAX_HEADER_SIZE: u64 = 8 + (18 * 8);
+
+/// Reads a BSP file from anything that can seek, reading only the lumps that are asked for.
+/// Useful when only a few lumps are needed from each of a lot of files.
+#[derive(Debug)]
+pub struct BSPReader<R> {
+    pub directory: Header,
+    reader: R,
+}
+
+impl<R: Read + Seek> BSPReader<R> {
+    /// Read & check the header, and that all of its lumps are inside the file.
+    /// No lumps are read.
+    pub fn new(mut reader: R) -> Result<BSPReader<R>> {
+        let len = reader.seek(SeekFrom::End(0))?;
+        reader.seek(SeekFrom::Start(0))?;
+
+        let mut header = Vec::new();
+        reader.by_ref().take(MAX_HEADER_SIZE).read_to_end(&mut header)?;
+
+        let directory = Header::from(&header)?;
+        match directory.version {
+            // Quake 3 or Quake LIVE (IBSP47)
+            0x2e | 0x2f => {}
+            version => return Err(Error::Unsupported { version }),
+        }
+
+        for index in 0..18 {
+            if let Some(entry) = directory.entry(index) {
+                let lump_end = u64::from(entry.offset) + u64::from(entry.length);
+                if lump_end > len {
+                    return Err(Error::OutOfBounds {
+                        lump: LUMP_NAMES.get(index).copied().unwrap_or("Advertisements"),
+                        offset: entry.offset as usize,
+                        length: entry.length as usize,
+                    });
+                }
+            }
+        }
+
+        Ok(BSPReader { directory, reader })
+    }
+
+    /// Read the raw bytes of the lump at the given index.
+    /// Lumps that aren't in the directory are empty.
+    pub fn lump(&mut self, index: usize) -> Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        if let Some(entry) = self.directory.entry(index) {
+            buf.resize(entry.length as usize, 0);
+            self.reader.seek(SeekFrom::Start(entry.offset.into()))?;
+            self.reader.read_exact(&mut buf)?;
+        }
+
+        Ok(buf)
+    }
+
+    pub fn entities(&mut self) -> Result<EntitiesLump> {
+        EntitiesLump::from_lump(&self.lump(0)?)
+    }
+
+    pub fn textures(&mut self) -> Result<TexturesLump> {
+        TexturesLump::from_lump(&self.lump(1)?)
+    }
+
+    pub fn planes(&mut self) -> Result<PlanesLump> {
+        PlanesLump::from_lump(&self.lump(2)?)
+    }
+
+    pub fn vertices(&mut self) -> Result<VerticesLump> {
+        VerticesLump::from_lump(&self.lump(10)?)
+    }
+
+    pub fn meshverts(&mut self) -> Result<MeshVertsLump> {
+        MeshVertsLump::from_lump(&self.lump(11)?)
+    }
+
+    pub fn light_maps(&mut self) -> Result<LightMapsLump> {
+        LightMapsLump::from_lump(&self.lump(14)?)
+    }
+
+    pub fn light_vols(&mut self) -> Result<LightVolsLump> {
+        LightVolsLump::from_lump(&self.lump(15)?)
+    }
+
+    pub fn visdata(&mut self) -> Result<VisDataLump> {
+        VisDataLump::from_lump(&self.lump(16)?)
+    }
+
+    /// Only present for Quake live maps (IBSP47)
+    pub fn advertisements(&mut self) -> Result<Option<AdvertisementsLump>> {
+        if self.directory.version == 0x2f {
+            Ok(Some(AdvertisementsLump::from_lump(&self.lump(17)?)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub fn brushes(&mut self, textures: &TexturesLump, planes: &PlanesLump) -> Result<BrushesLump> {
+        BrushesLump::from_lump(&self.lump(8)?, &self.lump(9)?, textures, planes)
+    }
+
+    pub fn effects(&mut self, brushes: &BrushesLump) -> Result<EffectsLump> {
+        EffectsLump::from_lump(&self.lump(12)?, brushes)
+    }
+
+    pub fn faces(
+        &mut self,
+        textures: &TexturesLump,
+        effects: &EffectsLump,
+        vertices: &VerticesLump,
+        meshverts: &MeshVertsLump,
+        light_maps: &LightMapsLump,
+    ) -> Result<FaceLump> {
+        FaceLump::from_lump(&self.lump(13)?, textures, effects, vertices, meshverts, light_maps)
+    }
+
+    pub fn tree(&mut self, faces: &FaceLump, brushes: &BrushesLump) -> Result<BSPTree> {
+        BSPTree::from_lumps(
+            &self.lump(3)?,
+            &self.lump(4)?,
+            &self.lump(5)?,
+            &self.lump(6)?,
+            faces,
+            brushes,
+        )
+    }
+
+    pub fn models(&mut self, faces: &FaceLump, brushes: &BrushesLump) -> Result<ModelsLump> {
+        ModelsLump::from_lump(&self.lump(7)?, faces, brushes)
+    }
+
+    /// Read every lump and parse them into an owned `BSPFile`.
+    pub fn parse(&mut self) -> Result<BSPFile> {
+        self.parse_with(&LoadOptions::default())
+    }
+
+    /// Read & parse the lumps chosen by `options` into an owned `BSPFile`, leaving the others empty.
+    /// Each lump is read into its own buffer, which is dropped once it's parsed, and lumps that aren't loaded aren't read.
+    /// Lumps are checked in the same order as `BSPFileRef::parse_with`, so the same error is returned.
+    pub fn parse_with(&mut self, options: &LoadOptions) -> Result<BSPFile> {
+        let entities = self.entities()?;
+        let textures = self.textures()?;
+        let planes = self.planes()?;
+        let vertices = self.vertices()?;
+        let meshverts = self.meshverts()?;
+        let light_maps = if options.light_maps { Some(self.light_maps()?) } else { None };
+        let light_vols = if options.light_vols { self.light_vols()? } else { LightVolsLump::empty() };
+        let visdata = if options.visdata { self.visdata()? } else { VisDataLump::empty() };
+
+        let brushes = self.brushes(&textures, &planes)?;
+        let effects = self.effects(&brushes)?;
+        let faces = FaceLump::from_lump_with(
+            &self.lump(13)?,
+            &textures,
+            &effects,
+            &vertices,
+            &meshverts,
+            light_maps.as_ref(),
+        )?;
+        let tree = if options.tree { self.tree(&faces, &brushes)? } else { BSPTree::empty() };
+        let models = self.models(&faces, &brushes)?;
+        let light_maps = light_maps.unwrap_or_else(LightMapsLump::empty);
+        let advertisements = if options.advertisements { self.advertisements()? } else { None };
+
+        Ok(BSPFile {
+            directory: self.directory,
+            entities,
+            textures,
+            planes,
+            light_vols,
+            light_maps,
+            vertices,
+            meshverts,
+            visdata,
+            advertisements,
+            brushes,
+            effects,
+            faces,
+            tree,
+            models,
+        })
+    }
+
+    /// Get back the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+/// A memory-mapped BSP file. Lumps are only read from disk as they're used.
+#[cfg(feature = "mmap")]
+#[derive(Debug)]
+pub struct MappedBSPFile {
+    map: Mmap,
+}
+
+#[cfg(feature = "mmap")]
+impl MappedBSPFile {
+    /// Map the file at the given path, and check it can be viewed with `file_ref`.
+    /// # Safety
+    /// The file must not be changed by anything else while it's mapped, otherwise the bytes borrowed from
+    /// `bytes` & `file_ref` may change underneath them. Truncating the file while it's mapped may crash the process.
+    /// Use `BSPFile::open` to read the file into memory instead.
+    pub unsafe fn open<P: AsRef<Path>>(path: P) -> Result<MappedBSPFile> {
+        let file = File::open(path)?;
+
+        // Upheld by the caller.
+        let map = Mmap::map(&file)?;
+        BSPFileRef::from_buffer(&map)?;
+
+        Ok(MappedBSPFile { map })
+    }
+
+    /// A view over the mapped file, which parses lumps as they're asked for.
+    pub fn file_ref(&self) -> BSPFileRef<'_> {
+        // checked in `open`
+        BSPFileRef::from_buffer(&self.map).unwrap()
+    }
+
+    /// The whole file.
+    pub fn bytes(&self) -> &[u8] {
+        &self.map
+    }
+}
@@ -19,7 +19,10 @@
 
 use std::{
     str::Utf8Error,
-    convert::TryInto
+    convert::TryInto,
+    error,
+    fmt,
+    io
 };
 
 /// RGBA Colour (0-255)
@@ -85,14 +88,201 @@ impl RGB {
     }
 }
 
+/// Where in a file an error was found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Location {
+    /// The name of the lump, eg. `"Faces"`.
+    pub lump: &'static str,
+
+    /// The index of the record in the lump, if the error is in one record.
+    pub record: Option<usize>,
+
+    /// The offset in bytes from the start of the lump, if known.
+    pub offset: Option<usize>,
+}
+
+impl Location {
+    /// Somewhere in the given lump.
+    pub fn lump(lump: &'static str) -> Location {
+        Location {
+            lump,
+            record: None,
+            offset: None,
+        }
+    }
+
+    /// In the given record, starting at the given offset from the start of the lump.
+    pub fn record(lump: &'static str, record: usize, offset: usize) -> Location {
+        Location {
+            lump,
+            record: Some(record),
+            offset: Some(offset),
+        }
+    }
+
+    /// At the given offset from the start of a record, whose index isn't known yet.
+    /// Use `Error::at_record` once it is.
+    pub fn field(lump: &'static str, offset: usize) -> Location {
+        Location {
+            lump,
+            record: None,
+            offset: Some(offset),
+        }
+    }
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} lump", self.lump)?;
+        if let Some(record) = self.record {
+            write!(f, ", record {}", record)?;
+        }
+        if let Some(offset) = self.offset {
+            write!(f, ", byte {}", offset)?;
+        }
+
+        Ok(())
+    }
+}
+
 #[derive(Debug)]
 /// An error encountered while parsing.
 pub enum Error {
+    /// The file's version isn't one we can parse.
     Unsupported {
         version: u32,
     },
+
+    /// A problem described only by a message.
+    /// This is what all errors used to be; the variants below carry where the problem is, and are preferred.
+    /// Code matching on this can match on the others with a catch-all arm and use `to_string()` for the same message.
     Invalid {
         error: String
+    },
+
+    /// A lump isn't a whole number of records.
+    BadSize {
+        lump: &'static str,
+        length: usize,
+        record_size: usize,
+    },
+
+    /// A lump's directory entry points outside of the file, or a lump is too short for what it says it holds.
+    /// `offset` & `length` are the bytes that were asked for.
+    OutOfBounds {
+        lump: &'static str,
+        offset: usize,
+        length: usize,
+    },
+
+    /// A record refers to a record in another lump that doesn't exist.
+    BadReference {
+        at: Location,
+
+        /// The field holding the reference.
+        field: &'static str,
+
+        /// The kind of record referred to, eg. `"Texture"`.
+        target: &'static str,
+
+        /// The offending index. For ranges, the end of the range.
+        value: i64,
+    },
+
+    /// A record holds a value that's out of range or otherwise can't be right.
+    BadValue {
+        at: Location,
+        field: &'static str,
+        value: String,
+    },
+
+    /// Reading the file failed.
+    Io {
+        error: io::Error,
+    },
+}
+
+impl Error {
+    /// Fill in which record of the lump an error happened in.
+    /// `offset` is where the record starts, and is added to the offset within the record.
+    /// Errors that aren't about a single record are returned unchanged.
+    pub fn at_record(mut self, record: usize, offset: usize) -> Error {
+        match self {
+            Error::BadReference { ref mut at, .. } | Error::BadValue { ref mut at, .. } if at.record.is_none() => {
+                at.record = Some(record);
+                at.offset = Some(offset + at.offset.unwrap_or(0));
+            }
+            _ => {}
+        }
+
+        self
+    }
+
+    /// Where the error was found, if known.
+    pub fn location(&self) -> Option<Location> {
+        match *self {
+            Error::BadSize { lump, .. } | Error::OutOfBounds { lump, .. } => Some(Location::lump(lump)),
+            Error::BadReference { at, .. } | Error::BadValue { at, .. } => Some(at),
+            Error::Unsupported { .. } | Error::Invalid { .. } | Error::Io { .. } => None,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Unsupported { version } => write!(f, "Unsupported BSP version {}", version),
+            Error::Invalid { ref error } => f.write_str(error),
+            Error::BadSize {
+                lump,
+                length,
+                record_size,
+            } => write!(
+                f,
+                "{} lump is incorrectly sized: {} bytes isn't a multiple of {}",
+                lump, length, record_size
+            ),
+            Error::OutOfBounds {
+                lump,
+                offset,
+                length,
+            } => write!(
+                f,
+                "{} lump is out of bounds: {} bytes at offset {}",
+                lump, length, offset
+            ),
+            Error::BadReference {
+                at,
+                field,
+                target,
+                value,
+            } => write!(
+                f,
+                "{} references {} that doesn't exist: {} is {} ({})",
+                at.lump, target, field, value, at
+            ),
+            Error::BadValue {
+                at,
+                field,
+                ref value,
+            } => write!(f, "{} has invalid {}: {} ({})", at.lump, field, value, at),
+            Error::Io { ref error } => write!(f, "Error reading file: {}", error),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            Error::Io { ref error } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Error {
+        Error::Io { error }
     }
 }
 
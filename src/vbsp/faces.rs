@@ -0,0 +1,161 @@
+// Copyright (C) 2019 Oscar Shrimpton
+//
+// This file is part of stockton-bsp.
+//
+// stockton-bsp is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// stockton-bsp is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with stockton-bsp.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Parses the faces lump of a Source file
+
+use super::texinfo::TexInfoLump;
+use crate::lumps::helpers::{slice_to_f32, slice_to_i16, slice_to_i32, slice_to_u16, slice_to_u32, slice_to_vec2i};
+use crate::lumps::{EdgesLump, PlanesLump, SurfEdgesLump};
+use crate::types::{Location, Result};
+use na::Vector2;
+use std::convert::TryInto;
+use std::ops::Range;
+
+const FACE_SIZE: usize = 2 + 1 + 1 + 4 + (2 * 4) + 4 + 4 + 4 + (4 * 2 * 2) + 4 + (2 * 2) + 4;
+
+/// The faces of a Source map.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FacesLump {
+    pub faces: Box<[Face]>,
+}
+
+/// A face made from edges, with lightmap & displacement information.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Face {
+    pub plane_idx: usize,
+
+    /// True if the face is on the back side of its plane.
+    pub back: bool,
+
+    /// True if the face is on a node, false if it's in a leaf.
+    pub on_node: bool,
+
+    /// Indices into the surface edges lump.
+    pub surf_edges_idx: Range<usize>,
+
+    /// `None` for faces with no texture.
+    pub texinfo_idx: Option<usize>,
+    pub disp_info_idx: Option<usize>,
+
+    /// Light styles, with 255 meaning unused.
+    pub styles: [u8; 4],
+
+    /// Byte offset into the lighting lump, or `None` if the face isn't lit.
+    pub light_offset: Option<usize>,
+
+    /// Area of the face in square units.
+    pub area: f32,
+    pub lightmap_mins: Vector2<i32>,
+    pub lightmap_size: Vector2<i32>,
+
+    /// The original face this was split from.
+    pub orig_face_idx: Option<usize>,
+    pub smoothing_groups: u32,
+}
+
+impl Face {
+    /// The vertex indices of this face's polygon, in order.
+    /// # Panics
+    /// If the face wasn't validated against the given lumps.
+    pub fn vertex_indices<'a>(
+        &self,
+        surf_edges: &'a SurfEdgesLump,
+        edges: &'a EdgesLump,
+    ) -> impl Iterator<Item = usize> + 'a {
+        surf_edges.vertex_indices(self.surf_edges_idx.clone(), edges)
+    }
+}
+
+impl FacesLump {
+    /// Parse the faces lump.
+    /// # Format
+    /// ushort planenum
+    /// byte side
+    /// byte onNode
+    /// int firstedge
+    /// short numedges
+    /// short texinfo
+    /// short dispinfo
+    /// short surfaceFogVolumeID
+    /// byte styles[4]
+    /// int lightofs
+    /// float area
+    /// int LightmapTextureMinsInLuxels[2]
+    /// int LightmapTextureSizeInLuxels[2]
+    /// int origFace
+    /// ushort numPrims
+    /// ushort firstPrimID
+    /// uint smoothingGroups
+    pub fn from_lump(
+        lump: &[u8],
+        planes: &PlanesLump,
+        surf_edges: &SurfEdgesLump,
+        texinfo: &TexInfoLump,
+    ) -> Result<FacesLump> {
+        if lump.len() % FACE_SIZE != 0 {
+            return Err(size_error!("Faces", lump.len(), FACE_SIZE));
+        }
+
+        let mut faces = Vec::with_capacity(lump.len() / FACE_SIZE);
+        for (i, raw) in lump.chunks_exact(FACE_SIZE).enumerate() {
+            let at = |offset| Location::record("Faces", i, (i * FACE_SIZE) + offset);
+            let plane_idx = slice_to_u16(&raw[0..2]) as usize;
+            if plane_idx >= planes.planes.len() {
+                return Err(reference_error!(at(0), "plane", "Plane", plane_idx));
+            }
+
+            let surf_edges_idx = {
+                let start = slice_to_i32(&raw[4..8]);
+                let n = slice_to_i16(&raw[8..10]);
+                if start < 0 || n < 0 || start as usize + n as usize > surf_edges.surf_edges.len() {
+                    return Err(reference_error!(at(4), "surf_edges", "SurfEdge", i64::from(start) + i64::from(n)));
+                }
+
+                start as usize..start as usize + n as usize
+            };
+
+            let texinfo_idx = slice_to_i16(&raw[10..12]);
+            if texinfo_idx as i32 >= texinfo.texinfo.len() as i32 {
+                return Err(reference_error!(at(10), "texinfo", "TexInfo", texinfo_idx));
+            }
+
+            let disp_info_idx = slice_to_i16(&raw[12..14]);
+            let light_offset = slice_to_i32(&raw[20..24]);
+            let orig_face_idx = slice_to_i32(&raw[44..48]);
+
+            faces.push(Face {
+                plane_idx,
+                back: raw[2] != 0,
+                on_node: raw[3] != 0,
+                surf_edges_idx,
+                texinfo_idx: if texinfo_idx < 0 { None } else { Some(texinfo_idx as usize) },
+                disp_info_idx: if disp_info_idx < 0 { None } else { Some(disp_info_idx as usize) },
+                styles: raw[16..20].try_into().unwrap(),
+                light_offset: if light_offset < 0 { None } else { Some(light_offset as usize) },
+                area: slice_to_f32(&raw[24..28]),
+                lightmap_mins: slice_to_vec2i(&raw[28..36]),
+                lightmap_size: slice_to_vec2i(&raw[36..44]),
+                orig_face_idx: if orig_face_idx < 0 { None } else { Some(orig_face_idx as usize) },
+                smoothing_groups: slice_to_u32(&raw[52..56]),
+            });
+        }
+
+        Ok(FacesLump {
+            faces: faces.into_boxed_slice(),
+        })
+    }
+}
@@ -0,0 +1,376 @@
+// Copyright (C) 2019 Oscar Shrimpton
+//
+// This file is part of stockton-bsp.
+//
+// stockton-bsp is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// stockton-bsp is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with stockton-bsp.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Valve Source engine (VBSP versions 19 to 21) files.
+//! Only the core geometry lumps are parsed. Displacements, static props & other game lumps are skipped.
+
+mod brushes;
+mod faces;
+mod models;
+mod texinfo;
+mod tree;
+
+pub use self::brushes::{Brush, BrushSide, BrushesLump, ContentsFlags};
+pub use self::faces::{Face, FacesLump};
+pub use self::models::{Model, ModelsLump};
+pub use self::texinfo::{SurfaceFlags, TexData, TexDataLump, TexInfo, TexInfoLump};
+pub use self::tree::{Leaf, Node, Tree};
+
+use std::convert::TryInto;
+
+use crate::directory::DirEntry;
+use crate::lumps::helpers::slice_to_i32;
+use crate::lumps::*;
+use crate::types::{Error, Result};
+
+/// "VBSP"
+pub const VBSP_MAGIC: &[u8] = b"VBSP";
+
+/// The number of lumps in a Source file.
+pub const VBSP_LUMPS: usize = 64;
+
+/// The name of each lump in a Source file, in directory order.
+/// Some of these were renamed or reused between games, this uses the names from the 2013 SDK.
+pub const VBSP_LUMP_NAMES: [&str; VBSP_LUMPS] = [
+    "Entities",
+    "Planes",
+    "TexData",
+    "Vertices",
+    "Visibility",
+    "Nodes",
+    "TexInfo",
+    "Faces",
+    "Lighting",
+    "Occlusion",
+    "Leaves",
+    "FaceIds",
+    "Edges",
+    "SurfEdges",
+    "Models",
+    "WorldLights",
+    "LeafFaces",
+    "LeafBrushes",
+    "Brushes",
+    "BrushSides",
+    "Areas",
+    "AreaPortals",
+    "Portals",
+    "Clusters",
+    "PortalVerts",
+    "ClusterPortals",
+    "DispInfo",
+    "OriginalFaces",
+    "PhysDisp",
+    "PhysCollide",
+    "VertNormals",
+    "VertNormalIndices",
+    "DispLightmapAlphas",
+    "DispVerts",
+    "DispLightmapSamplePositions",
+    "GameLump",
+    "LeafWaterData",
+    "Primitives",
+    "PrimVerts",
+    "PrimIndices",
+    "PakFile",
+    "ClipPortalVerts",
+    "Cubemaps",
+    "TexDataStringData",
+    "TexDataStringTable",
+    "Overlays",
+    "LeafMinDistToWater",
+    "FaceMacroTextureInfo",
+    "DispTris",
+    "PhysCollideSurface",
+    "WaterOverlays",
+    "LeafAmbientIndexHDR",
+    "LeafAmbientIndex",
+    "LightingHDR",
+    "WorldLightsHDR",
+    "LeafAmbientLightingHDR",
+    "LeafAmbientLighting",
+    "XZipPakFile",
+    "FacesHDR",
+    "MapFlags",
+    "OverlayFades",
+    "OverlaySystemLevels",
+    "PhysLevel",
+    "DispMultiBlend",
+];
+
+const LUMP_ENTRY_SIZE: usize = 4 * 4;
+const HEADER_LEN: usize = 4 + 4 + (VBSP_LUMPS * LUMP_ENTRY_SIZE) + 4;
+
+/// A directory entry in a Source file, which also has a version for the lump's format.
+#[derive(Clone, Copy, Debug)]
+pub struct LumpEntry {
+    /// Offset from beginning of file to start of lump
+    pub offset: u32,
+
+    /// Length of lump
+    pub length: u32,
+
+    /// Version of the lump's format
+    pub version: i32,
+
+    /// The uncompressed size if the lump is compressed, otherwise zero.
+    /// Called `fourCC` in the Source SDK.
+    pub uncompressed_size: i32,
+}
+
+/// The header found at the start of a Source bsp file.
+#[derive(Clone, Copy, Debug)]
+pub struct VBSPHeader {
+    pub version: u32,
+    pub lumps: [LumpEntry; VBSP_LUMPS],
+
+    /// The number of times the map has been saved in Hammer.
+    pub map_revision: i32,
+}
+
+impl VBSPHeader {
+    /// Deserialise from buffer.
+    /// # Format
+    /// string[4] magic             Magic number. Always "VBSP".
+    /// int version                 Version number. 19 to 21 for most Source games.
+    /// lump_t[64] lumps            Lump directory. Each is an offset, length, version & fourCC.
+    /// int mapRevision
+    pub fn from(v: &[u8]) -> Result<VBSPHeader> {
+        if v.len() < HEADER_LEN {
+            return Err(invalid_error!("Header is too short"));
+        }
+
+        if &v[0..4] != VBSP_MAGIC {
+            return Err(invalid_error!("Header magic is incorrect"));
+        }
+
+        let mut lumps = [LumpEntry {
+            offset: 0,
+            length: 0,
+            version: 0,
+            uncompressed_size: 0,
+        }; VBSP_LUMPS];
+
+        for (n, entry) in lumps.iter_mut().enumerate() {
+            let base = &v[8 + (n * LUMP_ENTRY_SIZE)..8 + ((n + 1) * LUMP_ENTRY_SIZE)];
+            *entry = LumpEntry {
+                offset: u32::from_le_bytes(base[0..4].try_into().unwrap()),
+                length: u32::from_le_bytes(base[4..8].try_into().unwrap()),
+                version: slice_to_i32(&base[8..12]),
+                uncompressed_size: slice_to_i32(&base[12..16]),
+            };
+        }
+
+        Ok(VBSPHeader {
+            version: u32::from_le_bytes(v[4..8].try_into().unwrap()),
+            lumps,
+            map_revision: slice_to_i32(&v[HEADER_LEN - 4..HEADER_LEN]),
+        })
+    }
+
+    /// Get the lump at given index from the buffer, checking it's inside the buffer and isn't compressed.
+    pub fn get_lump<'l>(&self, buf: &'l [u8], index: usize) -> Result<&'l [u8]> {
+        let entry = self.lumps[index];
+        if entry.uncompressed_size != 0 {
+            return Err(invalid_error!("{} lump is compressed, which isn't supported", VBSP_LUMP_NAMES[index]));
+        }
+
+        DirEntry {
+            offset: entry.offset,
+            length: entry.length,
+        }
+        .get_lump(buf)
+        .ok_or(Error::OutOfBounds {
+            lump: VBSP_LUMP_NAMES[index],
+            offset: entry.offset as usize,
+            length: entry.length as usize,
+        })
+    }
+}
+
+/// Represents a parsed Source BSP file.
+#[derive(Debug, Clone)]
+pub struct VBSPFile {
+    pub directory: VBSPHeader,
+    pub entities: EntitiesLump,
+    pub planes: PlanesLump,
+    pub texdata: TexDataLump,
+    pub vertices: PositionsLump,
+    pub visibility: ClusterVisLump,
+    pub tree: Tree,
+    pub texinfo: TexInfoLump,
+    pub faces: FacesLump,
+    pub edges: EdgesLump,
+    pub surf_edges: SurfEdgesLump,
+    pub models: ModelsLump,
+    pub brushes: BrushesLump,
+}
+
+impl VBSPFile {
+    /// Try to parse the given buffer as a Source BSP file
+    pub fn from_buffer(buf: Box<[u8]>) -> Result<VBSPFile> {
+        let header = VBSPHeader::from(&buf)?;
+        if !(19..=21).contains(&header.version) {
+            return Err(Error::Unsupported {
+                version: header.version,
+            });
+        }
+
+        let lump = |i| header.get_lump(&buf, i);
+
+        let entities = EntitiesLump::from_lump(lump(0)?)?;
+        let planes = PlanesLump::from_typed_lump(lump(1)?)?;
+        let texdata = TexDataLump::from_lump(lump(2)?, lump(44)?, lump(43)?)?;
+        let vertices = PositionsLump::from_lump(lump(3)?)?;
+        let texinfo = TexInfoLump::from_lump(lump(6)?, &texdata)?;
+        let edges = EdgesLump::from_lump(lump(12)?, &vertices)?;
+        let surf_edges = SurfEdgesLump::from_lump(lump(13)?, &edges)?;
+        let faces = FacesLump::from_lump(lump(7)?, &planes, &surf_edges, &texinfo)?;
+        let brushes = BrushesLump::from_lump(lump(18)?, lump(19)?, &planes, &texinfo)?;
+        let tree = Tree::from_lumps(
+            lump(5)?,
+            lump(10)?,
+            header.lumps[10].version,
+            lump(16)?,
+            lump(17)?,
+            &planes,
+            &faces,
+            &brushes,
+        )?;
+        let n_clusters = tree.leaves.iter().filter_map(|l| l.cluster).max().map_or(0, |c| c + 1);
+        let visibility = ClusterVisLump::from_lump(lump(4)?, n_clusters)?;
+        let models = ModelsLump::from_lump(lump(14)?, &faces, &tree)?;
+
+        Ok(VBSPFile {
+            directory: header,
+            entities,
+            planes,
+            texdata,
+            vertices,
+            visibility,
+            tree,
+            texinfo,
+            faces,
+            edges,
+            surf_edges,
+            models,
+            brushes,
+        })
+    }
+}
+
+#[test]
+fn vbsp_synthetic_file() {
+    use crate::lumps::test_util::{floats, ints, shorts};
+    use na::Vector3;
+
+    let mut texdata = floats(&[0.5, 0.5, 0.5]);
+    texdata.extend(ints(&[1, 512, 256, 512, 256]));
+
+    let mut texinfo = floats(&[1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0]);
+    texinfo.extend(floats(&[0.0625, 0.0, 0.0, 0.0, 0.0, 0.0625, 0.0, 0.0]));
+    texinfo.extend(ints(&[0x400, 0]));
+
+    let mut face = shorts(&[0]);
+    face.extend(&[0, 1]);
+    face.extend(ints(&[0]));
+    face.extend(shorts(&[3, 0, -1, -1]));
+    face.extend(&[0, 255, 255, 255]);
+    face.extend(ints(&[0]));
+    face.extend(floats(&[2048.0]));
+    face.extend(ints(&[-2, -2, 4, 4, -1]));
+    face.extend(shorts(&[0, 0]));
+    face.extend(ints(&[1]));
+
+    let mut node = ints(&[0, -1, -2]);
+    node.extend(shorts(&[-64, -64, -64, 64, 64, 64, 0, 1, 0, 0]));
+
+    let mut leaves = ints(&[0]);
+    leaves.extend(shorts(&[0, 1 | (2 << 9), -64, -64, 0, 64, 64, 64, 0, 1, 0, 0, -1, 0]));
+    leaves.extend(ints(&[1]));
+    leaves.extend(shorts(&[-1, 0, -64, -64, -64, 64, 64, 0, 0, 0, 0, 1, -1, 0]));
+
+    let mut model = floats(&[-64.0, -64.0, -64.0, 64.0, 64.0, 64.0, 0.0, 0.0, 0.0]);
+    model.extend(ints(&[0, 0, 1]));
+
+    let mut lumps = vec![(0, vec![]); VBSP_LUMPS];
+    lumps[0].1 = b"{\n\"classname\" \"worldspawn\"\n}\n\0".to_vec();
+    lumps[1].1 = floats(&[0.0, 0.0, 1.0, 0.0, 2.0]);
+    lumps[2].1 = texdata;
+    lumps[3].1 = floats(&[0.0, 0.0, 0.0, 64.0, 0.0, 0.0, 0.0, 64.0, 0.0]);
+    lumps[5].1 = node;
+    lumps[6].1 = texinfo;
+    lumps[7].1 = face;
+    lumps[10] = (1, leaves);
+    lumps[12].1 = shorts(&[0, 0, 0, 1, 1, 2, 0, 2]);
+    lumps[13].1 = ints(&[1, 2, -3]);
+    lumps[14].1 = model;
+    lumps[16].1 = shorts(&[0]);
+    lumps[17].1 = shorts(&[0]);
+    lumps[18].1 = ints(&[0, 1, 1]);
+    lumps[19].1 = [shorts(&[0, 0, -1]), vec![1, 0]].concat();
+    lumps[43].1 = b"tools/toolsnodraw\0dev/dev_measuregeneric01\0".to_vec();
+    lumps[44].1 = ints(&[0, 18]);
+
+    let mut buf = Vec::new();
+    buf.extend_from_slice(VBSP_MAGIC);
+    buf.extend(ints(&[20]));
+    let mut offset = HEADER_LEN;
+    for (version, lump) in lumps.iter() {
+        buf.extend(ints(&[offset as i32, lump.len() as i32, *version, 0]));
+        offset += lump.len();
+    }
+    buf.extend(ints(&[7]));
+    for (_, lump) in lumps.iter() {
+        buf.extend(lump);
+    }
+
+    let file = VBSPFile::from_buffer(buf.into_boxed_slice()).unwrap();
+
+    assert_eq!(file.directory.version, 20);
+    assert_eq!(file.directory.map_revision, 7);
+    assert_eq!(file.directory.lumps[10].version, 1);
+
+    assert_eq!(file.texdata.texdata[0].name, "dev/dev_measuregeneric01");
+    assert_eq!(file.texdata.texdata[0].width, 512);
+    assert_eq!(file.texinfo.texinfo[0].flags, SurfaceFlags::NO_LIGHT);
+    assert_eq!(file.texinfo.texinfo[0].texdata_idx, Some(0));
+
+    let face = &file.faces.faces[0];
+    assert!(face.on_node);
+    assert_eq!(face.disp_info_idx, None);
+    assert_eq!(face.lightmap_size, na::Vector2::new(4, 4));
+    assert_eq!(
+        face.vertex_indices(&file.surf_edges, &file.edges).collect::<Vec<_>>(),
+        vec![0, 1, 2]
+    );
+
+    let above = file.tree.find_leaf(0, Vector3::new(0.0, 0.0, 8.0), &file.planes).unwrap();
+    assert_eq!(above.cluster, Some(0));
+    assert_eq!((above.area, above.flags), (1, 2));
+    assert_eq!(above.water_data_idx, None);
+
+    let below = file.tree.find_leaf(0, Vector3::new(0.0, 0.0, -8.0), &file.planes).unwrap();
+    assert_eq!(below.contents, ContentsFlags::SOLID);
+    assert_eq!(&*below.brushes_idx, &[0]);
+
+    let side = &file.brushes.brushes[0].sides[0];
+    assert_eq!(side.texinfo_idx, Some(0));
+    assert!(side.bevel);
+
+    assert_eq!(file.models.models[0].faces_idx, 0..1);
+}
@@ -0,0 +1,206 @@
+// Copyright (C) 2019 Oscar Shrimpton
+//
+// This file is part of stockton-bsp.
+//
+// stockton-bsp is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// stockton-bsp is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with stockton-bsp.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Parses the texinfo & texdata lumps of a Source file
+
+use crate::lumps::helpers::{slice_to_cstr, slice_to_f32, slice_to_i32, slice_to_u32, slice_to_vec3};
+use crate::types::{Location, Result};
+use na::Vector3;
+
+const TEXINFO_SIZE: usize = (4 * 4 * 2 * 2) + 4 + 4;
+const TEXDATA_SIZE: usize = (4 * 3) + (4 * 5);
+
+/// Texture & lightmap projections, and surface properties.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TexInfoLump {
+    pub texinfo: Box<[TexInfo]>,
+}
+
+/// A record from a `TexInfoLump`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TexInfo {
+    /// The s & t texture projection vectors, with the offset as the last element.
+    pub texture_vecs: [[f32; 4]; 2],
+
+    /// The s & t lightmap projection vectors, with the offset as the last element.
+    pub lightmap_vecs: [[f32; 4]; 2],
+    pub flags: SurfaceFlags,
+
+    /// `None` for surfaces with no texture, such as nodraw.
+    pub texdata_idx: Option<usize>,
+}
+
+impl TexInfoLump {
+    /// Parse the texinfo lump.
+    /// # Format
+    /// float textureVecs[2][4]
+    /// float lightmapVecs[2][4]
+    /// int flags
+    /// int texdata         Index into the texdata lump, or -1.
+    pub fn from_lump(lump: &[u8], texdata: &TexDataLump) -> Result<TexInfoLump> {
+        if lump.len() % TEXINFO_SIZE != 0 {
+            return Err(size_error!("TexInfo", lump.len(), TEXINFO_SIZE));
+        }
+
+        let mut texinfo = Vec::with_capacity(lump.len() / TEXINFO_SIZE);
+        for (i, raw) in lump.chunks_exact(TEXINFO_SIZE).enumerate() {
+            let mut vecs = [[[0.0; 4]; 2]; 2];
+            for (n, v) in vecs.iter_mut().flatten().flatten().enumerate() {
+                *v = slice_to_f32(&raw[n * 4..(n + 1) * 4]);
+            }
+
+            let texdata_idx = slice_to_i32(&raw[68..72]);
+            if texdata_idx >= texdata.texdata.len() as i32 {
+                let at = Location::record("TexInfo", i, (i * TEXINFO_SIZE) + 68);
+                return Err(reference_error!(at, "texdata", "TexData", texdata_idx));
+            }
+
+            texinfo.push(TexInfo {
+                texture_vecs: vecs[0],
+                lightmap_vecs: vecs[1],
+                flags: SurfaceFlags::from_bits_truncate(slice_to_u32(&raw[64..68])),
+                texdata_idx: if texdata_idx < 0 { None } else { Some(texdata_idx as usize) },
+            });
+        }
+
+        Ok(TexInfoLump {
+            texinfo: texinfo.into_boxed_slice(),
+        })
+    }
+}
+
+/// Information about the textures used in the map.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TexDataLump {
+    pub texdata: Box<[TexData]>,
+}
+
+/// A record from a `TexDataLump`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TexData {
+    /// Average colour of the texture, used by vrad.
+    pub reflectivity: Vector3<f32>,
+
+    /// Material name, relative to `materials/` and without an extension.
+    pub name: String,
+    pub width: i32,
+    pub height: i32,
+    pub view_width: i32,
+    pub view_height: i32,
+}
+
+impl TexDataLump {
+    /// Parse the texdata lump, looking up names in the string table & string data lumps.
+    /// # Format
+    /// float reflectivity[3]
+    /// int nameStringTableID   Index into the string table, which has offsets into the string data.
+    /// int width
+    /// int height
+    /// int view_width
+    /// int view_height
+    pub fn from_lump(lump: &[u8], string_table: &[u8], string_data: &[u8]) -> Result<TexDataLump> {
+        if lump.len() % TEXDATA_SIZE != 0 {
+            return Err(size_error!("TexData", lump.len(), TEXDATA_SIZE));
+        }
+        if string_table.len() % 4 != 0 {
+            return Err(size_error!("TexDataStringTable", string_table.len(), 4));
+        }
+
+        let mut texdata = Vec::with_capacity(lump.len() / TEXDATA_SIZE);
+        for (i, raw) in lump.chunks_exact(TEXDATA_SIZE).enumerate() {
+            let name = {
+                let idx = slice_to_i32(&raw[12..16]);
+                let offset = string_table
+                    .get((idx as usize).wrapping_mul(4)..(idx as usize).wrapping_mul(4).wrapping_add(4))
+                    .map(slice_to_i32)
+                    .ok_or_else(|| {
+                        let at = Location::record("TexData", i, (i * TEXDATA_SIZE) + 12);
+                        reference_error!(at, "name", "TexDataStringTable", idx)
+                    })?;
+
+                let at = Location::record("TexDataStringTable", idx as usize, idx as usize * 4);
+                slice_to_cstr(string_data.get(offset as usize..).ok_or_else(|| value_error!(at, "offset", offset))?)?
+                    .to_owned()
+            };
+
+            texdata.push(TexData {
+                reflectivity: slice_to_vec3(&raw[0..12]),
+                name,
+                width: slice_to_i32(&raw[16..20]),
+                height: slice_to_i32(&raw[20..24]),
+                view_width: slice_to_i32(&raw[24..28]),
+                view_height: slice_to_i32(&raw[28..32]),
+            });
+        }
+
+        Ok(TexDataLump {
+            texdata: texdata.into_boxed_slice(),
+        })
+    }
+}
+
+bitflags!(
+    /// Extracted from the Source SDK.
+    /// https://github.com/ValveSoftware/source-sdk-2013/blob/master/mp/src/public/bspflags.h
+    pub struct SurfaceFlags: u32 {
+        /// value will hold the light strength
+        const LIGHT = 0x1;
+
+        /// don't draw, indicates we should skylight + draw 2d sky but not draw the 3D skybox
+        const SKY_2D = 0x2;
+
+        /// don't draw, but add to skybox
+        const SKY = 0x4;
+
+        /// turbulent water warp
+        const WARP = 0x8;
+        const TRANS = 0x10;
+
+        /// the surface can not have a portal placed on it
+        const NO_PORTAL = 0x20;
+
+        /// This is an xbox hack to work around elimination of trigger surfaces, which breaks occluders
+        const TRIGGER = 0x40;
+
+        /// don't bother referencing the texture
+        const NODRAW = 0x80;
+
+        /// make a primary bsp splitter
+        const HINT = 0x01_00;
+
+        /// completely ignore, allowing non-closed brushes
+        const SKIP = 0x02_00;
+
+        /// Don't calculate light
+        const NO_LIGHT = 0x04_00;
+
+        /// calculate three lightmaps for the surface for bumpmapping
+        const BUMP_LIGHT = 0x08_00;
+
+        /// Don't receive shadows
+        const NO_SHADOWS = 0x10_00;
+
+        /// Don't receive decals
+        const NO_DECALS = 0x20_00;
+
+        /// Don't subdivide patches on this surface
+        const NO_CHOP = 0x40_00;
+
+        /// surface is part of a hitbox
+        const HITBOX = 0x80_00;
+    }
+);
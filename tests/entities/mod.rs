@@ -15,17 +15,14 @@
 // You should have received a copy of the GNU General Public License
 // along with stockton-bsp.  If not, see <http://www.gnu.org/licenses/>.
 
+use na::Vector3;
 use stockton_bsp::lumps::entities::{EntitiesLump, Entity};
+use stockton_bsp::lumps::entity_graph::{DanglingTarget, EntityGraph, LinkKind};
+use stockton_bsp::types::Error;
 
-macro_rules! map(
+macro_rules! pairs(
     { $($key:expr => $value:expr),+ } => {
-        {
-            let mut m = ::std::collections::HashMap::new();
-            $(
-                m.insert(($key).to_string(), ($value).to_string());
-            )+
-            m
-        }
+        vec![$((($key).to_string(), ($value).to_string())),+]
     };
 );
 
@@ -48,19 +45,175 @@ fn entities_valid() {
     assert_eq!(
         valid.entities,
         vec![
-            Entity {
-                attributes: map!(
-                    "classname" => "weapon_rocketlauncher",
-                    "origin" => "1 2 3",
-                    "angle" => "90"
-                )
-            },
-            Entity {
-                attributes: map!(
-                    "classname" => "worldspawn",
-                    "message" => "Hello, World!"
-                )
-            }
+            Entity::from_pairs(pairs!(
+                "classname" => "weapon_rocketlauncher",
+                "origin" => "1 2 3",
+                "angle" => "90"
+            )),
+            Entity::from_pairs(pairs!(
+                "classname" => "worldspawn",
+                "message" => "Hello, World!"
+            ))
         ]
     );
 }
+
+#[test]
+fn entities_typed_keys() {
+    let lump = EntitiesLump::from_lump(
+        br#"
+        {
+            "classname" "worldspawn"
+        }
+        {
+            "classname" "func_door"
+            "model" "*1"
+            "origin" "1 -2 3.5"
+            "angle" "90"
+            "spawnflags" "5"
+            "targetname" "door"
+        }
+        {
+            "classname" "misc_model"
+            "model" "models/tree.md3"
+            "angles" "10 20 30"
+            "spawnflags" "lots"
+            "origin" "1 2"
+            "target" "door"
+        }
+    "#,
+    )
+    .unwrap();
+
+    assert_eq!(lump.worldspawn(), Some(&lump.entities[0]));
+
+    let door = &lump.entities[1];
+    assert_eq!(door.classname(), Some("func_door"));
+    assert_eq!(door.origin().unwrap(), Some(Vector3::new(1.0, -2.0, 3.5)));
+    assert_eq!(door.angles().unwrap(), Some(Vector3::new(0.0, 90.0, 0.0)));
+    assert_eq!(door.spawnflags().unwrap(), Some(5));
+    assert_eq!(door.targetname(), Some("door"));
+    assert_eq!(door.target(), None);
+
+    let model = &lump.entities[2];
+    assert_eq!(model.angles().unwrap(), Some(Vector3::new(10.0, 20.0, 30.0)));
+    assert_eq!(model.target(), Some("door"));
+    match model.spawnflags() {
+        Err(Error::BadValue { at, field: "spawnflags", value }) => {
+            assert_eq!(at, model.location());
+            assert_eq!(value, "lots");
+        }
+        other => panic!("expected a bad spawnflags value, got {:?}", other),
+    }
+    match model.origin() {
+        Err(e @ Error::BadValue { field: "origin", .. }) => assert_eq!(e.location().unwrap().record, Some(2)),
+        other => panic!("expected a bad origin value, got {:?}", other),
+    }
+    assert_eq!(model.get("spawnflags"), Some("lots"));
+
+    assert_eq!(lump.entities[0].origin().unwrap(), None);
+}
+
+#[test]
+fn entities_order_and_duplicates() {
+    let lump = EntitiesLump::from_lump(
+        b"{\n\"classname\" \"target_relay\"\n\"target\" \"a\"\n\"target\" \"b\"\n}\n\0",
+    )
+    .unwrap();
+
+    let relay = &lump.entities[0];
+    assert_eq!(relay.pairs(), pairs!("classname" => "target_relay", "target" => "a", "target" => "b"));
+    assert_eq!(relay.get_all("target").collect::<Vec<_>>(), vec!["a", "b"]);
+    assert_eq!(relay.target(), Some("b"));
+
+    let mut edited = relay.clone();
+    edited.insert("target".to_string(), "c".to_string());
+    assert_eq!(edited.pairs(), pairs!("classname" => "target_relay", "target" => "c"));
+    edited.push("target".to_string(), "d".to_string());
+    assert_eq!(edited.get_all("target").collect::<Vec<_>>(), vec!["c", "d"]);
+    edited.remove("target");
+    assert_eq!(edited.target(), None);
+}
+
+#[test]
+fn entities_comments_escapes_latin1() {
+    let lump = EntitiesLump::from_lump(
+        b"// a comment { \"with\" \"quotes\" }\n{\n\"message\" \"say \\\"hi\\\" caf\xe9\" // trailing\n\"model\" \"models\\tree.md3\"\n}\n",
+    )
+    .unwrap();
+
+    assert_eq!(lump.entities.len(), 1);
+    assert_eq!(lump.entities[0].get("message"), Some("say \"hi\" caf\u{e9}"));
+    assert_eq!(lump.entities[0].model(), Some("models\\tree.md3"));
+
+    assert!(EntitiesLump::from_lump(b"{\n\"key\" \"unterminated\n}").is_err());
+    assert!(EntitiesLump::from_lump(b"{\n\"key\"\n}").is_err());
+}
+
+#[test]
+fn entities_round_trip() {
+    let source: &[u8] = b"{\r\n  \"classname\"   \"worldspawn\" // comment\r\n\"a\" \"1\"\n\"a\" \"2\"\n}\n\0";
+    let lump = EntitiesLump::from_lump(source).unwrap();
+    assert_eq!(lump.to_lump(), source);
+
+    // once changed, entities are written out fresh, keeping their order & escapes
+    let mut changed = lump.clone();
+    changed.entities[0].push("message".to_string(), "a \"quote\" \\".to_string());
+    let written = changed.to_lump();
+    assert_eq!(
+        written,
+        &b"{\n\"classname\" \"worldspawn\"\n\"a\" \"1\"\n\"a\" \"2\"\n\"message\" \"a \\\"quote\\\" \\\\\"\n}\n\0"[..]
+    );
+    assert_eq!(EntitiesLump::from_lump(&written).unwrap(), changed);
+
+    // entities that weren't changed are copied as they were, so Latin-1 strings aren't re-encoded as UTF-8
+    let source: &[u8] = b"{\n\"classname\" \"worldspawn\"\n}\n{ \"message\" \"caf\xe9\" }\n\0";
+    let mut changed = EntitiesLump::from_lump(source).unwrap();
+    changed.entities[0].insert("music".to_string(), "music/intro.wav".to_string());
+    let written = changed.to_lump();
+    assert_eq!(
+        written,
+        &b"{\n\"classname\" \"worldspawn\"\n\"music\" \"music/intro.wav\"\n}\n{ \"message\" \"caf\xe9\" }\n\0"[..]
+    );
+    assert_eq!(EntitiesLump::from_lump(&written).unwrap(), changed);
+}
+
+#[test]
+fn entities_graph() {
+    let lump = EntitiesLump::from_lump(
+        br#"
+        { "classname" "worldspawn" }
+        { "classname" "trigger_multiple" "target" "relay" }
+        { "classname" "target_relay" "targetname" "relay" "target" "door" "target" "missing" "killtarget" "light" }
+        { "classname" "func_door" "targetname" "door" "team" "doors" "target2" "loop" }
+        { "classname" "func_door" "targetname" "door" "team" "doors" }
+        { "classname" "target_delay" "targetname" "loop" "target" "relay" }
+        { "classname" "light" "targetname" "light" }
+        { "classname" "target_relay" "targetname" "self" "target" "self" }
+    "#,
+    )
+    .unwrap();
+
+    let graph = EntityGraph::new(&lump);
+
+    assert_eq!(graph.fires(1).collect::<Vec<_>>(), vec![2]);
+    assert_eq!(graph.fires(2).collect::<Vec<_>>(), vec![3, 4]);
+    assert_eq!(graph.kills(2).collect::<Vec<_>>(), vec![6]);
+    assert_eq!(graph.fired_by(2).collect::<Vec<_>>(), vec![1, 5]);
+    assert_eq!(graph.team(3).collect::<Vec<_>>(), vec![4]);
+    assert_eq!(graph.team(4).collect::<Vec<_>>(), vec![3]);
+    assert_eq!(graph.links_from(0), &[]);
+
+    assert_eq!(
+        graph.dangling(),
+        &[DanglingTarget {
+            kind: LinkKind::Target,
+            from: 2,
+            name: "missing".to_string()
+        }]
+    );
+
+    let mut cycles = graph.cycles();
+    cycles.sort();
+    assert_eq!(cycles, vec![vec![2, 3, 5], vec![7]]);
+}
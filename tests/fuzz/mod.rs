@@ -0,0 +1,118 @@
+// Copyright (C) 2019 Oscar Shrimpton
+//
+// This file is part of stockton-bsp.
+//
+// stockton-bsp is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// stockton-bsp is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with stockton-bsp.  If not, see <http://www.gnu.org/licenses/>.
+
+//! A corpus of mutated copies of `test.bsp`, none of which should make parsing panic.
+//! `fuzz/` has a cargo-fuzz target for searching further.
+
+use std::panic;
+
+use stockton_bsp::BSPFile;
+
+const TEST_BSP: &[u8] = include_bytes!("../test.bsp");
+
+/// Values most likely to break index & length checks.
+const INTERESTING: [i32; 8] = [-1, -2, 0, 1, i32::MIN, i32::MAX, 0x7fff, 0x10000];
+
+/// Deterministic xorshift, so failures can be reproduced from the seed.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
+
+/// Make a mutated copy of `test.bsp` from the given seed.
+fn mutate(seed: u64) -> Vec<u8> {
+    let mut rng = Rng(seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1);
+    let mut data = TEST_BSP.to_vec();
+
+    for _ in 0..1 + rng.below(4) {
+        match rng.below(5) {
+            // flip some bits
+            0 => {
+                for _ in 0..1 + rng.below(16) {
+                    let at = rng.below(data.len());
+                    data[at] ^= 1 << rng.below(8);
+                }
+            }
+
+            // write an interesting value over an aligned field, where most counts & indices are
+            1 => {
+                let at = rng.below(data.len() / 4) * 4;
+                let value = INTERESTING[rng.below(INTERESTING.len())];
+                data[at..at + 4].copy_from_slice(&value.to_le_bytes());
+            }
+
+            // break a directory entry
+            2 => {
+                let at = 8 + rng.below(17 * 2) * 4;
+                let value = INTERESTING[rng.below(INTERESTING.len())];
+                data[at..at + 4].copy_from_slice(&value.to_le_bytes());
+            }
+
+            // truncate
+            3 => {
+                let len = rng.below(data.len());
+                data.truncate(len);
+            }
+
+            // claim to be Quake Live, which has an extra directory entry
+            _ => data[4] = 0x2f,
+        }
+
+        if data.len() < 4 {
+            break;
+        }
+    }
+
+    data
+}
+
+#[test]
+fn fuzz_corpus() {
+    for seed in 0..3000 {
+        let data = mutate(seed).into_boxed_slice();
+        let result = panic::catch_unwind(|| BSPFile::from_buffer(data).map(|_| ()));
+
+        assert!(result.is_ok(), "parsing mutant {} panicked", seed);
+    }
+}
+
+#[test]
+fn fuzz_truncated() {
+    for len in (0..TEST_BSP.len()).step_by(97) {
+        assert!(BSPFile::from_buffer(TEST_BSP[..len].into()).is_err());
+    }
+}
+
+#[test]
+fn fuzz_tree_cycle() {
+    let mut data = TEST_BSP.to_vec();
+    let nodes = u32::from_le_bytes([data[8 + 24], data[9 + 24], data[10 + 24], data[11 + 24]]) as usize;
+
+    // make the root node its own first child
+    data[nodes + 4..nodes + 8].copy_from_slice(&0i32.to_le_bytes());
+    assert!(BSPFile::from_buffer(data.into_boxed_slice()).is_err());
+}
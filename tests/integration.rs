@@ -15,13 +15,619 @@
 // You should have received a copy of the GNU General Public License
 // along with stockton-bsp.  If not, see <http://www.gnu.org/licenses/>.
 
+extern crate nalgebra as na;
 extern crate stockton_bsp;
 
-use stockton_bsp::BSPFile;
+use na::{Vector2, Vector3};
+use std::io::{self, Cursor, Read, Seek, SeekFrom};
+use stockton_bsp::lumps::light_vols::LightGrid;
+use stockton_bsp::lumps::planes::Plane;
+use stockton_bsp::export::{GltfExport, GltfOptions, ObjExport, ObjOptions};
+use stockton_bsp::collision::Tracer;
+use stockton_bsp::lumps::entities::Entity;
+use stockton_bsp::lumps::faces::FaceType;
+use stockton_bsp::lumps::textures::{ContentsFlags, SurfaceFlags};
+use stockton_bsp::lumps::FlatTree;
+use stockton_bsp::mesh::{BatchKey, Mesh, MeshOptions};
+use stockton_bsp::types::{Error, Location};
+use stockton_bsp::{AnyBSPFile, BSPFile, BSPFileRef, BSPReader, LoadOptions};
 
 #[test]
 fn test_basic() {
     let data = include_bytes!("./test.bsp").to_vec().into_boxed_slice();
 
     let _lump = BSPFile::from_buffer(data).unwrap();
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_any_format() {
+    let data = include_bytes!("./test.bsp").to_vec().into_boxed_slice();
+
+    match AnyBSPFile::from_buffer(data).unwrap() {
+        AnyBSPFile::Quake3(file) => assert_eq!(file.directory.version, 0x2e),
+        other => panic!("Detected wrong format: {:?}", other),
+    }
+}
+
+#[test]
+fn test_round_trip() {
+    let data = include_bytes!("./test.bsp").to_vec().into_boxed_slice();
+
+    let original = BSPFile::from_buffer(data).unwrap();
+    let written = original.to_buffer().unwrap();
+    let reparsed = BSPFile::from_buffer(written.clone()).unwrap();
+
+    assert_eq!(reparsed.directory.version, original.directory.version);
+    assert_eq!(reparsed.entities, original.entities);
+    assert_eq!(reparsed.textures, original.textures);
+    assert_eq!(reparsed.planes, original.planes);
+    assert_eq!(reparsed.light_vols, original.light_vols);
+    assert_eq!(reparsed.brushes, original.brushes);
+    assert_eq!(reparsed.vertices, original.vertices);
+    assert_eq!(reparsed.meshverts, original.meshverts);
+    assert_eq!(reparsed.light_maps, original.light_maps);
+    assert_eq!(reparsed.effects, original.effects);
+    assert_eq!(reparsed.faces, original.faces);
+    assert_eq!(reparsed.tree, original.tree);
+    assert_eq!(reparsed.visdata, original.visdata);
+    assert_eq!(reparsed.models, original.models);
+    assert_eq!(reparsed.advertisements, original.advertisements);
+
+    // Writing is deterministic
+    assert_eq!(reparsed.to_buffer().unwrap(), written);
+}
+
+#[test]
+fn test_visible_faces() {
+    let data = include_bytes!("./test.bsp").to_vec().into_boxed_slice();
+    let file = BSPFile::from_buffer(data).unwrap();
+
+    // info_player_start
+    let origin = Vector3::new(-8.0, -6.0, 15.0);
+
+    let faces = file.tree.visible_faces(origin, &file.planes, &file.visdata, None);
+    assert!(!faces.is_empty());
+    assert!(faces.windows(2).all(|w| w[0] < w[1]));
+    assert!(faces.iter().all(|f| (*f as usize) < file.faces.faces.len()));
+
+    // Nothing is in front of a plane above the map
+    let above = [Plane {
+        normal: Vector3::new(0.0, 0.0, 1.0),
+        dist: 10000.0,
+    }];
+    let culled = file.tree.visible_faces(origin, &file.planes, &file.visdata, Some(&above));
+    assert!(culled.is_empty());
+
+    // Everything is in front of a plane below the map
+    let below = [Plane {
+        normal: Vector3::new(0.0, 0.0, 1.0),
+        dist: -10000.0,
+    }];
+    let unculled = file.tree.visible_faces(origin, &file.planes, &file.visdata, Some(&below));
+    assert_eq!(unculled, faces);
+}
+
+#[test]
+fn test_file_ref() {
+    let data = include_bytes!("./test.bsp");
+    let file = BSPFile::from_buffer(data.to_vec().into_boxed_slice()).unwrap();
+
+    let view = BSPFileRef::from_buffer(data).unwrap();
+    assert!(view.entities_str().unwrap().contains("\"classname\" \"worldspawn\""));
+    assert_eq!(view.entities().unwrap(), file.entities);
+    assert_eq!(view.textures().unwrap(), file.textures);
+
+    let vertices = view.vertex_records().unwrap();
+    assert_eq!(vertices.len(), file.vertices.vertices.len());
+    assert_eq!(vertices.get(3), Some(file.vertices.vertices[3]));
+    assert_eq!(vertices.get(vertices.len()), None);
+    assert!(vertices.iter().eq(file.vertices.vertices.iter().cloned()));
+
+    let light_maps = view.light_map_records().unwrap();
+    assert_eq!(light_maps.len(), file.light_maps.maps.len());
+    assert!(light_maps.get(0).unwrap() == file.light_maps.maps[0]);
+
+    let textures = view.texture_records().unwrap();
+    assert_eq!(textures.get(1).unwrap().unwrap(), file.textures.textures[1]);
+
+    let parsed = view.parse().unwrap();
+    assert_eq!(parsed.faces, file.faces);
+    assert_eq!(parsed.tree, file.tree);
+
+    // Lumps past the end of the buffer are caught up front
+    assert!(BSPFileRef::from_buffer(&data[..data.len() - 1]).is_err());
+}
+
+#[test]
+fn test_light_grid() {
+    let data = include_bytes!("./test.bsp").to_vec().into_boxed_slice();
+    let file = BSPFile::from_buffer(data).unwrap();
+
+    let size = LightGrid::size_from_entities(&file.entities).unwrap();
+    let grid = file.light_vols.grid(&file.models, size).unwrap();
+    assert_eq!(grid.bounds, Vector3::new(11, 10, 5));
+
+    let sample = grid.sample(Vector3::new(-8.0, -6.0, 15.0));
+    assert!((sample.dir.norm() - 1.0).abs() < 0.001);
+    assert!(sample.directional.iter().all(|c| *c > 0.0 && *c <= 255.0));
+}
+
+#[test]
+fn test_lightmap_atlas() {
+    let data = include_bytes!("./test.bsp").to_vec().into_boxed_slice();
+    let file = BSPFile::from_buffer(data).unwrap();
+
+    let packed = file.light_maps.pack_atlases(1024, 1024, 1).unwrap();
+    assert_eq!(packed.regions.len(), file.light_maps.maps.len());
+
+    let remapped = packed.remap_vertices(&file.faces, &file.vertices).unwrap();
+    for face in file.faces.faces.iter().filter(|f| f.lightmap_idx.is_some()) {
+        for (vertex, original) in remapped.vertices[face.vertices_idx.clone()]
+            .iter()
+            .zip(file.vertices.vertices[face.vertices_idx.clone()].iter())
+        {
+            assert_eq!(vertex.position, original.position);
+            assert!(vertex.tex.v.iter().all(|c| *c >= 0.0 && *c <= 1.0));
+        }
+    }
+}
+
+#[test]
+fn test_entity_models() {
+    let data = include_bytes!("./test.bsp").to_vec().into_boxed_slice();
+    let file = BSPFile::from_buffer(data).unwrap();
+
+    assert!(file.entities.worldspawn().is_some());
+    for entity in file.entities.entities.iter() {
+        if let Some(index) = entity.model_index(&file.models).unwrap() {
+            assert_eq!(entity.model(), Some(format!("*{}", index).as_str()));
+        }
+    }
+}
+
+#[test]
+fn test_error_location() {
+    let mut data = include_bytes!("./test.bsp").to_vec();
+    let faces = BSPFileRef::from_buffer(&data).unwrap().directory.dir_entries[13];
+
+    // point the third face at a texture that doesn't exist
+    let at = faces.offset as usize + 2 * 104;
+    data[at..at + 4].copy_from_slice(&1000i32.to_le_bytes());
+
+    match BSPFile::from_buffer(data.into_boxed_slice()) {
+        Err(e @ Error::BadReference { .. }) => {
+            assert_eq!(
+                e.location(),
+                Some(Location {
+                    lump: "Faces",
+                    record: Some(2),
+                    offset: Some(2 * 104),
+                })
+            );
+            assert_eq!(
+                e.to_string(),
+                "Faces references Texture that doesn't exist: texture is 1000 (Faces lump, record 2, byte 208)"
+            );
+        }
+        other => panic!("expected a bad reference, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_flat_tree() {
+    let data = include_bytes!("./test.bsp");
+    let file = BSPFileRef::from_buffer(data).unwrap();
+    let parsed = file.parse().unwrap();
+
+    let flat = FlatTree::from_lumps(
+        file.lump(3),
+        file.lump(4),
+        file.lump(5),
+        file.lump(6),
+        &parsed.faces,
+        &parsed.brushes,
+    )
+    .unwrap();
+
+    // indices are kept the same as the file
+    let lumps = flat.to_lumps();
+    assert!(lumps.nodes == file.lump(3));
+    let reparsed = FlatTree::from_lumps(
+        &lumps.nodes,
+        &lumps.leaves,
+        &lumps.leaf_faces,
+        &lumps.leaf_brushes,
+        &parsed.faces,
+        &parsed.brushes,
+    )
+    .unwrap();
+    assert_eq!(reparsed, flat);
+
+    assert_eq!(flat.to_tree().unwrap(), parsed.tree);
+    // unreachable leaves are dropped, so only the shape survives
+    assert_eq!(FlatTree::from_tree(&parsed.tree).to_tree().unwrap(), parsed.tree);
+
+    for point in [Vector3::new(0.0, 0.0, 0.0), Vector3::new(100.0, -50.0, 20.0)].iter() {
+        let leaf = flat.find_leaf(*point, &parsed.planes).unwrap();
+        assert_eq!(Some(&flat.leaves[leaf].leaf), parsed.tree.find_leaf(*point, &parsed.planes));
+    }
+}
+
+/// Counts how many bytes are read through it, and the most read at once.
+struct CountingReader<R> {
+    inner: R,
+    read: usize,
+    largest: usize,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.read += n;
+        self.largest = self.largest.max(n);
+        Ok(n)
+    }
+}
+
+impl<R: Seek> Seek for CountingReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+#[test]
+fn test_reader() {
+    let data = include_bytes!("./test.bsp");
+    let file = BSPFile::from_buffer(data.to_vec().into_boxed_slice()).unwrap();
+
+    let read = BSPFile::from_reader(Cursor::new(&data[..])).unwrap();
+    assert_eq!(read.entities, file.entities);
+    assert_eq!(read.faces, file.faces);
+    assert_eq!(read.tree, file.tree);
+    assert_eq!(read.models, file.models);
+
+    // Only the header & the lumps asked for are read
+    let mut reader = BSPReader::new(CountingReader {
+        inner: Cursor::new(&data[..]),
+        read: 0,
+        largest: 0,
+    })
+    .unwrap();
+    assert_eq!(reader.entities().unwrap(), file.entities);
+    assert_eq!(reader.textures().unwrap(), file.textures);
+
+    let lumps = (reader.directory.dir_entries[0].length + reader.directory.dir_entries[1].length) as usize;
+    // the header is read with room for the Quake Live entry
+    assert_eq!(reader.into_inner().read, 8 + 18 * 8 + lumps);
+
+    // Parsing everything reads each lump once, into a buffer of its own
+    let mut reader = BSPReader::new(CountingReader {
+        inner: Cursor::new(&data[..]),
+        read: 0,
+        largest: 0,
+    })
+    .unwrap();
+    let parsed = reader.parse().unwrap();
+    assert_eq!(parsed.faces, file.faces);
+    assert_eq!(parsed.tree, file.tree);
+    assert_eq!(parsed.light_maps, file.light_maps);
+    let entries = reader.directory.dir_entries;
+    let reader = reader.into_inner();
+    let lumps: usize = entries.iter().map(|e| e.length as usize).sum();
+    assert_eq!(reader.read, 8 + 18 * 8 + lumps);
+    assert_eq!(reader.largest, entries.iter().map(|e| e.length as usize).max().unwrap());
+
+    assert!(BSPReader::new(Cursor::new(&data[..data.len() - 1])).is_err());
+    assert!(BSPReader::new(Cursor::new(&data[..20])).is_err());
+}
+
+#[test]
+fn test_open() {
+    let path = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/test.bsp");
+    let file = BSPFile::from_buffer(include_bytes!("./test.bsp").to_vec().into_boxed_slice()).unwrap();
+
+    let opened = BSPFile::open(path).unwrap();
+    assert_eq!(opened.entities, file.entities);
+    assert_eq!(opened.tree, file.tree);
+
+    match BSPFile::open(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/missing.bsp")) {
+        Err(Error::Io { error }) => assert_eq!(error.kind(), io::ErrorKind::NotFound),
+        other => panic!("expected io error, got {:?}", other.map(|_| ())),
+    }
+}
+
+#[cfg(feature = "mmap")]
+#[test]
+fn test_mapped() {
+    let path = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/test.bsp");
+    let data = include_bytes!("./test.bsp");
+
+    // Nothing else writes to the test files.
+    let mapped = unsafe { stockton_bsp::MappedBSPFile::open(path) }.unwrap();
+    assert_eq!(mapped.bytes(), &data[..]);
+    assert_eq!(
+        mapped.file_ref().entities().unwrap(),
+        BSPFileRef::from_buffer(data).unwrap().entities().unwrap()
+    );
+}
+
+#[test]
+fn test_load_options() {
+    let data = include_bytes!("./test.bsp");
+    let file = BSPFile::from_buffer(data.to_vec().into_boxed_slice()).unwrap();
+
+    let options = LoadOptions::new().light_maps(false).light_vols(false).visdata(false).tree(false);
+    let partial = options.from_buffer(data).unwrap();
+    assert!(partial.light_maps.maps.is_empty());
+    assert!(partial.light_vols.vols.is_empty());
+    assert!(partial.visdata.vecs.is_empty());
+    assert!(partial.tree.root.leaf.is_some());
+    assert_eq!(partial.faces, file.faces);
+    assert_eq!(partial.models, file.models);
+    assert_eq!(partial.entities, file.entities);
+
+    // Partial loads can't be written back
+    assert!(partial.to_buffer().is_err());
+    let no_light_maps = LoadOptions::new().light_maps(false).from_buffer(data).unwrap();
+    match no_light_maps.to_buffer() {
+        Err(Error::BadReference { target: "LightMap", .. }) => {}
+        other => panic!("expected a missing lightmap, got {:?}", other.map(|_| ())),
+    }
+    let no_vis = LoadOptions::new().light_vols(false).visdata(false).from_buffer(data).unwrap();
+    assert!(BSPFile::from_buffer(no_vis.to_buffer().unwrap()).is_ok());
+
+    // Skipped lumps aren't read at all
+    let mut reader = CountingReader {
+        inner: Cursor::new(&data[..]),
+        read: 0,
+        largest: 0,
+    };
+    assert_eq!(options.from_reader(&mut reader).unwrap().faces, file.faces);
+    let skipped: u32 = [3, 4, 5, 6, 14, 15, 16]
+        .iter()
+        .map(|i| file.directory.dir_entries[*i].length)
+        .sum();
+    let all: u32 = file.directory.dir_entries.iter().map(|e| e.length).sum();
+    assert_eq!(reader.read, 8 + 18 * 8 + (all - skipped) as usize);
+
+    // Skipped lumps aren't checked either
+    let mut broken = data.to_vec();
+    let entry = 8 + 14 * 8 + 4;
+    let length = u32::from_le_bytes([broken[entry], broken[entry + 1], broken[entry + 2], broken[entry + 3]]);
+    broken[entry..entry + 4].copy_from_slice(&(length - 1).to_le_bytes());
+    assert!(BSPFile::from_buffer(broken.clone().into_boxed_slice()).is_err());
+    assert!(LoadOptions::new().light_maps(false).from_buffer(&broken).is_ok());
+}
+
+#[test]
+fn test_mesh() {
+    let file = BSPFile::from_buffer(include_bytes!("./test.bsp").to_vec().into_boxed_slice()).unwrap();
+    let mesh = Mesh::new(&file, &MeshOptions::default()).unwrap();
+
+    let n_meshverts: usize = file.faces.faces.iter().map(|f| f.meshverts_idx.len()).sum();
+    assert_eq!(mesh.indices.len(), n_meshverts);
+    assert_eq!(mesh.batches.iter().map(|b| b.faces.len()).sum::<usize>(), file.faces.faces.len());
+
+    let mut vertices = 0;
+    let mut indices = 0;
+    for (i, batch) in mesh.batches.iter().enumerate() {
+        // batches are sorted & contiguous
+        assert!(i == 0 || mesh.batches[i - 1].key < batch.key);
+        assert_eq!(batch.vertices.start, vertices);
+        assert_eq!(batch.indices.start, indices);
+        vertices = batch.vertices.end;
+        indices = batch.indices.end;
+
+        for face_idx in batch.faces.iter() {
+            assert_eq!(BatchKey::of(&file.faces.faces[*face_idx]), batch.key);
+        }
+        for index in mesh.indices[batch.indices.clone()].iter() {
+            assert!(batch.vertices.contains(&(*index as usize)));
+        }
+    }
+    assert_eq!(vertices, mesh.vertices.len());
+
+    // every triangle matches the file, with meshverts offset from the face's first vertex
+    let mut triangles = Vec::new();
+    for batch in mesh.batches.iter() {
+        for face in batch.faces.iter().map(|f| &file.faces.faces[*f]) {
+            for meshvert in file.meshverts.meshverts[face.meshverts_idx.clone()].iter() {
+                triangles.push(file.vertices.vertices[face.vertices_idx.start + meshvert.offset as usize]);
+            }
+        }
+    }
+    let built: Vec<_> = mesh.indices.iter().map(|i| mesh.vertices[*i as usize]).collect();
+    assert_eq!(built, triangles);
+
+    let visible = file.tree.visible_faces(Vector3::new(0.0, 0.0, 0.0), &file.planes, &file.visdata, None);
+    let partial = Mesh::from_faces(&file, visible.iter().map(|f| *f as usize), &MeshOptions::default()).unwrap();
+    assert_eq!(partial.batches.iter().map(|b| b.faces.len()).sum::<usize>(), visible.len());
+}
+
+#[test]
+fn test_mesh_bad_patch() {
+    let original = BSPFile::from_buffer(include_bytes!("./test.bsp").to_vec().into_boxed_slice()).unwrap();
+
+    // patch sizes come straight from the file, so a bad one has to be an error rather than a panic
+    for size in [Vector2::new(-1, 3), Vector2::new(3, -3), Vector2::new(i32::MAX, i32::MAX)].iter() {
+        let mut file = original.clone();
+        file.faces.faces[0].face_type = FaceType::Patch;
+        file.faces.faces[0].size = *size;
+        let file = BSPFile::from_buffer(file.to_buffer().unwrap()).unwrap();
+
+        assert!(Mesh::new(&file, &MeshOptions::default()).is_err());
+        assert!(ObjExport::new(&file, ObjOptions::default()).is_err());
+        assert!(GltfExport::new(&file, GltfOptions::default()).is_err());
+    }
+}
+
+#[test]
+fn test_obj_export() {
+    let file = BSPFile::from_buffer(include_bytes!("./test.bsp").to_vec().into_boxed_slice()).unwrap();
+    let options = ObjOptions {
+        skip: SurfaceFlags::empty(),
+        ..ObjOptions::default()
+    };
+    let export = ObjExport::new(&file, options).unwrap();
+
+    let mut obj = Vec::new();
+    export.write_obj(&mut obj, Some("test.mtl")).unwrap();
+    let obj = String::from_utf8(obj).unwrap();
+    let mut mtl = Vec::new();
+    export.write_mtl(&mut mtl).unwrap();
+    let mtl = String::from_utf8(mtl).unwrap();
+    let mut uvs = Vec::new();
+    export.write_lightmap_uvs(&mut uvs).unwrap();
+    let uvs = String::from_utf8(uvs).unwrap();
+
+    let count = |text: &str, prefix: &str| text.lines().filter(|l| l.starts_with(prefix)).count();
+    let n_vertices = count(&obj, "v ");
+    assert_eq!(count(&obj, "vt "), n_vertices);
+    assert_eq!(count(&obj, "vn "), n_vertices);
+    assert_eq!(count(&uvs, "vt "), n_vertices);
+    assert_eq!(count(&obj, "o "), file.models.models.len());
+
+    let n_meshverts: usize = file.faces.faces.iter().map(|f| f.meshverts_idx.len()).sum();
+    assert_eq!(count(&obj, "f ") * 3, n_meshverts);
+    for line in obj.lines().filter(|l| l.starts_with("f ")) {
+        for corner in line[2..].split(' ') {
+            let index: usize = corner.split('/').next().unwrap().parse().unwrap();
+            assert!(index >= 1 && index <= n_vertices);
+        }
+    }
+
+    // every material used is defined, once
+    let materials: Vec<&str> = mtl.lines().filter_map(|l| l.strip_prefix("newmtl ")).collect();
+    assert!(materials.contains(&"textures/base_floor/concrete"));
+    assert!(mtl.contains("map_Kd textures/base_floor/concrete.tga\n"));
+    for line in obj.lines().filter_map(|l| l.strip_prefix("usemtl ")) {
+        assert_eq!(materials.iter().filter(|m| **m == line).count(), 1);
+    }
+
+    // skipping faces by texture flags
+    let skip = ObjOptions::default().skip;
+    let export = ObjExport::new(&file, ObjOptions::default()).unwrap();
+    let mut obj = Vec::new();
+    export.write_obj(&mut obj, None).unwrap();
+    let kept: usize = file
+        .faces
+        .faces
+        .iter()
+        .filter(|f| !file.textures.textures[f.texture_idx].surface.intersects(skip))
+        .map(|f| f.meshverts_idx.len())
+        .sum();
+    assert!(kept < n_meshverts);
+    assert_eq!(count(&String::from_utf8(obj).unwrap(), "f ") * 3, kept);
+
+    let path = std::env::temp_dir().join(format!("stockton-bsp-test-{}.obj", std::process::id()));
+    ObjExport::new(&file, ObjOptions::default()).unwrap().save(&path).unwrap();
+    for ext in ["obj", "mtl", "lmuv"].iter() {
+        let path = path.with_extension(ext);
+        assert!(std::fs::metadata(&path).unwrap().len() > 0);
+        std::fs::remove_file(path).unwrap();
+    }
+}
+
+#[test]
+fn test_gltf_export() {
+    let file = BSPFile::from_buffer(include_bytes!("./test.bsp").to_vec().into_boxed_slice()).unwrap();
+    let export = GltfExport::new(&file, GltfOptions::default()).unwrap();
+
+    let mut glb = Vec::new();
+    export.write_glb(&mut glb).unwrap();
+    let u32_at = |at: usize| u32::from_le_bytes([glb[at], glb[at + 1], glb[at + 2], glb[at + 3]]) as usize;
+
+    // header, then the JSON & binary chunks, each 4 byte aligned
+    assert_eq!(&glb[0..4], b"glTF");
+    assert_eq!(u32_at(4), 2);
+    assert_eq!(u32_at(8), glb.len());
+
+    let json_len = u32_at(12);
+    assert_eq!(json_len % 4, 0);
+    assert_eq!(&glb[16..20], b"JSON");
+    let json = std::str::from_utf8(&glb[20..20 + json_len]).unwrap();
+    assert_eq!(json.trim_end(), export.json());
+
+    let bin_start = 20 + json_len;
+    let bin_len = u32_at(bin_start);
+    assert_eq!(&glb[bin_start + 4..bin_start + 8], b"BIN\0");
+    assert_eq!(bin_start + 8 + bin_len, glb.len());
+    assert_eq!(&glb[bin_start + 8..bin_start + 8 + export.bin().len()], export.bin());
+    assert!(json.contains(&format!("\"buffers\":[{{\"byteLength\":{}}}]", export.bin().len())));
+
+    // a node per model, and per entity with an origin
+    let with_origin = file.entities.entities.iter().filter(|e| e.get("origin").is_some()).count();
+    assert!(json.contains("{\"name\":\"world\",\"mesh\":0,\"extras\":{"));
+    assert!(json.contains("\"classname\":\"worldspawn\""));
+    assert_eq!(json.matches("\"translation\":").count(), with_origin);
+
+    // one primitive & material for each texture drawn, and each lightmap atlas embedded as a PNG
+    let skip = GltfOptions::default().skip;
+    let mut drawn: Vec<usize> = file
+        .faces
+        .faces
+        .iter()
+        .map(|f| f.texture_idx)
+        .filter(|t| !file.textures.textures[*t].surface.intersects(skip))
+        .collect();
+    drawn.sort();
+    drawn.dedup();
+    assert_eq!(json.matches("\"TEXCOORD_1\":").count(), drawn.len());
+    assert_eq!(json.matches("\"COLOR_0\":").count(), drawn.len());
+    assert_eq!(json.matches("\"pbrMetallicRoughness\"").count(), drawn.len());
+    assert!(json.contains("\"name\":\"textures/base_floor/concrete\""));
+
+    let n_atlases = (file.light_maps.maps.len() + 48) / 49;
+    assert_eq!(json.matches("\"mimeType\":\"image/png\"").count(), n_atlases);
+    assert_eq!(
+        export.bin().windows(8).filter(|w| *w == b"\x89PNG\r\n\x1a\n").count(),
+        n_atlases
+    );
+
+    let path = std::env::temp_dir().join(format!("stockton-bsp-test-{}.glb", std::process::id()));
+    export.save(&path).unwrap();
+    assert_eq!(std::fs::read(&path).unwrap(), glb);
+    std::fs::remove_file(&path).unwrap();
+
+    // entities with a bad origin or model are left out, and the rest still exported
+    let mut broken = file.clone();
+    let entity = |pairs: &[(&str, &str)]| {
+        Entity::from_pairs(pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect())
+    };
+    broken.entities.entities.push(entity(&[("classname", "bad_origin"), ("origin", "1 2 oops")]));
+    broken.entities.entities.push(entity(&[("classname", "bad_model"), ("model", "*99")]));
+    let json = GltfExport::new(&broken, GltfOptions::default()).unwrap().json().to_owned();
+    assert_eq!(json.matches("\"translation\":").count(), with_origin);
+    assert!(!json.contains("bad_origin") && !json.contains("bad_model"));
+}
+
+#[test]
+fn test_trace() {
+    let file = BSPFile::from_buffer(include_bytes!("./test.bsp").to_vec().into_boxed_slice()).unwrap();
+    let tracer = Tracer::from_file(&file);
+    let start = file.entities.entities.iter().find_map(|e| e.origin().unwrap()).unwrap();
+
+    // the floor
+    let trace = tracer.trace_ray(start, start - Vector3::new(0.0, 0.0, 1000.0), ContentsFlags::SOLID);
+    assert!(trace.fraction > 0.0 && trace.fraction < 1.0);
+    assert!(trace.end_pos.z > -256.0 && trace.end_pos.z < -255.0);
+    assert_eq!(trace.plane.unwrap().normal, Vector3::new(0.0, 0.0, 1.0));
+    assert_eq!(trace.texture_idx, Some(4));
+    assert!(!trace.start_solid);
+
+    // the sky
+    let trace = tracer.trace_ray(start, start + Vector3::new(0.0, 0.0, 1000.0), ContentsFlags::SOLID);
+    assert!(trace.surface.contains(SurfaceFlags::SKY));
+
+    // a player sized box stops with its bottom on the floor
+    let mins = Vector3::new(-15.0, -15.0, -24.0);
+    let maxs = Vector3::new(15.0, 15.0, 32.0);
+    let trace = tracer.trace_box(start, start - Vector3::new(0.0, 0.0, 1000.0), mins, maxs, ContentsFlags::SOLID);
+    assert!(trace.end_pos.z + mins.z > -256.0 && trace.end_pos.z + mins.z < -255.0);
+
+    // nothing in the map is water, and the walls are solid
+    let end = start + Vector3::new(1000.0, 0.0, 0.0);
+    assert_eq!(tracer.trace_ray(start, end, ContentsFlags::WATER).fraction, 1.0);
+    let in_wall = Vector3::new(258.0, start.y, start.z);
+    assert!(tracer.trace_ray(in_wall, start, ContentsFlags::SOLID).start_solid);
+}
@@ -15,6 +15,7 @@
 // You should have received a copy of the GNU General Public License
 // along with stockton-bsp.  If not, see <http://www.gnu.org/licenses/>.
 
+use stockton_bsp::lumps::light_maps::AtlasRegion;
 use stockton_bsp::lumps::LightMapsLump;
 use stockton_bsp::types::RGB;
 
@@ -39,3 +40,45 @@ fn test_lightmaps() {
         }
     }
 }
+
+#[test]
+fn test_pack_atlases() {
+    let map = include_bytes!("./test_lightmaps.bin");
+    let lump = LightMapsLump::from_lump(&[&map[..], &map[..], &map[..]].concat()).unwrap();
+
+    let packed = lump.pack_atlases(256, 256, 0).unwrap();
+    assert_eq!(packed.atlases.len(), 1);
+    assert_eq!(packed.atlases[0].data.len(), 256 * 256 * 4);
+    assert_eq!(packed.regions[2], AtlasRegion { atlas: 0, x: 0, y: 128 });
+
+    // each row of the test lightmap is the same shade as its index
+    let atlas = &packed.atlases[0];
+    let texel = |x: usize, y: usize| &atlas.data[(y * 256 + x) * 4..(y * 256 + x + 1) * 4];
+    assert_eq!(texel(130, 5), &[5, 5, 5, 255]);
+    assert_eq!(texel(3, 200), &[72, 72, 72, 255]);
+    assert_eq!(texel(200, 200), &[0, 0, 0, 0]);
+
+    assert_eq!(packed.remap(1, [0.5, 0.5]), [0.75, 0.25]);
+    assert_eq!(packed.atlas_of(Some(2)), Some(0));
+    assert_eq!(packed.atlas_of(None), None);
+}
+
+#[test]
+fn test_pack_atlases_padding() {
+    let map = include_bytes!("./test_lightmaps.bin");
+    let lump = LightMapsLump::from_lump(&[&map[..], &map[..], &map[..]].concat()).unwrap();
+
+    // only one padded lightmap fits in each atlas
+    let packed = lump.pack_atlases(256, 256, 2).unwrap();
+    assert_eq!(packed.atlases.len(), 3);
+    assert_eq!(packed.regions[2], AtlasRegion { atlas: 2, x: 2, y: 2 });
+
+    // padding copies the edges
+    let atlas = &packed.atlases[1];
+    let texel = |x: usize, y: usize| &atlas.data[(y * 256 + x) * 4..(y * 256 + x + 1) * 4];
+    assert_eq!(texel(0, 0), &[0, 0, 0, 255]);
+    assert_eq!(texel(131, 131), &[127, 127, 127, 255]);
+    assert_eq!(texel(50, 1), &[0, 0, 0, 255]);
+
+    assert!(lump.pack_atlases(128, 128, 1).is_err());
+}
@@ -0,0 +1,98 @@
+// Copyright (C) 2019 Oscar Shrimpton
+//
+// This file is part of stockton-bsp.
+//
+// stockton-bsp is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// stockton-bsp is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with stockton-bsp.  If not, see <http://www.gnu.org/licenses/>.
+use na::Vector3;
+use stockton_bsp::lumps::light_vols::LightGrid;
+use stockton_bsp::lumps::models::Model;
+use stockton_bsp::lumps::{EntitiesLump, LightVolsLump, ModelsLump};
+
+/// A world model covering a 2x2x2 light grid with 64 unit cells.
+fn world() -> ModelsLump {
+    ModelsLump {
+        models: Box::new([Model {
+            mins: Vector3::new(-10.0, -10.0, -10.0),
+            maxs: Vector3::new(70.0, 70.0, 70.0),
+            faces_idx: 0..0,
+            brushes_idx: 0..0,
+        }]),
+    }
+}
+
+#[test]
+fn test_light_grid() {
+    // x = 0 is dark with the light straight up, x = 1 is bright with the light along +x
+    let mut data = Vec::new();
+    for n in 0..8 {
+        if n % 2 == 0 {
+            data.extend_from_slice(&[10, 10, 10, 20, 20, 20, 0, 0]);
+        } else {
+            data.extend_from_slice(&[110, 110, 110, 220, 220, 220, 64, 0]);
+        }
+    }
+
+    let vols = LightVolsLump::from_lump(&data).unwrap();
+    let grid = vols.grid(&world(), Vector3::new(64.0, 64.0, 64.0)).unwrap();
+
+    assert_eq!(grid.origin, Vector3::new(0.0, 0.0, 0.0));
+    assert_eq!(grid.bounds, Vector3::new(2, 2, 2));
+    assert_eq!(grid.get(1, 0, 0).unwrap().dir, [64, 0]);
+    assert!(grid.get(2, 0, 0).is_none());
+
+    let corner = grid.sample(Vector3::new(0.0, 0.0, 0.0));
+    assert_eq!(corner.ambient, Vector3::new(10.0, 10.0, 10.0));
+    assert!((corner.dir - Vector3::z()).norm() < 0.001);
+
+    let middle = grid.sample(Vector3::new(32.0, 32.0, 32.0));
+    assert_eq!(middle.ambient, Vector3::new(60.0, 60.0, 60.0));
+    assert_eq!(middle.directional, Vector3::new(120.0, 120.0, 120.0));
+    assert!((middle.dir - Vector3::new(1.0, 0.0, 1.0).normalize()).norm() < 0.001);
+
+    // outside the grid is clamped to the edge
+    let outside = grid.sample(Vector3::new(500.0, -500.0, 0.0));
+    assert_eq!(outside.ambient, Vector3::new(110.0, 110.0, 110.0));
+}
+
+#[test]
+fn test_light_grid_skips_solid() {
+    // only the first volume has any light
+    let mut data = vec![0; 8 * 8];
+    data[0..8].copy_from_slice(&[100, 50, 25, 0, 0, 0, 0, 0]);
+
+    let vols = LightVolsLump::from_lump(&data).unwrap();
+    let grid = vols.grid(&world(), Vector3::new(64.0, 64.0, 64.0)).unwrap();
+
+    let sample = grid.sample(Vector3::new(32.0, 32.0, 32.0));
+    assert_eq!(sample.ambient, Vector3::new(100.0, 50.0, 25.0));
+}
+
+#[test]
+fn test_light_grid_mismatch() {
+    let vols = LightVolsLump::from_lump(&[0; 8 * 7]).unwrap();
+
+    assert!(vols.grid(&world(), Vector3::new(64.0, 64.0, 64.0)).is_err());
+}
+
+#[test]
+fn test_grid_size() {
+    let default = EntitiesLump::from_lump(b"{\n\"classname\" \"worldspawn\"\n}\n").unwrap();
+    assert_eq!(LightGrid::size_from_entities(&default).unwrap(), Vector3::new(64.0, 64.0, 128.0));
+
+    let custom = EntitiesLump::from_lump(b"{\n\"classname\" \"worldspawn\"\n\"gridsize\" \"32 32 64\"\n}\n").unwrap();
+    assert_eq!(LightGrid::size_from_entities(&custom).unwrap(), Vector3::new(32.0, 32.0, 64.0));
+
+    let malformed = EntitiesLump::from_lump(b"{\n\"classname\" \"worldspawn\"\n\"gridsize\" \"32 32\"\n}\n").unwrap();
+    assert!(LightGrid::size_from_entities(&malformed).is_err());
+}
@@ -22,9 +22,12 @@ mod advertisements;
 mod brushes;
 mod effects;
 mod entities;
+mod fuzz;
 mod lightmaps;
+mod lightvols;
 mod models;
 mod tree;
 mod vertices;
 mod visdata;
-mod planes;
\ No newline at end of file
+mod planes;
+
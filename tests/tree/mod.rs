@@ -18,10 +18,10 @@
 use na::{Vector2, Vector3};
 use stockton_bsp::lumps::brushes::{Brush, BrushesLump};
 use stockton_bsp::lumps::faces::{Face, FaceLump, FaceType};
-use stockton_bsp::lumps::BSPTree;
+use stockton_bsp::lumps::planes::Plane;
+use stockton_bsp::lumps::{BSPTree, PlanesLump};
 
-#[test]
-fn test_tree() {
+fn load_tree() -> BSPTree {
     let buf = include_bytes!("./test_tree.bin");
 
     let faces = FaceLump {
@@ -55,8 +55,12 @@ fn test_tree() {
     let leaf_faces = &buf[0x180..0x184];
     let brush_faces = &buf[0x184..0x188];
 
-    let tree =
-        BSPTree::from_lumps(nodes, leaves, leaf_faces, brush_faces, &faces, &brushes).unwrap();
+    BSPTree::from_lumps(nodes, leaves, leaf_faces, brush_faces, &faces, &brushes).unwrap()
+}
+
+#[test]
+fn test_tree() {
+    let tree = load_tree();
 
     //            0
     //     1            2
@@ -83,3 +87,48 @@ fn test_tree() {
     assert!(children_2[0].leaf.is_some()); // l1
     assert!(children_2[1].leaf.is_some()); // l2
 }
+
+#[test]
+fn test_find_leaf() {
+    let tree = load_tree();
+
+    // node 0 splits on x, nodes 1 & 2 on y, node 3 on z
+    let axis = |x, y, z| Plane {
+        normal: Vector3::new(x, y, z),
+        dist: 0.0,
+    };
+    let planes = PlanesLump {
+        planes: vec![
+            axis(1.0, 0.0, 0.0),
+            axis(0.0, 1.0, 0.0),
+            axis(0.0, 1.0, 0.0),
+            axis(0.0, 0.0, 1.0),
+        ]
+        .into_boxed_slice(),
+    };
+
+    let leaf = tree.find_leaf(Vector3::new(1.0, -1.0, 0.0), &planes).unwrap();
+    assert_eq!(leaf.cluster(), Some(3));
+    assert_eq!(leaf.area, 3);
+
+    let visited: Vec<u32> = tree
+        .walk(Vector3::new(1.0, -1.0, 0.0), &planes)
+        .map(|node| node.plane_idx)
+        .collect();
+    assert_eq!(visited, vec![0, 1, 3, 0]);
+
+    let leaf = tree.find_leaf(Vector3::new(1.0, 1.0, -1.0), &planes).unwrap();
+    assert_eq!(leaf.cluster(), Some(0));
+
+    let leaf = tree.find_leaf(Vector3::new(-1.0, 1.0, 0.0), &planes).unwrap();
+    assert_eq!(leaf.cluster(), Some(1));
+
+    let leaf = tree.find_leaf(Vector3::new(-1.0, -1.0, 0.0), &planes).unwrap();
+    assert_eq!(leaf.cluster(), Some(2));
+
+    // Nodes referencing missing planes stop the walk early
+    let empty = PlanesLump {
+        planes: vec![].into_boxed_slice(),
+    };
+    assert!(tree.find_leaf(Vector3::new(1.0, 1.0, 1.0), &empty).is_none());
+}
@@ -15,7 +15,9 @@
 // You should have received a copy of the GNU General Public License
 // along with stockton-bsp.  If not, see <http://www.gnu.org/licenses/>.
 
-use stockton_bsp::lumps::VisDataLump;
+use stockton_bsp::lumps::visdata::decompress_vis;
+use stockton_bsp::lumps::{ClusterVisLump, VisDataLump};
+use stockton_bsp::types::{Error, Location};
 
 #[test]
 fn test_visdata() {
@@ -37,3 +39,94 @@ fn test_visdata() {
         assert_eq!(lump.vecs[2][n], n % 2 == 0);
     }
 }
+
+#[test]
+fn test_visdata_bad_size() {
+    let data = include_bytes!("./test_visdata.bin");
+
+    // a byte short of 3 vectors of 1 byte
+    match VisDataLump::from_lump(&data[..data.len() - 1]) {
+        Err(e @ Error::BadValue { field: "n_vecs", .. }) => assert_eq!(e.location(), Some(Location::lump("VisData"))),
+        other => panic!("expected a bad n_vecs, got {:?}", other),
+    }
+
+    // the right length, but vectors too short for the number of clusters
+    let mut too_short = data[..8].to_vec();
+    too_short[0] = 9;
+    too_short.extend(&[0; 9]);
+    match VisDataLump::from_lump(&too_short) {
+        Err(Error::BadValue { field: "size_vecs", .. }) => {}
+        other => panic!("expected a bad size_vecs, got {:?}", other),
+    }
+
+    match VisDataLump::from_lump(&data[..4]) {
+        Err(e @ Error::OutOfBounds { offset: 0, length: 8, .. }) => {
+            assert_eq!(e.location(), Some(Location::lump("VisData")))
+        }
+        other => panic!("expected the header to be out of bounds, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_cluster_vis_bad_size() {
+    match ClusterVisLump::from_lump(&[2, 0, 0, 0, 0, 0, 0, 0], 2) {
+        Err(Error::OutOfBounds { lump: "Visibility", offset: 4, length: 16 }) => {}
+        other => panic!("expected the offsets to be out of bounds, got {:?}", other),
+    }
+
+    match ClusterVisLump::from_lump(&(-1i32).to_le_bytes(), 2) {
+        Err(e @ Error::BadValue { field: "numclusters", .. }) => {
+            assert_eq!(e.location(), Some(Location::lump("Visibility")))
+        }
+        other => panic!("expected a bad numclusters, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_cluster_vis_too_many_clusters() {
+    // 100,000 clusters all sharing one run of zeros would decompress to 2.5GB
+    let n_clusters: i32 = 100_000;
+    let mut data = n_clusters.to_le_bytes().to_vec();
+    let run = 4 + n_clusters * 8;
+    for _ in 0..n_clusters * 2 {
+        data.extend_from_slice(&run.to_le_bytes());
+    }
+    data.extend_from_slice(&[0, 255]);
+
+    match ClusterVisLump::from_lump(&data, 64) {
+        Err(Error::BadValue { field: "numclusters", value, .. }) => assert_eq!(value, "100000"),
+        other => panic!("expected too many clusters, got {:?}", other.map(|_| ())),
+    }
+
+    // Vectors that don't decompress to enough bytes don't allocate the rest
+    assert!(decompress_vis(&[0, 1], usize::MAX - 7).is_err());
+}
+
+#[test]
+fn test_visible_from() {
+    let data = include_bytes!("./test_visdata.bin");
+
+    let lump = VisDataLump::from_lump(data).unwrap();
+
+    assert!((0..8).all(|n| lump.visible_from(0, n)));
+    assert!((0..8).all(|n| !lump.visible_from(1, n)));
+
+    // clusters start at the least significant bit
+    for n in 0..8 {
+        assert_eq!(lump.visible_from(2, n), n % 2 == 1);
+    }
+}
+
+#[test]
+fn test_decompress_vis() {
+    // one literal byte, then a run of two zero bytes, then another literal
+    let vec = decompress_vis(&[0b0000_0101, 0x00, 0x02, 0x80], 32).unwrap();
+
+    assert_eq!(vec.len(), 32);
+    for n in 0..32 {
+        assert_eq!(vec[n], n == 0 || n == 2 || n == 31);
+    }
+
+    assert!(decompress_vis(&[0x00], 16).is_err());
+    assert!(decompress_vis(&[0xff], 16).is_err());
+}